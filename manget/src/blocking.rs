@@ -0,0 +1,76 @@
+//! Blocking versions of the main functions, for code that is not async. Each call runs the async
+//! version on its own current-thread runtime, so they must not be called from an async context.
+
+use std::{fmt::Display, path::PathBuf};
+
+use reqwest::IntoUrl;
+
+use crate::manga::{self, Chapter, ChapterError};
+
+fn block_on<F: std::future::Future>(future: F) -> Result<F::Output, ChapterError> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    Ok(runtime.block_on(future))
+}
+
+/// Blocking version of [manga::get_chapter]
+pub fn get_chapter(url: impl IntoUrl + Display + Clone) -> Result<Box<dyn Chapter>, ChapterError> {
+    block_on(manga::get_chapter(url))?
+}
+
+/// Blocking version of [manga::download_chapter]
+pub fn download_chapter<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    path: Option<P>,
+) -> Result<PathBuf, ChapterError> {
+    block_on(manga::download_chapter(chapter, path))?
+}
+
+/// Blocking version of [manga::download_chapter_as_cbz]
+pub fn download_chapter_as_cbz<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    zip_path: Option<P>,
+) -> Result<PathBuf, ChapterError> {
+    block_on(manga::download_chapter_as_cbz(chapter, zip_path))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blocking_get_chapter() {
+        let page = tempfile::NamedTempFile::with_suffix(".png").unwrap();
+        std::fs::write(page.path(), "page").unwrap();
+        let page_url = reqwest::Url::from_file_path(page.path())
+            .unwrap()
+            .to_string();
+        manga::register_site(
+            "blocking-fixture.test",
+            |url| url.domain() == Some("blocking-fixture.test"),
+            move |_| {
+                let page_url = page_url.clone();
+                Box::pin(async move {
+                    Ok(Box::new(manga::ManualChapter::new(
+                        "Fixture",
+                        "chap 1",
+                        [page_url],
+                        None,
+                    )) as Box<dyn Chapter>)
+                })
+            },
+        );
+
+        let chapter = get_chapter("https://blocking-fixture.test/chapter/1").unwrap();
+        assert_eq!(chapter.chapter(), "chap 1");
+        let dir = tempfile::tempdir().unwrap();
+        let path =
+            download_chapter_as_cbz(chapter.as_ref(), Some(dir.path().join("chap.cbz"))).unwrap();
+        assert!(path.exists());
+        assert!(matches!(
+            get_chapter("https://unsupported-site.test/chapter/1"),
+            Err(ChapterError::SiteNotSupported(_))
+        ));
+    }
+}
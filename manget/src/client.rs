@@ -0,0 +1,342 @@
+// without any site, only page downloads use this module
+#![cfg_attr(
+    not(any(
+        feature = "site-asura",
+        feature = "site-batoto",
+        feature = "site-blogtruyen",
+        feature = "site-comick",
+        feature = "site-mangadex",
+        feature = "site-mangakakalot",
+        feature = "site-mangapark",
+        feature = "site-nettruyen",
+        feature = "site-toptruyen",
+        feature = "site-truyentranhtuan",
+        feature = "site-webtoon",
+        feature = "site-weebcentral"
+    )),
+    allow(dead_code)
+)]
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock, RwLock},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use reqwest::{IntoUrl, Url};
+use tracing::warn;
+
+/// Environment variable read for the initial User-Agent
+pub const USER_AGENT_ENV: &str = "MANGET_USER_AGENT";
+const DEFAULT_USER_AGENT: &str = "Manget";
+
+const DEFAULT_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variables of the proxies by scheme, the lowercase names are also read
+const PROXY_ENVS: [&str; 3] = ["HTTPS_PROXY", "HTTP_PROXY", "ALL_PROXY"];
+/// Environment variable of the comma separated hosts, domains and IP ranges reached directly
+const NO_PROXY_ENV: &str = "NO_PROXY";
+
+/// Accept-Language of the Vietnamese sites by default
+#[cfg(any(
+    feature = "site-blogtruyen",
+    feature = "site-nettruyen",
+    feature = "site-toptruyen",
+    feature = "site-truyentranhtuan"
+))]
+const DEFAULT_VIETNAMESE_ACCEPT_LANGUAGE: &str = "vi-VN,vi;q=0.9";
+
+static USER_AGENT: OnceLock<RwLock<String>> = OnceLock::new();
+static VIETNAMESE_ACCEPT_LANGUAGE: RwLock<Option<String>> = RwLock::new(None);
+static FETCH_TIMEOUT: RwLock<Duration> = RwLock::new(DEFAULT_FETCH_TIMEOUT);
+static REQUEST_DELAY: RwLock<Duration> = RwLock::new(Duration::ZERO);
+/// Time of the latest scraper request (or the reserved slot of the next one) by host
+static LAST_REQUESTS: OnceLock<Mutex<HashMap<String, Instant>>> = OnceLock::new();
+/// Cookie header of the scraper requests by domain
+static COOKIES: OnceLock<RwLock<HashMap<String, String>>> = OnceLock::new();
+
+fn user_agent_lock() -> &'static RwLock<String> {
+    USER_AGENT.get_or_init(|| {
+        RwLock::new(
+            std::env::var(USER_AGENT_ENV)
+                .ok()
+                .filter(|ua| !ua.trim().is_empty())
+                .unwrap_or_else(|| DEFAULT_USER_AGENT.to_string()),
+        )
+    })
+}
+
+/// Set the User-Agent sent by all scrapers and page downloads, ex: to impersonate a browser
+/// on sites blocking the default one. It is initialized from `MANGET_USER_AGENT`, or "Manget".
+pub fn set_user_agent(user_agent: impl ToString) {
+    *user_agent_lock().write().unwrap() = user_agent.to_string();
+}
+
+/// Get the User-Agent sent by all scrapers and page downloads
+pub fn user_agent() -> String {
+    user_agent_lock().read().unwrap().clone()
+}
+
+/// Set the Accept-Language sent to the Vietnamese sites (nettruyen, truyenqq, toptruyen,
+/// blogtruyen, truyentuan), whose markup may change with the locale. "vi-VN,vi;q=0.9" by
+/// default, an empty value sends none.
+pub fn set_vietnamese_accept_language(accept_language: impl ToString) {
+    *VIETNAMESE_ACCEPT_LANGUAGE.write().unwrap() = Some(accept_language.to_string());
+}
+
+#[cfg(any(
+    feature = "site-blogtruyen",
+    feature = "site-nettruyen",
+    feature = "site-toptruyen",
+    feature = "site-truyentranhtuan"
+))]
+fn vietnamese_accept_language() -> Option<String> {
+    let accept_language = VIETNAMESE_ACCEPT_LANGUAGE.read().unwrap().clone();
+    Some(accept_language.unwrap_or_else(|| DEFAULT_VIETNAMESE_ACCEPT_LANGUAGE.to_string()))
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Set the timeout of the requests made by scrapers to get a chapter page or api response,
+/// 30 seconds by default
+pub fn set_fetch_timeout(timeout: Duration) {
+    *FETCH_TIMEOUT.write().unwrap() = timeout;
+}
+
+pub(crate) fn fetch_timeout() -> Duration {
+    *FETCH_TIMEOUT.read().unwrap()
+}
+
+/// Set the minimum delay between two scraper requests to the same host, ex: to avoid tripping
+/// anti-bot protections when resolving many chapters. Page downloads are not affected, use
+/// [`crate::manga::ChapterDownloadOptions::set_max_bytes_per_sec`] for them. 0 by default.
+pub fn set_request_delay(delay: Duration) {
+    *REQUEST_DELAY.write().unwrap() = delay;
+}
+
+pub(crate) fn request_delay() -> Duration {
+    *REQUEST_DELAY.read().unwrap()
+}
+
+/// Wait until `delay` has passed since the previous request to the host of `url`
+async fn wait_request_slot(url: &Url, delay: Duration) {
+    let Some(host) = url.host_str().filter(|_| !delay.is_zero()) else {
+        return;
+    };
+    let slot = {
+        let mut last_requests = LAST_REQUESTS.get_or_init(Default::default).lock().unwrap();
+        let now = Instant::now();
+        let slot = match last_requests.get(host) {
+            Some(&last) => now.max(last + delay),
+            None => now,
+        };
+        // reserve the slot so concurrent requests queue up behind it
+        last_requests.insert(host.to_string(), slot);
+        slot
+    };
+    tokio::time::sleep_until(slot).await;
+}
+
+/// Send `cookie`, a raw Cookie header (ex: copied from the browser), with the scraper requests to
+/// `domain` and its subdomains, ex: to get members-only chapters. `None` removes it.
+pub fn set_cookie(domain: &str, cookie: Option<String>) {
+    let mut cookies = COOKIES.get_or_init(Default::default).write().unwrap();
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    match cookie {
+        Some(cookie) => cookies.insert(domain, cookie),
+        None => cookies.remove(&domain),
+    };
+}
+
+/// The cookie set for the host of `url` or one of its parent domains
+pub(crate) fn cookie_for(url: &Url) -> Option<String> {
+    let host = url.host_str()?.to_lowercase();
+    let cookies = COOKIES.get()?.read().unwrap();
+    cookies
+        .iter()
+        .find(|(domain, _)| host == **domain || host.ends_with(&format!(".{domain}")))
+        .map(|(_, cookie)| cookie.clone())
+}
+
+/// A client builder with the proxies of `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY`, the hosts
+/// of `NO_PROXY` are reached directly. Unlike the default client which reads them once by
+/// process, the variables are read each time.
+pub(crate) fn client_builder() -> reqwest::ClientBuilder {
+    client_builder_with_env(|name| std::env::var(name).ok())
+}
+
+fn client_builder_with_env(var: impl Fn(&str) -> Option<String>) -> reqwest::ClientBuilder {
+    env_proxies(var)
+        .into_iter()
+        .fold(reqwest::Client::builder().no_proxy(), |builder, proxy| {
+            builder.proxy(proxy)
+        })
+}
+
+/// The proxies of the variables given by `var`, invalid ones are skipped with a warning
+fn env_proxies(var: impl Fn(&str) -> Option<String>) -> Vec<reqwest::Proxy> {
+    let var = |name: &str| {
+        var(name)
+            .or_else(|| var(&name.to_lowercase()))
+            .filter(|value| !value.trim().is_empty())
+    };
+    let no_proxy = var(NO_PROXY_ENV).and_then(|hosts| reqwest::NoProxy::from_string(&hosts));
+    PROXY_ENVS
+        .into_iter()
+        .filter_map(|name| {
+            let value = var(name)?;
+            let proxy = match name {
+                "HTTPS_PROXY" => reqwest::Proxy::https(&value),
+                "HTTP_PROXY" => reqwest::Proxy::http(&value),
+                _ => reqwest::Proxy::all(&value),
+            };
+            match proxy {
+                Ok(proxy) => Some(proxy.no_proxy(no_proxy.clone())),
+                Err(e) => {
+                    warn!("Ignore invalid proxy {name}={value}: {e}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Get the text of a page or api response with the configured User-Agent, cookie, timeout and
+/// delay between requests to the same host. Failed requests are not retried here, transient
+/// failures are retried once for the whole chapter resolution by the `SiteDispatcher`.
+pub(crate) async fn fetch_text(url: impl IntoUrl) -> reqwest::Result<String> {
+    let url = url.into_url()?;
+    let cookie = cookie_for(&url);
+    fetch_text_with(url, None, cookie, fetch_timeout(), request_delay()).await
+}
+
+/// Same as `fetch_text` with the Accept-Language of the Vietnamese sites, so their markup
+/// stays the one the scrapers parse
+#[cfg(any(
+    feature = "site-blogtruyen",
+    feature = "site-nettruyen",
+    feature = "site-toptruyen",
+    feature = "site-truyentranhtuan"
+))]
+pub(crate) async fn fetch_vietnamese_text(url: impl IntoUrl) -> reqwest::Result<String> {
+    let url = url.into_url()?;
+    let cookie = cookie_for(&url);
+    fetch_text_with(
+        url,
+        vietnamese_accept_language(),
+        cookie,
+        fetch_timeout(),
+        request_delay(),
+    )
+    .await
+}
+
+/// Same as `fetch_text` with an optional Accept-Language and Cookie header, the given `timeout`
+/// and `delay` between requests to the same host
+pub(crate) async fn fetch_text_with(
+    url: Url,
+    accept_language: Option<String>,
+    cookie: Option<String>,
+    timeout: Duration,
+    delay: Duration,
+) -> reqwest::Result<String> {
+    wait_request_slot(&url, delay).await;
+    let mut request = client_builder()
+        .build()?
+        .get(url)
+        .header("Accept", "*/*")
+        .header("User-Agent", user_agent())
+        .timeout(timeout);
+    if let Some(cookie) = cookie {
+        request = request.header("Cookie", cookie);
+    }
+    if let Some(accept_language) = accept_language {
+        request = request.header("Accept-Language", accept_language);
+    }
+    request.send().await?.error_for_status()?.text().await
+}
+
+/// Status of a HEAD request to `url`, with the User-Agent, cookie, timeout and delay of
+/// `fetch_text` but without retries, ex: to check that a page exists before getting it
+pub(crate) async fn head_status(url: impl IntoUrl) -> reqwest::Result<reqwest::StatusCode> {
+    let url = url.into_url()?;
+    wait_request_slot(&url, request_delay()).await;
+    let mut request = client_builder()
+        .build()?
+        .head(url.clone())
+        .header("User-Agent", user_agent())
+        .timeout(fetch_timeout());
+    if let Some(cookie) = cookie_for(&url) {
+        request = request.header("Cookie", cookie);
+    }
+    Ok(request.send().await?.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_request_delay() {
+        let server = MockServer::start().await;
+        Mock::given(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("chapter"))
+            .mount(&server)
+            .await;
+        // use the "localhost" name of the mock server so the slots of other tests are not shared
+        let uri = server.uri().replace("127.0.0.1", "localhost");
+        let delay = Duration::from_millis(300);
+        let url = Url::parse(&format!("{uri}/chapter")).unwrap();
+        let fetch = || fetch_text_with(url.clone(), None, None, fetch_timeout(), delay);
+        let start = std::time::Instant::now();
+        fetch().await.unwrap();
+        fetch().await.unwrap();
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[tokio::test]
+    async fn test_env_proxies() {
+        use std::io::Read;
+
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_url = format!("http://{}", proxy.local_addr().unwrap());
+        let env = |name: &str, no_proxy: &str| {
+            let (name, proxy_url, no_proxy) =
+                (name.to_string(), proxy_url.clone(), no_proxy.to_string());
+            move |var: &str| match var {
+                _ if var == name => Some(proxy_url.clone()),
+                "no_proxy" => Some(no_proxy.clone()),
+                _ => None,
+            }
+        };
+
+        let client = client_builder_with_env(env("HTTPS_PROXY", ""))
+            .build()
+            .unwrap();
+        let request = tokio::spawn(client.get("https://manga.invalid/chapter").send());
+        let connect = tokio::task::spawn_blocking(move || {
+            let (mut stream, _) = proxy.accept().unwrap();
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        })
+        .await
+        .unwrap();
+        assert!(connect.starts_with("CONNECT manga.invalid:443"));
+        assert!(request.await.unwrap().is_err());
+
+        // the hosts of NO_PROXY are reached directly
+        let server = MockServer::start().await;
+        Mock::given(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("chapter"))
+            .mount(&server)
+            .await;
+        let client = client_builder_with_env(env("HTTP_PROXY", "127.0.0.1"))
+            .build()
+            .unwrap();
+        let response = client.get(format!("{}/chapter", server.uri())).send().await;
+        assert_eq!(response.unwrap().text().await.unwrap(), "chapter");
+    }
+}
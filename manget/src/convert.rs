@@ -0,0 +1,145 @@
+use std::{
+    fs,
+    io::{self, BufReader, Cursor},
+    path::{Path, PathBuf},
+};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPDecoder},
+    imageops::FilterType,
+    DynamicImage, ImageFormat, ImageReader,
+};
+use tracing::info;
+
+/// Transcode a webp page to PNG if it has transparency, to JPEG otherwise. Returns the new path.
+/// Files that are not webp and animated webp are left untouched.
+pub(crate) fn convert_webp(path: &Path) -> io::Result<PathBuf> {
+    if !path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("webp"))
+    {
+        return Ok(path.to_path_buf());
+    }
+    let decoder =
+        WebPDecoder::new(BufReader::new(fs::File::open(path)?)).map_err(io::Error::other)?;
+    if decoder.has_animation() {
+        info!("Keeping animated webp {}", path.display());
+        return Ok(path.to_path_buf());
+    }
+    let image = DynamicImage::from_decoder(decoder).map_err(io::Error::other)?;
+    let (new_path, image, format) = if image.color().has_alpha() {
+        (path.with_extension("png"), image, ImageFormat::Png)
+    } else {
+        (
+            path.with_extension("jpg"),
+            DynamicImage::ImageRgb8(image.into_rgb8()),
+            ImageFormat::Jpeg,
+        )
+    };
+    image
+        .save_with_format(&new_path, format)
+        .map_err(io::Error::other)?;
+    fs::remove_file(path)?;
+    Ok(new_path)
+}
+
+/// Re-encode a JPEG page at `quality` (90 if not set) and downscale pages wider than `max_width`.
+/// Images with transparency and files that cannot be decoded are left untouched. A re-encoded
+/// page that is not smaller than the original is discarded unless it was downscaled.
+pub(crate) fn recompress(
+    path: &Path,
+    quality: Option<u8>,
+    max_width: Option<u32>,
+) -> io::Result<()> {
+    let Ok(reader) = ImageReader::open(path)?.with_guessed_format() else {
+        return Ok(());
+    };
+    let Some(format) = reader.format() else {
+        return Ok(());
+    };
+    let Ok(mut image) = reader.decode() else {
+        return Ok(());
+    };
+    if image.color().has_alpha() {
+        return Ok(());
+    }
+    let resized = match max_width {
+        Some(max_width) if image.width() > max_width => {
+            let height = (image.height() as u64 * max_width as u64 / image.width() as u64) as u32;
+            image = image.resize_exact(max_width, height.max(1), FilterType::Lanczos3);
+            true
+        }
+        _ => false,
+    };
+    if !resized && (format != ImageFormat::Jpeg || quality.is_none()) {
+        return Ok(());
+    }
+
+    let mut data = Vec::new();
+    if format == ImageFormat::Jpeg {
+        let encoder = JpegEncoder::new_with_quality(&mut data, quality.unwrap_or(90));
+        DynamicImage::ImageRgb8(image.into_rgb8())
+            .write_with_encoder(encoder)
+            .map_err(io::Error::other)?;
+    } else {
+        image
+            .write_to(&mut Cursor::new(&mut data), format)
+            .map_err(io::Error::other)?;
+    }
+    if resized || (data.len() as u64) < fs::metadata(path)?.len() {
+        fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_recompress() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page_001.jpg");
+        let image = image::RgbImage::from_fn(1600, 1200, |x, y| {
+            image::Rgb([
+                (x * 7 % 256) as u8,
+                (y * 13 % 256) as u8,
+                ((x ^ y) % 256) as u8,
+            ])
+        });
+        let mut data = Vec::new();
+        image
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut data, 100))
+            .unwrap();
+        fs::write(&path, &data).unwrap();
+
+        recompress(&path, Some(60), Some(800)).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < data.len() as u64);
+        assert_eq!(image::image_dimensions(&path).unwrap(), (800, 600));
+
+        let png = dir.path().join("page_002.png");
+        RgbaImage::from_pixel(1600, 4, Rgba([0, 0, 0, 0]))
+            .save(&png)
+            .unwrap();
+        recompress(&png, Some(60), Some(800)).unwrap();
+        assert_eq!(image::image_dimensions(&png).unwrap(), (1600, 4));
+    }
+
+    #[test]
+    fn test_convert_webp_keeps_transparency() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("page_001.webp");
+        RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]))
+            .save_with_format(&path, ImageFormat::WebP)
+            .unwrap();
+        assert_eq!(
+            convert_webp(&path).unwrap(),
+            dir.path().join("page_001.png")
+        );
+        assert!(!path.exists());
+
+        let other = dir.path().join("page_002.gif");
+        assert_eq!(convert_webp(&other).unwrap(), other);
+    }
+}
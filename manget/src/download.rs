@@ -1,16 +1,32 @@
-use log::{error, info};
 use std::{
     fs,
-    io::{self, Cursor},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
 };
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
 
-use futures::FutureExt;
-use reqwest::{header::CONTENT_TYPE, Response};
+use futures::{stream::FuturesUnordered, FutureExt, Stream, StreamExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER, USER_AGENT},
+    Response, StatusCode,
+};
+use tokio::sync::mpsc::UnboundedSender;
 
 type Result<T> = std::result::Result<T, DownloadError>;
 
+/// Number of times a url answering 429 with a `Retry-After` header is retried
+const MAX_RATE_LIMIT_RETRIES: u32 = 3;
+/// Longer `Retry-After` delays are not waited for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+/// Where MangaDex@Home page requests are reported, see <https://api.mangadex.org/docs/04-chapter/retrieving-chapter/>
+const MANGADEX_REPORT_URL: &str = "https://api.mangadex.network/report";
+/// User-Agent of the retry of a page answered with an html page, ex: a bot challenge
+const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+
 #[derive(thiserror::Error, Debug)]
 pub enum DownloadError {
     #[error("invalid url: {0}")]
@@ -21,6 +37,17 @@ pub enum DownloadError {
     ConvertError(#[from] reqwest::header::ToStrError),
     #[error(transparent)]
     RequestError(#[from] reqwest::Error),
+    #[error("{0} is not a valid image")]
+    InvalidImage(String),
+    #[error("invalid resolve override {0:?}, expected host=ip or host=ip:port")]
+    InvalidResolve(String),
+    #[error("{url} rejected before download: {reason}")]
+    PreflightFailed { url: String, reason: String },
+    #[error("too many requests to {url}")]
+    RateLimited {
+        url: String,
+        retry_after: Option<Duration>,
+    },
     #[error("this error should never be reported")]
     PhantomError,
 }
@@ -30,6 +57,7 @@ pub struct DownloadItem {
     url: String,
     name: Option<String>,
     alt_urls: Vec<String>,
+    extension: Option<String>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -37,6 +65,112 @@ pub struct DownloadOptions {
     items: Vec<DownloadItem>,
     path: PathBuf,
     referer: Option<String>,
+    cookie: Option<String>,
+    verify_images: bool,
+    preflight: bool,
+    events: Option<UnboundedSender<DownloadEvent>>,
+    throttle: Option<Arc<Throttle>>,
+    /// Origin of the last url that worked, tried first when set
+    working_mirror: Option<Arc<Mutex<Option<String>>>>,
+    /// Endpoint receiving the reports of MangaDex@Home page requests
+    report_url: Option<String>,
+    /// User-Agent retrying pages answered with html, `BROWSER_USER_AGENT` if not set
+    escalation_user_agent: Option<String>,
+    /// Client of the page requests, one is built for each page if not set
+    client: Option<reqwest::Client>,
+    /// Addresses used for hosts instead of resolving them
+    resolve: Vec<(String, SocketAddr)>,
+}
+
+/// Report of a MangaDex@Home page request
+#[derive(Debug, Default, serde::Serialize)]
+struct AtHomeReport {
+    url: String,
+    success: bool,
+    bytes: u64,
+    /// milliseconds
+    duration: u64,
+    cached: bool,
+    /// The body was an html page instead of an image, not part of the report
+    #[serde(skip)]
+    html: bool,
+}
+
+/// Paces the chunks of all downloads sharing it to a bandwidth cap
+#[derive(Debug)]
+struct Throttle {
+    bytes_per_sec: usize,
+    /// When the bytes received so far are allowed to be done at the cap
+    next: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: usize) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait until `bytes` more bytes are allowed by the cap
+    async fn consume(&self, bytes: usize) {
+        let until = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+            *next
+        };
+        tokio::time::sleep_until(until.into()).await;
+    }
+}
+
+/// Events sent while downloading, see `DownloadOptions::set_event_sender`
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadEvent {
+    /// Some bytes of `url` were received. `total` is known when the server sends a Content-Length.
+    Progress {
+        url: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// `url` was saved to `path`
+    Finished {
+        url: String,
+        path: PathBuf,
+        size: u64,
+    },
+    /// All the pages of a chapter were tried, even if some of them failed
+    ChapterFinished { stats: DownloadStats },
+}
+
+/// Statistics of a chapter download
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DownloadStats {
+    /// Downloaded pages, including the ones of a resumed download
+    pub pages: usize,
+    /// Size of the downloaded pages, before any conversion
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Number of page downloads tried again after a failure
+    pub retries: usize,
+    /// Pages still failing after the retries
+    pub failures: usize,
+}
+
+impl std::fmt::Display for DownloadStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} pages, {:.1} MB in {:.1}s",
+            self.pages,
+            self.bytes as f64 / 1_000_000.0,
+            self.duration.as_secs_f64()
+        )?;
+        if self.failures > 0 {
+            write!(f, ", {} failed", self.failures)?;
+        }
+        Ok(())
+    }
 }
 
 impl DownloadItem {
@@ -45,6 +179,7 @@ impl DownloadItem {
             url: url.to_string(),
             name: name.map(|x| x.to_string()),
             alt_urls: Vec::new(),
+            extension: None,
         }
     }
 
@@ -53,6 +188,19 @@ impl DownloadItem {
         self
     }
 
+    /// Replace the file name of the item, `None` names it after its url
+    pub fn with_name<T: ToString>(mut self, name: Option<T>) -> Self {
+        self.name = name.map(|x| x.to_string());
+        self
+    }
+
+    /// Force the extension of the saved file, whatever the url or the content type tell, ex: for
+    /// sites serving all pages as `image.php?id=...`
+    pub fn with_extension<T: ToString>(mut self, extension: T) -> Self {
+        self.extension = Some(extension.to_string());
+        self
+    }
+
     pub fn add_option_url<T: ToString>(mut self, url: Option<T>) -> Self {
         if let Some(url) = url {
             self.alt_urls.push(url.to_string())
@@ -71,6 +219,10 @@ impl DownloadItem {
     pub fn alt_urls(&self) -> &[String] {
         &self.alt_urls
     }
+
+    pub fn extension(&self) -> Option<&str> {
+        self.extension.as_deref()
+    }
 }
 
 impl DownloadOptions {
@@ -123,86 +275,561 @@ impl DownloadOptions {
         self.referer = Some(referer.to_string());
         self
     }
+
+    /// Send a raw Cookie header with every page request, ex: a session cookie of a gated site
+    pub fn set_cookie(&mut self, cookie: Option<String>) -> &mut Self {
+        self.cookie = cookie;
+        self
+    }
+
+    /// Receive progress events of the downloads, ex: to show a progress bar
+    pub fn set_event_sender(&mut self, sender: UnboundedSender<DownloadEvent>) -> &mut Self {
+        self.events = Some(sender);
+        self
+    }
+
+    fn send_event(&self, event: DownloadEvent) {
+        if let Some(sender) = &self.events {
+            // the receiver may have been dropped, events are then discarded
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Send a HEAD request before each download to fail fast on empty or non-image pages.
+    /// Servers not supporting HEAD are downloaded as usual.
+    pub fn set_preflight(&mut self, preflight: bool) -> &mut Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Cap the bandwidth of all downloads of these options together, in bytes per second
+    pub fn set_max_bytes_per_sec(&mut self, max_bytes_per_sec: Option<usize>) -> &mut Self {
+        self.throttle = max_bytes_per_sec.map(|rate| Arc::new(Throttle::new(rate)));
+        self
+    }
+
+    /// Try the urls of each item starting with the mirror (scheme, host and port) of the last
+    /// successful download, so a dead primary mirror doesn't cost a failure for every page
+    pub fn set_prefer_working_mirror(&mut self, prefer_working_mirror: bool) -> &mut Self {
+        self.working_mirror = prefer_working_mirror.then(Default::default);
+        self
+    }
+
+    /// Report the result of each page request to a MangaDex@Home node, as asked by the MangaDex
+    /// api to keep the network healthy. Pages of other sites are not reported.
+    pub fn set_report_mangadex(&mut self, report_mangadex: bool) -> &mut Self {
+        self.report_url = report_mangadex.then(|| MANGADEX_REPORT_URL.to_string());
+        self
+    }
+
+    /// Whether page requests are reported to MangaDex
+    #[cfg(test)]
+    pub(crate) fn reports_mangadex(&self) -> bool {
+        self.report_url.is_some()
+    }
+
+    /// Treat downloaded files that are not decodable images as failures, so alternative urls are tried
+    pub fn set_verify_images(&mut self, verify_images: bool) -> &mut Self {
+        self.verify_images = verify_images;
+        self
+    }
+
+    /// User-Agent of the retry of a page answered with an html page instead of an image, as
+    /// detected by `set_verify_images`. Sites with a light bot protection often serve the image
+    /// to a browser. `None` uses a desktop browser User-Agent.
+    pub fn set_escalation_user_agent(&mut self, user_agent: Option<String>) -> &mut Self {
+        self.escalation_user_agent = user_agent;
+        self
+    }
+
+    /// Send the page requests with `client`, ex: to share its connection pool or to configure
+    /// its TLS, proxy or cookie store. Its User-Agent is used instead of `crate::user_agent`.
+    pub fn set_client(&mut self, client: reqwest::Client) -> &mut Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Connect to an address instead of resolving a host, ex: to bypass a poisoned DNS or to pick
+    /// a CDN edge. `spec` is `host=ip` or `host=ip:port`, without port the one of the url is
+    /// used. Not applied to a client given by `set_client`.
+    pub fn add_resolve(&mut self, spec: &str) -> Result<&mut Self> {
+        let invalid = || DownloadError::InvalidResolve(spec.to_string());
+        let (host, addr) = spec.split_once('=').ok_or_else(invalid)?;
+        let host = host.trim().to_lowercase();
+        if host.is_empty() || host.contains([':', '/']) {
+            return Err(invalid());
+        }
+        let addr = addr.trim();
+        // port 0 keeps the port of the url
+        let addr = match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => SocketAddr::new(addr.parse::<IpAddr>().map_err(|_| invalid())?, 0),
+        };
+        self.resolve.push((host, addr));
+        Ok(self)
+    }
 }
 
 pub async fn download(options: &DownloadOptions) -> Vec<Result<PathBuf>> {
-    let items = &options.items;
-    let path = &options.path;
-    let referer = &options.referer;
-    let downloads: Vec<_> = items
+    let mut results: Vec<_> = download_stream(options).collect().await;
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Download all items concurrently, yielding each result with the index of its item as soon as
+/// it is done
+pub fn download_stream(
+    options: &DownloadOptions,
+) -> impl Stream<Item = (usize, Result<PathBuf>)> + '_ {
+    options
+        .items
         .iter()
-        .map(|item| {
+        .enumerate()
+        .map(|(i, item)| {
             let url = item.url().to_string();
-            download_one_item(item, path, referer).then(|result| async move {
+            download_one_item(item, i, options).map(move |result| {
                 match &result {
                     Ok(p) => info!("Downloaded: {} -> {}", url, p.display()),
                     Err(e) => error!("{e}"),
                 }
-                result
+                (i, result)
             })
         })
-        .collect();
-    futures::future::join_all(downloads).await
+        .collect::<FuturesUnordered<_>>()
 }
 
 async fn download_one_item(
     item: &DownloadItem,
-    path: &Path,
-    referer: &Option<String>,
+    index: usize,
+    options: &DownloadOptions,
 ) -> Result<PathBuf> {
     let mut urls = vec![item.url()];
     for url in item.alt_urls() {
         urls.push(url);
     }
+    if let Some(working_mirror) = &options.working_mirror {
+        let working_mirror = working_mirror.lock().unwrap().clone();
+        if working_mirror.is_some() {
+            // stable sort, other urls keep their order
+            urls.sort_by_key(|url| url_origin(url) != working_mirror);
+        }
+    }
     let mut ret_err = DownloadError::PhantomError;
     for url in urls {
-        match download_one_url(url, item.name(), path, referer).await {
-            Ok(p) => return Ok(p),
-            Err(e) => ret_err = e,
+        let mut rate_limit_retries = 0;
+        loop {
+            let span = info_span!(
+                "page",
+                url,
+                name = item.name(),
+                attempt = rate_limit_retries + 1,
+                bytes = field::Empty
+            );
+            match download_one_url(url, item, index, options)
+                .instrument(span)
+                .await
+            {
+                Ok(p) => {
+                    if let Some(working_mirror) = &options.working_mirror {
+                        *working_mirror.lock().unwrap() = url_origin(url);
+                    }
+                    return Ok(p);
+                }
+                Err(DownloadError::RateLimited {
+                    retry_after: Some(delay),
+                    ..
+                }) if rate_limit_retries < MAX_RATE_LIMIT_RETRIES && delay <= MAX_RETRY_AFTER => {
+                    rate_limit_retries += 1;
+                    info!("Rate limited by {url}, retry in {delay:?}");
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    ret_err = e;
+                    break;
+                }
+            }
         }
     }
     Err(ret_err)
 }
 
+fn url_origin(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()
+        .map(|url| url.origin().ascii_serialization())
+}
+
+/// Parse the `Retry-After` header, either a number of seconds or an HTTP date
+fn parse_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default()),
+    }
+}
+
+/// `url` is the one of `item` or one of its mirrors. `index` is the position of the item, used
+/// to name the file if neither its name nor the url gives a usable one
 async fn download_one_url(
     url: &str,
-    name: Option<&str>,
-    path: &Path,
-    referer: &Option<String>,
+    item: &DownloadItem,
+    index: usize,
+    options: &DownloadOptions,
 ) -> Result<PathBuf> {
-    let client = reqwest::ClientBuilder::new().user_agent("Manget").build()?;
+    // reqwest doesn't support the file scheme
+    if let Ok(parsed) = reqwest::Url::parse(url) {
+        if parsed.scheme() == "file" {
+            return copy_local_page(&parsed, item, index, options);
+        }
+    }
+    let user_agent = crate::user_agent();
+    let Some(report_url) = options
+        .report_url
+        .as_deref()
+        .filter(|_| is_mangadex_at_home(url))
+    else {
+        return download_http_page_escalating(
+            url,
+            item,
+            index,
+            options,
+            &user_agent,
+            &mut AtHomeReport::default(),
+        )
+        .await;
+    };
+    let start = Instant::now();
+    let mut report = AtHomeReport {
+        url: url.to_string(),
+        ..Default::default()
+    };
+    let result =
+        download_http_page_escalating(url, item, index, options, &user_agent, &mut report).await;
+    report.success = result.is_ok();
+    report.duration = start.elapsed().as_millis() as u64;
+    send_at_home_report(report_url, &report).await;
+    result
+}
+
+/// Download a page over http with `user_agent`, or the one of the client given by
+/// `set_client`, retrying with the escalation User-Agent if an html page is served instead of
+/// the image
+async fn download_http_page_escalating(
+    url: &str,
+    item: &DownloadItem,
+    index: usize,
+    options: &DownloadOptions,
+    user_agent: &str,
+    report: &mut AtHomeReport,
+) -> Result<PathBuf> {
+    let client_user_agent = options.client.is_none().then_some(user_agent);
+    let result = download_http_page(url, item, index, options, client_user_agent, report).await;
+    let escalation = options
+        .escalation_user_agent
+        .as_deref()
+        .unwrap_or(BROWSER_USER_AGENT);
+    if result.is_err() && report.html && escalation != user_agent {
+        info!("{url} served an html page, retry with a browser User-Agent");
+        *report = AtHomeReport {
+            url: report.url.clone(),
+            ..Default::default()
+        };
+        return download_http_page(url, item, index, options, Some(escalation), report).await;
+    }
+    result
+}
+
+/// Download a page over http, filling the size and cache status of `report`. `user_agent`
+/// overrides the one of the client.
+async fn download_http_page(
+    url: &str,
+    item: &DownloadItem,
+    index: usize,
+    options: &DownloadOptions,
+    user_agent: Option<&str>,
+    report: &mut AtHomeReport,
+) -> Result<PathBuf> {
+    let client = match &options.client {
+        Some(client) => client.clone(),
+        // some servers compress images, the saved page must be the decoded one
+        None => options
+            .resolve
+            .iter()
+            .fold(crate::client::client_builder(), |builder, (host, addr)| {
+                builder.resolve(host, *addr)
+            })
+            .gzip(true)
+            .brotli(true)
+            .build()?,
+    };
+    let expected_size = if options.preflight {
+        preflight(&client, url, options, user_agent).await?
+    } else {
+        None
+    };
     let mut request = client.get(url).timeout(Duration::from_secs(60));
-    if let Some(r) = referer {
+    if let Some(user_agent) = user_agent {
+        request = request.header(USER_AGENT, user_agent);
+    }
+    if let Some(r) = &options.referer {
         request = request.header("referer", r);
     }
-    let response = request.send().await?.error_for_status()?;
+    if let Some(cookie) = &options.cookie {
+        request = request.header("cookie", cookie);
+    }
+    let response = request.send().await?;
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        return Err(DownloadError::RateLimited {
+            url: url.to_string(),
+            retry_after: parse_retry_after(&response),
+        });
+    }
+    report.cached = response
+        .headers()
+        .get("X-Cache")
+        .and_then(|x| x.to_str().ok())
+        .is_some_and(|x| x.starts_with("HIT"));
+    let response = response.error_for_status()?;
 
-    // provided file name or inferred from url
-    let file_name = match name {
-        Some(value) => value.to_string(),
-        None => reqwest::Url::parse(url)
-            .map_err(|_| DownloadError::InvalidUrl(url.to_string()))?
-            .path_segments()
-            .ok_or(DownloadError::InvalidUrl(url.to_string()))?
-            .last()
-            .ok_or(DownloadError::InvalidUrl(url.to_string()))?
-            .to_string(),
+    let total = response.content_length().or(expected_size);
+    let mut response = response;
+    // CDNs often send images as application/octet-stream, then the first bytes tell the format
+    let mut next_chunk = response.chunk().await?;
+    let extension = infer_extension_from_response(&response)
+        .or_else(|| next_chunk.as_deref().and_then(sniff_image_extension));
+    // after redirects, the final url names the resource, ex: a CDN redirecting to "{hash}.png"
+    let final_url = response.url().to_string();
+    let (file_path, mut file) = create_page_file(&final_url, item, index, extension, options)?;
+    if let Some(size) = expected_size {
+        file.set_len(size)?;
+    }
+    let mut written = 0;
+    while let Some(chunk) = next_chunk {
+        if let Some(throttle) = &options.throttle {
+            throttle.consume(chunk.len()).await;
+        }
+        file.write_all(&chunk)?;
+        written += chunk.len() as u64;
+        options.send_event(DownloadEvent::Progress {
+            url: url.to_string(),
+            downloaded: written,
+            total,
+        });
+        next_chunk = response.chunk().await?;
+    }
+    Span::current().record("bytes", written);
+    report.bytes = written;
+    if expected_size.is_some_and(|size| size != written) {
+        file.set_len(written)?;
+    }
+    if options.verify_images && !is_valid_image(&file_path) {
+        report.html = is_html_page(&file_path);
+        let _ = fs::remove_file(&file_path);
+        return Err(DownloadError::InvalidImage(url.to_string()));
+    }
+    options.send_event(DownloadEvent::Finished {
+        url: url.to_string(),
+        path: file_path.clone(),
+        size: written,
+    });
+    Ok(file_path)
+}
+
+/// Pages served by a MangaDex@Home node, the main MangaDex server is not part of the network
+fn is_mangadex_at_home(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| {
+        url.host_str() != Some("uploads.mangadex.org")
+            && (url.path().starts_with("/data/") || url.path().starts_with("/data-saver/"))
+    })
+}
+
+/// Reports are best effort, failures are only logged
+async fn send_at_home_report(report_url: &str, report: &AtHomeReport) {
+    let result = async {
+        crate::client::client_builder()
+            .build()?
+            .post(report_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(report).unwrap_or_default())
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?
+            .error_for_status()
+    }
+    .await;
+    if let Err(e) = result {
+        warn!("Cannot report {} to MangaDex@Home: {e}", report.url);
+    }
+}
+
+/// Create the file of a page. It is named after the item, or the last url segment, or its index,
+/// with `extension` added if the name has none. The extension forced by the item replaces any.
+fn create_page_file(
+    url: &str,
+    item: &DownloadItem,
+    index: usize,
+    extension: Option<String>,
+    options: &DownloadOptions,
+) -> Result<(PathBuf, fs::File)> {
+    let with_extension = |file_name: String| {
+        // convert to path to check for extension
+        let file_name = PathBuf::from(file_name);
+        match (item.extension(), &extension) {
+            (Some(forced), _) => file_name.with_extension(forced),
+            (None, Some(extension)) if file_name.extension().is_none() => {
+                file_name.with_extension(extension)
+            }
+            _ => file_name,
+        }
     };
+    let index_name = || with_extension(format!("page_{:03}", index + 1));
 
-    // convert to path to check for extension
-    let mut file_name = PathBuf::from(file_name);
-    if file_name.extension().is_none() {
-        if let Some(extension) = infer_extension_from_response(&response) {
-            file_name = file_name.with_extension(extension);
+    // provided file name or inferred from url
+    let page_file = match item.name() {
+        Some(value) => {
+            let file_path = options.path.join(with_extension(value.to_string()));
+            let file = fs::File::create(&file_path)?;
+            (file_path, file)
+        }
+        None => {
+            let url_name = reqwest::Url::parse(url)
+                .map_err(|_| DownloadError::InvalidUrl(url.to_string()))?
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .and_then(file_name_from_url_segment);
+            let file_path = options
+                .path
+                .join(url_name.map(with_extension).unwrap_or_else(index_name));
+            // pages sharing the last url segment must not overwrite each other
+            match fs::File::options()
+                .write(true)
+                .create_new(true)
+                .open(&file_path)
+            {
+                Ok(file) => (file_path, file),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let file_path = options.path.join(index_name());
+                    let file = fs::File::create(&file_path)?;
+                    (file_path, file)
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
+    };
+    Ok(page_file)
+}
+
+/// Copy a local page, ex: pages scraped beforehand
+fn copy_local_page(
+    url: &reqwest::Url,
+    item: &DownloadItem,
+    index: usize,
+    options: &DownloadOptions,
+) -> Result<PathBuf> {
+    let source = url
+        .to_file_path()
+        .map_err(|_| DownloadError::InvalidUrl(url.to_string()))?;
+    let extension = source
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    let mut reader = fs::File::open(&source)?;
+    let (file_path, mut file) = create_page_file(url.as_str(), item, index, extension, options)?;
+    let size = io::copy(&mut reader, &mut file)?;
+    Span::current().record("bytes", size);
+    options.send_event(DownloadEvent::Progress {
+        url: url.to_string(),
+        downloaded: size,
+        total: Some(size),
+    });
+    if options.verify_images && !is_valid_image(&file_path) {
+        let _ = fs::remove_file(&file_path);
+        return Err(DownloadError::InvalidImage(url.to_string()));
     }
-    let file_path = path.join(file_name);
-    let mut file = std::fs::File::create(&file_path)?;
-    let mut content = Cursor::new(response.bytes().await?);
-    std::io::copy(&mut content, &mut file)?;
+    options.send_event(DownloadEvent::Finished {
+        url: url.to_string(),
+        path: file_path.clone(),
+        size,
+    });
     Ok(file_path)
 }
 
+/// Decode and sanitize the last segment of a url path to use it as file name
+fn file_name_from_url_segment(segment: &str) -> Option<String> {
+    let decoded = percent_encoding::percent_decode_str(segment).decode_utf8_lossy();
+    let file_name = sanitize_filename::sanitize(decoded.trim());
+    (!file_name.is_empty() && !file_name.starts_with('.')).then_some(file_name)
+}
+
+/// Check the page with a HEAD request, returning its size if known. Any failure of the HEAD
+/// request itself is ignored as some servers don't support it.
+async fn preflight(
+    client: &reqwest::Client,
+    url: &str,
+    options: &DownloadOptions,
+    user_agent: Option<&str>,
+) -> Result<Option<u64>> {
+    let mut request = client.head(url).timeout(Duration::from_secs(60));
+    if let Some(user_agent) = user_agent {
+        request = request.header(USER_AGENT, user_agent);
+    }
+    if let Some(r) = &options.referer {
+        request = request.header("referer", r);
+    }
+    if let Some(cookie) = &options.cookie {
+        request = request.header("cookie", cookie);
+    }
+    let response = match request.send().await {
+        Ok(response) if response.status().is_success() => response,
+        _ => return Ok(None),
+    };
+    let rejected = |reason: String| DownloadError::PreflightFailed {
+        url: url.to_string(),
+        reason,
+    };
+    let size = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+    if size == Some(0) {
+        return Err(rejected(String::from("empty content")));
+    }
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<mime::Mime>().ok());
+    if let Some(content_type) = content_type {
+        if content_type.type_() != mime::IMAGE && content_type != mime::APPLICATION_OCTET_STREAM {
+            return Err(rejected(format!("content type is {content_type}")));
+        }
+    }
+    Ok(size)
+}
+
+/// Check that the file header can be decoded as an image with non-zero dimensions
+fn is_valid_image(path: &Path) -> bool {
+    image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .ok()
+        .and_then(|reader| reader.into_dimensions().ok())
+        .is_some_and(|(width, height)| width > 0 && height > 0)
+}
+
+/// Whether the file starts like an html document, ex: a bot challenge page
+fn is_html_page(path: &Path) -> bool {
+    let mut start = [0; 512];
+    let Ok(len) = fs::File::open(path).and_then(|mut file| io::Read::read(&mut file, &mut start))
+    else {
+        return false;
+    };
+    let start = String::from_utf8_lossy(&start[..len]).to_lowercase();
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    start.starts_with("<!doctype html") || start.starts_with("<html") || start.contains("<body")
+}
+
 fn infer_extension_from_response(response: &Response) -> Option<String> {
     response
         .headers()
@@ -223,3 +850,656 @@ fn infer_extension_from_response(response: &Response) -> Option<String> {
             _ => None,
         })
 }
+
+/// Extension of an image from its magic number
+fn sniff_image_extension(data: &[u8]) -> Option<String> {
+    match image::guess_format(data).ok()? {
+        image::ImageFormat::Jpeg => Some(String::from("jpg")),
+        image::ImageFormat::Png => Some(String::from("png")),
+        image::ImageFormat::WebP => Some(String::from("webp")),
+        image::ImageFormat::Gif => Some(String::from("gif")),
+        image::ImageFormat::Avif => Some(String::from("avif")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn png() -> Vec<u8> {
+        let mut data = Vec::new();
+        image::RgbImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut data), image::ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[tokio::test]
+    async fn test_verify_images() {
+        let server = MockServer::start().await;
+        Mock::given(path("/error_page"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<html></html>", "text/html"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/page.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url_with_name(&format!("{}/error_page", server.uri()), "page_001")
+            .add_download_item(
+                &DownloadItem::new(format!("{}/error_page", server.uri()), Some("page_002"))
+                    .add_url(format!("{}/page.png", server.uri())),
+            );
+
+        let results = download(&options).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+
+        options.set_verify_images(true);
+        let results = download(&options).await;
+        assert!(matches!(results[0], Err(DownloadError::InvalidImage(_))));
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &dir.path().join("page_002.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_escalation_user_agent() {
+        let server = MockServer::start().await;
+        Mock::given(path("/page"))
+            .and(wiremock::matchers::header(
+                "user-agent",
+                "Mozilla/5.0 Browser",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .with_priority(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/page"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                "<!DOCTYPE html><html><body>Checking your browser</body></html>",
+                "text/html",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url_with_name(&format!("{}/page", server.uri()), "page_001")
+            .set_verify_images(true)
+            .set_escalation_user_agent(Some(String::from("Mozilla/5.0 Browser")));
+        let results = download(&options).await;
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &dir.path().join("page_001.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_client() {
+        let server = MockServer::start().await;
+        Mock::given(path("/page.png"))
+            .and(wiremock::matchers::header("x-api-key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-api-key", "secret".parse().unwrap());
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url_with_name(&format!("{}/page.png", server.uri()), "page_001")
+            .add_url_with_name(&format!("{}/page.png", server.uri()), "page_002")
+            .set_client(client);
+        let results = download(&options).await;
+        assert!(results.iter().all(|r| r.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_redirect_names_file() {
+        let server = MockServer::start().await;
+        Mock::given(path("/a"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/b.png"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/b.png"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw("page", "application/octet-stream"),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url(&format!("{}/a", server.uri()))
+            .add_url_with_name(&format!("{}/a", server.uri()), "named.jpg");
+        let results = download(&options).await;
+        assert_eq!(results[0].as_ref().unwrap(), &dir.path().join("b.png"));
+        // an explicit name wins
+        assert_eq!(results[1].as_ref().unwrap(), &dir.path().join("named.jpg"));
+    }
+
+    #[tokio::test]
+    async fn test_forced_extension() {
+        let server = MockServer::start().await;
+        Mock::given(path("/image.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let url = format!("{}/image.php?id=1", server.uri());
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options.add_download_items([
+            &DownloadItem::new(&url, None::<String>).with_extension("jpg"),
+            &DownloadItem::new(&url, Some("page_002")).with_extension("jpg"),
+            &DownloadItem::new(&url, Some("page_003")),
+        ]);
+        let results = download(&options).await;
+        assert_eq!(results[0].as_ref().unwrap(), &dir.path().join("image.jpg"));
+        assert_eq!(
+            results[1].as_ref().unwrap(),
+            &dir.path().join("page_002.jpg")
+        );
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &dir.path().join("page_003.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_override() {
+        let server = MockServer::start().await;
+        Mock::given(path("/page.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        let port = server.address().port();
+        options
+            .add_resolve("cdn.manga.invalid=127.0.0.1")
+            .unwrap()
+            .add_url_with_name(
+                &format!("http://cdn.manga.invalid:{port}/page.png"),
+                "page_001",
+            );
+        let results = download(&options).await;
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &dir.path().join("page_001.png")
+        );
+
+        for spec in [
+            "cdn.manga.invalid",
+            "=127.0.0.1",
+            "cdn:80=127.0.0.1",
+            "cdn=localhost",
+        ] {
+            assert!(matches!(
+                options.add_resolve(spec),
+                Err(DownloadError::InvalidResolve(_))
+            ));
+        }
+        assert!(options.add_resolve("cdn=[::1]:8080").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_gzip_encoded_page() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&png()).unwrap();
+        let server = MockServer::start().await;
+        Mock::given(path("/page.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_raw(encoder.finish().unwrap(), "image/png"),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url_with_name(&format!("{}/page.png", server.uri()), "page_001")
+            .set_verify_images(true);
+        let results = download(&options).await;
+        let page = results[0].as_ref().unwrap();
+        assert_eq!(fs::read(page).unwrap(), png());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(path("/page.png"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/page.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options.add_url_with_name(&format!("{}/page.png", server.uri()), "page_001");
+
+        let start = std::time::Instant::now();
+        let results = download(&options).await;
+        assert!(results[0].is_ok());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_user_agent() {
+        let server = MockServer::start().await;
+        Mock::given(path("/page.png"))
+            .and(wiremock::matchers::header(
+                "user-agent",
+                "Mozilla/5.0 Manget-Test",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        // the configured User-Agent is passed in, the global one is shared with other tests
+        let dir = tempfile::tempdir().unwrap();
+        let options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        let url = format!("{}/page.png", server.uri());
+        let result = download_http_page_escalating(
+            &url,
+            &DownloadItem::new(&url, Some("page_001")),
+            0,
+            &options,
+            "Mozilla/5.0 Manget-Test",
+            &mut AtHomeReport::default(),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cookie() {
+        let server = MockServer::start().await;
+        Mock::given(path("/chapter"))
+            .and(wiremock::matchers::header("cookie", "session=scraper"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/page.png"))
+            .and(wiremock::matchers::header("cookie", "session=page"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let uri = server.uri();
+        let html = crate::client::fetch_text_with(
+            format!("{uri}/chapter").parse().unwrap(),
+            None,
+            Some(String::from("session=scraper")),
+            crate::client::fetch_timeout(),
+            Duration::ZERO,
+        )
+        .await;
+        assert!(html.is_ok());
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .set_cookie(Some(String::from("session=page")))
+            .add_url(&format!("{uri}/page.png"));
+        assert!(download(&options).await[0].is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_sniff_generic_content_type() {
+        let server = MockServer::start().await;
+        Mock::given(path("/octet"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(png(), "application/octet-stream"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/missing"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png()))
+            .mount(&server)
+            .await;
+        Mock::given(path("/text"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw("not an image", "application/octet-stream"),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url_with_name(&format!("{}/octet", server.uri()), "page_001")
+            .add_url_with_name(&format!("{}/missing", server.uri()), "page_002")
+            .add_url_with_name(&format!("{}/text", server.uri()), "page_003");
+        let paths: Vec<_> = download(&options)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                dir.path().join("page_001.png"),
+                dir.path().join("page_002.png"),
+                dir.path().join("page_003")
+            ]
+        );
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), png());
+    }
+
+    #[tokio::test]
+    async fn test_report_mangadex() {
+        let server = MockServer::start().await;
+        Mock::given(path("/data-saver/h/1.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("X-Cache", "HIT")
+                    .set_body_raw(png(), "image/png"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(path("/data-saver/h/2.png"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/report"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options.set_report_mangadex(true).report_url = Some(format!("{}/report", server.uri()));
+        options
+            .add_url(&format!("{}/data-saver/h/1.png", server.uri()))
+            .add_url(&format!("{}/data-saver/h/2.png", server.uri()));
+        let results = download(&options).await;
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+
+        let mut reports: Vec<serde_json::Value> = server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|r| r.url.path() == "/report")
+            .map(|r| serde_json::from_slice(&r.body).unwrap())
+            .collect();
+        reports.sort_by_key(|r| r["url"].as_str().unwrap().to_string());
+        let success = reports[0].as_object().unwrap();
+        let mut keys: Vec<_> = success.keys().map(|k| k.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["bytes", "cached", "duration", "success", "url"]);
+        assert_eq!(success["success"], true);
+        assert_eq!(success["cached"], true);
+        assert_eq!(success["bytes"], png().len());
+        assert!(success["duration"].is_u64());
+        assert_eq!(reports[1]["success"], false);
+        assert_eq!(reports[1]["cached"], false);
+    }
+
+    #[tokio::test]
+    async fn test_preflight() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/empty.png"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "image/png")
+                    .insert_header("Content-Length", "0"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/page.png"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .set_preflight(true)
+            .add_url_with_name(&format!("{}/empty.png", server.uri()), "page_001")
+            .add_url_with_name(&format!("{}/page.png", server.uri()), "page_002");
+        let results = download(&options).await;
+        assert!(matches!(
+            results[0],
+            Err(DownloadError::PreflightFailed { .. })
+        ));
+        assert_eq!(std::fs::read(results[1].as_ref().unwrap()).unwrap(), png());
+    }
+
+    #[tokio::test]
+    async fn test_progress_events() {
+        let server = MockServer::start().await;
+        let pages = [vec![1u8; 100_000], vec![2u8; 20]];
+        for (i, page) in pages.iter().enumerate() {
+            Mock::given(path(format!("/{i}")))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(page.clone(), "image/png"))
+                .mount(&server)
+                .await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .set_event_sender(sender)
+            .add_url(&format!("{}/0", server.uri()))
+            .add_url(&format!("{}/1", server.uri()));
+        assert!(download(&options).await.iter().all(|r| r.is_ok()));
+        drop(options);
+
+        let mut downloaded = std::collections::HashMap::new();
+        let mut finished = 0;
+        while let Some(event) = receiver.recv().await {
+            match event {
+                DownloadEvent::Progress {
+                    url,
+                    downloaded: bytes,
+                    total,
+                } => {
+                    assert!(total.is_some());
+                    downloaded.insert(url, bytes);
+                }
+                DownloadEvent::Finished { .. } => finished += 1,
+                DownloadEvent::ChapterFinished { .. } => panic!("no chapter is downloaded"),
+            }
+        }
+        assert_eq!(finished, 2);
+        assert_eq!(downloaded.values().sum::<u64>(), 100_020);
+    }
+
+    /// Name and fields of a span
+    type SpanRecord = (String, Vec<(String, String)>);
+
+    /// Record every span created
+    #[derive(Clone, Default)]
+    struct SpanRecorder(std::sync::Arc<std::sync::Mutex<Vec<SpanRecord>>>);
+
+    struct FieldVisitor<'a>(&'a mut Vec<(String, String)>);
+
+    impl field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &field::Field, value: &str) {
+            self.0.push((field.name().to_string(), value.to_string()));
+        }
+
+        fn record_debug(&mut self, field: &field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .push((field.name().to_string(), format!("{value:?}")));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Vec::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            let name = attrs.metadata().name().to_string();
+            self.0.lock().unwrap().push((name, fields));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let server = MockServer::start().await;
+        Mock::given(path("/1.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let recorder = SpanRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        let url = format!("{}/1.png", server.uri());
+        options.add_url(&url);
+        assert!(download(&options).await[0].is_ok());
+
+        let spans = recorder.0.lock().unwrap();
+        assert!(spans.iter().any(|(name, fields)| name == "page"
+            && fields.contains(&(String::from("url"), url.clone()))
+            && fields.contains(&(String::from("attempt"), String::from("1")))));
+    }
+
+    #[tokio::test]
+    async fn test_max_bytes_per_sec() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0u8; 20_000], "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .set_max_bytes_per_sec(Some(20_000))
+            .add_url(&format!("{}/1.png", server.uri()))
+            .add_url(&format!("{}/2.png", server.uri()));
+        let start = Instant::now();
+        assert!(download(&options).await.iter().all(|r| r.is_ok()));
+        let elapsed = start.elapsed();
+        // 40 KB at 20 KB/s
+        assert!(elapsed >= Duration::from_millis(1800), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_prefer_working_mirror() {
+        let primary = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        let mirror = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .expect(3)
+            .mount(&mirror)
+            .await;
+        let item = |i: usize| {
+            DownloadItem::new(format!("{}/{i}.png", primary.uri()), None::<String>)
+                .add_url(format!("{}/{i}.png", mirror.uri()))
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .set_prefer_working_mirror(true)
+            .add_download_item(&item(1));
+        assert!(download(&options).await[0].is_ok());
+        options.clear_download_items();
+        options.add_download_items(&[item(2), item(3)]);
+        assert!(download(&options).await.iter().all(|r| r.is_ok()));
+    }
+
+    #[test]
+    fn test_file_name_from_url_segment() {
+        let segment = |url: &str| {
+            reqwest::Url::parse(url)
+                .unwrap()
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .and_then(file_name_from_url_segment)
+        };
+        assert_eq!(segment("https://a.com/chapter/"), None);
+        assert_eq!(
+            segment("https://a.com/img/01.jpg?token=abc&v=2").as_deref(),
+            Some("01.jpg")
+        );
+        assert_eq!(
+            segment("https://a.com/img/page%201%3F.jpg").as_deref(),
+            Some("page 1.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unnamed_pages_do_not_overwrite() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(png(), "image/png"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = DownloadOptions::new().set_path(dir.path()).unwrap();
+        options
+            .add_url(&format!("{}/a/image.png", server.uri()))
+            .add_url(&format!("{}/b/image.png", server.uri()))
+            .add_url(&format!("{}/c/", server.uri()));
+        let mut paths: Vec<_> = download(&options)
+            .await
+            .into_iter()
+            .map(|r| r.unwrap())
+            .collect();
+        let last = paths.pop().unwrap();
+        assert_eq!(last, dir.path().join("page_003.png"));
+        paths.sort();
+        assert_ne!(paths[0], paths[1]);
+        assert!(paths.contains(&dir.path().join("image.png")));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+}
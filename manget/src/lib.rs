@@ -1,2 +1,11 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod client;
+mod convert;
 pub mod download;
 pub mod manga;
+
+pub use client::{
+    set_cookie, set_fetch_timeout, set_request_delay, set_user_agent,
+    set_vietnamese_accept_language, user_agent, USER_AGENT_ENV,
+};
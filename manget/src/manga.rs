@@ -1,21 +1,68 @@
+#[cfg(feature = "site-asura")]
+mod asura;
+#[cfg(feature = "site-batoto")]
+mod batoto;
+#[cfg(feature = "site-blogtruyen")]
 mod blogtruyen;
+#[cfg(feature = "site-comick")]
+mod comick;
+#[cfg(feature = "html")]
+mod diagnose;
+#[cfg(feature = "site-mangadex")]
 mod mangadex;
+#[cfg(feature = "site-mangakakalot")]
+mod mangakakalot;
+// batoto reuses the mangapark parsers
+#[cfg(any(feature = "site-mangapark", feature = "site-batoto"))]
+#[cfg_attr(not(feature = "site-mangapark"), allow(dead_code))]
 mod mangapark;
+mod manual;
+#[cfg(feature = "site-nettruyen")]
 mod nettruyen;
+#[cfg(feature = "site-toptruyen")]
 mod toptruyen;
+#[cfg(feature = "site-truyentranhtuan")]
 mod truyentranhtuan;
+#[cfg(feature = "site-webtoon")]
+mod webtoon;
+#[cfg(feature = "site-weebcentral")]
+mod weebcentral;
 
-use log::info;
-use reqwest::IntoUrl;
+use futures::StreamExt;
+use regex::Regex;
+use reqwest::{IntoUrl, StatusCode, Url};
+use serde::{Deserialize, Serialize};
 use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
     fmt::Display,
     fs,
+    future::Future,
+    io::{Cursor, Seek, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    str::FromStr,
+    sync::{Arc, OnceLock, RwLock},
+    time::{Duration, Instant},
 };
+use tracing::{info, instrument, warn};
 use zip::write::FileOptions;
 use zip::ZipWriter;
 
-use crate::download::{download, DownloadError, DownloadItem, DownloadOptions};
+use crate::convert::{convert_webp, recompress};
+use crate::download::{
+    download, download_stream, DownloadError, DownloadEvent, DownloadItem, DownloadOptions,
+    DownloadStats,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+#[cfg(feature = "html")]
+pub use diagnose::{diagnose, DiagnosisReport, SelectorCheck};
+pub use manual::ManualChapter;
+pub use tokio_util::sync::CancellationToken;
 
 pub trait Chapter: Sync + Send {
     /// Get the URL of the chapter
@@ -32,7 +79,180 @@ pub trait Chapter: Sync + Send {
     }
     /// Get the full name of manga + chapter
     fn full_name(&self) -> String {
-        sanitize_filename::sanitize(format!("{} - {}", self.manga(), self.chapter()))
+        full_name(&self.url(), &self.manga(), &self.chapter())
+    }
+    /// Get the chapter number parsed from `chapter()`, ex: 99 for "vol 7 chap 99"
+    fn chapter_number(&self) -> Option<f64> {
+        parse_chapter_number(&self.chapter())
+    }
+    /// Get the description of the manga, if the site provides one
+    fn summary(&self) -> Option<String> {
+        None
+    }
+    /// Get the genres of the manga, if the site provides them
+    fn genres(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Get the names of the translators (scanlation groups) of the chapter
+    fn translators(&self) -> Vec<String> {
+        Vec::new()
+    }
+    /// Get a stable identifier of the site the chapter comes from, ex: "mangadex"
+    fn source(&self) -> &'static str {
+        "unknown"
+    }
+    /// Get the main url of every page, ex: to hand them to an external downloader
+    fn page_urls(&self) -> Vec<String> {
+        self.pages_download_info()
+            .iter()
+            .map(|item| item.url().to_string())
+            .collect()
+    }
+}
+
+/// "{manga} - {chapter}" sanitized for use as file name, with fallbacks for empty names
+fn full_name(url: &str, manga: &str, chapter: &str) -> String {
+    let mut manga = manga.trim().to_string();
+    if manga.is_empty() {
+        manga = name_from_url(url);
+        warn!("Manga name is empty, use '{}' instead", manga);
+    }
+    let mut chapter = chapter.trim().to_string();
+    if chapter.is_empty() {
+        chapter = String::from("Unknown Chapter");
+        warn!("Chapter name is empty, use '{}' instead", chapter);
+    }
+    sanitize_filename::sanitize(format!("{} - {}", manga, chapter))
+}
+
+/// Chapter information that is cheaper to get than the whole chapter, ex: to show its name
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChapterMeta {
+    pub url: String,
+    pub manga: String,
+    pub chapter: String,
+    pub volume: Option<String>,
+    /// Number of pages, if known without resolving them
+    pub page_count: Option<usize>,
+}
+
+impl ChapterMeta {
+    /// Get the full name of manga + chapter, same as `Chapter::full_name`
+    pub fn full_name(&self) -> String {
+        full_name(&self.url, &self.manga, &self.chapter)
+    }
+
+    fn from_chapter(chapter: &dyn Chapter) -> Self {
+        Self {
+            url: chapter.url(),
+            manga: chapter.manga(),
+            chapter: chapter.chapter(),
+            volume: None,
+            page_count: Some(chapter.pages_download_info().len()),
+        }
+    }
+}
+
+/// Path of a chapter in `out_dir`: "{out_dir}/{full name}", or "{out_dir}/{manga}/{full name}"
+/// when grouping by manga
+pub fn chapter_path<C: Chapter + ?Sized>(
+    chapter: &C,
+    out_dir: &Path,
+    group_by_manga: bool,
+) -> PathBuf {
+    if group_by_manga {
+        out_dir
+            .join(manga_dir_name(chapter))
+            .join(chapter.full_name())
+    } else {
+        out_dir.join(chapter.full_name())
+    }
+}
+
+/// Folder name of the manga of a chapter. Sanitizing can give different series the same name,
+/// ex: "Who?" and "Who", so a changed name is suffixed with a hash of the original one.
+fn manga_dir_name<C: Chapter + ?Sized>(chapter: &C) -> String {
+    let manga = chapter.manga().trim().to_string();
+    if manga.is_empty() {
+        return name_from_url(&chapter.url());
+    }
+    let sanitized = sanitize_filename::sanitize(&manga);
+    if sanitized == manga {
+        sanitized
+    } else {
+        let hash = blake3::hash(manga.as_bytes()).to_hex();
+        format!("{} ({})", sanitized.trim(), &hash[..6])
+    }
+}
+
+/// Derive a name from the last non-empty path segment of the url, or its domain.
+fn name_from_url(url: &str) -> String {
+    let url = reqwest::Url::parse(url).ok();
+    url.as_ref()
+        .and_then(|u| u.path_segments())
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .or_else(|| url.as_ref().and_then(|u| u.domain()))
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| String::from("Unknown Manga"))
+}
+
+/// Parse the chapter number from a chapter label like "Vol.13 Ch.106: Bell's Tears" or "Chương 85".
+/// If there is no chapter keyword, the first number in the label is used.
+pub fn parse_chapter_number(chapter: &str) -> Option<f64> {
+    static KEYWORD_PATTERN: OnceLock<Regex> = OnceLock::new();
+    static NUMBER_PATTERN: OnceLock<Regex> = OnceLock::new();
+    let keyword_pattern = KEYWORD_PATTERN.get_or_init(|| {
+        Regex::new(
+            r"(?i)(?:chapter|chap|ch\.?|chương|chuong|episode|ep\.?)\s*[-.:#]?\s*(\d+(?:\.\d+)?)",
+        )
+        .unwrap()
+    });
+    let number_pattern = NUMBER_PATTERN.get_or_init(|| Regex::new(r"\d+(?:\.\d+)?").unwrap());
+    keyword_pattern
+        .captures(chapter)
+        .and_then(|c| c.get(1))
+        .or_else(|| number_pattern.find(chapter))
+        .and_then(|m| m.as_str().parse().ok())
+}
+
+/// A filter on chapter numbers, parsed from strings like "10-20", "5,7,9" or "1-3,7.5".
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterFilter {
+    ranges: Vec<(f64, f64)>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid chapter filter: '{0}'")]
+pub struct ChapterFilterError(String);
+
+impl ChapterFilter {
+    /// Check if the chapter number is selected by the filter. Ranges are inclusive.
+    pub fn matches(&self, number: f64) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= number && number <= end)
+    }
+}
+
+impl FromStr for ChapterFilter {
+    type Err = ChapterFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ChapterFilterError(s.to_string());
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            let (start, end) = match part.split_once('-') {
+                Some((start, end)) => (start.trim(), end.trim()),
+                None => (part, part),
+            };
+            let start: f64 = start.parse().map_err(|_| error())?;
+            let end: f64 = end.parse().map_err(|_| error())?;
+            if start > end {
+                return Err(error());
+            }
+            ranges.push((start, end));
+        }
+        Ok(Self { ranges })
     }
 }
 
@@ -43,140 +263,3517 @@ pub enum ChapterError {
         path: PathBuf,
         source: DownloadError,
     },
-    #[error("failed to download some pages")]
-    PagesDownloadError { sources: Vec<DownloadError> },
+    /// Name of each failed page (or its url if unnamed) with its error
+    #[error("failed to download {}", failed_pages_message(sources))]
+    PagesDownloadError {
+        sources: Vec<(String, DownloadError)>,
+    },
     #[error(transparent)]
     IoError(#[from] std::io::Error),
     #[error("invalid url: {0}")]
     InvalidUrl(String),
+    #[cfg(feature = "site-mangapark")]
     #[error(transparent)]
     MangaParkError(#[from] mangapark::MangaParkError),
+    #[cfg(feature = "site-mangadex")]
     #[error(transparent)]
     MangadexError(#[from] mangadex::MangadexError),
+    #[cfg(feature = "site-truyentranhtuan")]
     #[error(transparent)]
     TruyenTranhTuanError(#[from] truyentranhtuan::TruyenTranhTuanError),
+    #[cfg(feature = "site-toptruyen")]
     #[error(transparent)]
     TopTruyenError(#[from] toptruyen::TopTruyenError),
+    #[cfg(feature = "site-blogtruyen")]
     #[error(transparent)]
     BlogTruyenError(#[from] blogtruyen::BlogTruyenError),
+    #[cfg(feature = "site-nettruyen")]
     #[error(transparent)]
     NettruyenError(#[from] nettruyen::NettruyenError),
+    #[cfg(feature = "site-weebcentral")]
+    #[error(transparent)]
+    WeebCentralError(#[from] weebcentral::WeebCentralError),
+    #[cfg(feature = "site-batoto")]
+    #[error(transparent)]
+    BatoToError(#[from] batoto::BatoToError),
+    #[cfg(feature = "site-comick")]
+    #[error(transparent)]
+    ComickError(#[from] comick::ComickError),
+    #[cfg(feature = "site-asura")]
+    #[error(transparent)]
+    AsuraError(#[from] asura::AsuraError),
+    #[cfg(feature = "site-webtoon")]
+    #[error(transparent)]
+    WebtoonError(#[from] webtoon::WebtoonError),
+    #[cfg(feature = "site-mangakakalot")]
+    #[error(transparent)]
+    MangakakalotError(#[from] mangakakalot::MangakakalotError),
     #[error("site '{0}' is not supported")]
     SiteNotSupported(String),
+    #[error("chapter has no pages")]
+    NoPages,
+    /// More pages than `ChapterDownloadOptions::set_max_pages`, likely a broken scraper
+    #[error("chapter has {pages} pages, more than the limit of {max}, check that the site is scraped correctly")]
+    TooManyPages { pages: usize, max: usize },
+    #[error("page range {start}..{end} is not within the {pages} pages of the chapter")]
+    InvalidPageRange {
+        start: usize,
+        end: usize,
+        pages: usize,
+    },
+    #[error("chapter {0} does not exist or was removed")]
+    ChapterNotFound(String),
+    #[error("download cancelled")]
+    Cancelled,
+    #[error("cannot build epub: {0}")]
+    EpubError(String),
+    /// Error of a site registered outside of this library
+    #[error(transparent)]
+    SiteError(Box<dyn std::error::Error + Send + Sync>),
+}
+
+fn failed_pages_message(sources: &[(String, DownloadError)]) -> String {
+    sources
+        .iter()
+        .map(|(name, e)| format!("{name} ({e})"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl ChapterError {
+    /// Get the HTTP status code if the error is caused by an unsuccessful response from the site
+    pub fn status(&self) -> Option<u16> {
+        self.request_error()
+            .and_then(|e| e.status())
+            .map(|s| s.as_u16())
+    }
+
+    /// Whether the chapter may resolve if tried again: a timeout, a connection error or a server
+    /// error. Parse errors are not transient.
+    pub fn is_transient(&self) -> bool {
+        self.request_error().is_some_and(|e| {
+            e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error())
+        })
+    }
+
+    /// The request error of the site, if the error is one
+    fn request_error(&self) -> Option<&reqwest::Error> {
+        match self {
+            #[cfg(feature = "site-mangapark")]
+            ChapterError::MangaParkError(mangapark::MangaParkError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-mangadex")]
+            ChapterError::MangadexError(mangadex::MangadexError::ReqwestError(e)) => Some(e),
+            #[cfg(feature = "site-truyentranhtuan")]
+            ChapterError::TruyenTranhTuanError(
+                truyentranhtuan::TruyenTranhTuanError::RequestError(e),
+            ) => Some(e),
+            #[cfg(feature = "site-toptruyen")]
+            ChapterError::TopTruyenError(toptruyen::TopTruyenError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-blogtruyen")]
+            ChapterError::BlogTruyenError(blogtruyen::BlogTruyenError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-nettruyen")]
+            ChapterError::NettruyenError(nettruyen::NettruyenError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-weebcentral")]
+            ChapterError::WeebCentralError(weebcentral::WeebCentralError::RequestError(e)) => {
+                Some(e)
+            }
+            #[cfg(feature = "site-batoto")]
+            ChapterError::BatoToError(batoto::BatoToError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-comick")]
+            ChapterError::ComickError(comick::ComickError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-asura")]
+            ChapterError::AsuraError(asura::AsuraError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-webtoon")]
+            ChapterError::WebtoonError(webtoon::WebtoonError::RequestError(e)) => Some(e),
+            #[cfg(feature = "site-mangakakalot")]
+            ChapterError::MangakakalotError(mangakakalot::MangakakalotError::RequestError(e)) => {
+                Some(e)
+            }
+            ChapterError::SiteError(e) => e.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// How the page files of a chapter are named
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageNaming {
+    /// The names given by the site scraper, they differ between sites
+    #[default]
+    Site,
+    /// The last segment of the page url
+    OriginalName,
+    /// `page_000`, `page_001`... with the index padded to `width` digits
+    IndexZeroPadded { width: usize },
+}
+
+impl PageNaming {
+    fn rename(&self, items: &[DownloadItem]) -> Vec<DownloadItem> {
+        items
+            .iter()
+            .enumerate()
+            .map(|(i, item)| match self {
+                PageNaming::Site => item.clone(),
+                PageNaming::OriginalName => item.clone().with_name(None::<String>),
+                PageNaming::IndexZeroPadded { width } => {
+                    item.clone().with_name(Some(format!("page_{i:0width$}")))
+                }
+            })
+            .collect()
+    }
+
+    /// Name of the page at `index` once duplicates are removed, `None` keeps the name
+    fn deduped_name(&self, index: usize) -> Option<String> {
+        match self {
+            // the scrapers number the pages from page_001
+            PageNaming::Site => Some(format!("page_{:03}", index + 1)),
+            PageNaming::OriginalName => None,
+            PageNaming::IndexZeroPadded { width } => Some(format!("page_{index:0width$}")),
+        }
+    }
+}
+
+/// Options applied to a whole chapter download
+#[derive(Debug, Clone)]
+pub struct ChapterDownloadOptions {
+    dedupe: bool,
+    convert_webp: bool,
+    jpeg_quality: Option<u8>,
+    max_width: Option<u32>,
+    retries: u32,
+    retry_delay: Duration,
+    events: Option<UnboundedSender<DownloadEvent>>,
+    max_bytes_per_sec: Option<usize>,
+    keep_folder: bool,
+    group_by_manga: bool,
+    prefer_working_mirror: bool,
+    cookie: Option<String>,
+    allow_partial: bool,
+    report_mangadex: bool,
+    naming: PageNaming,
+    record_sources: bool,
+    max_pages: Option<usize>,
+    on_complete: Option<String>,
+    pages: Option<Range<usize>>,
+}
+
+/// Page limit of a chapter by default, see `ChapterDownloadOptions::set_max_pages`
+const DEFAULT_MAX_PAGES: usize = 1000;
+
+impl Default for ChapterDownloadOptions {
+    fn default() -> Self {
+        Self {
+            dedupe: false,
+            convert_webp: false,
+            jpeg_quality: None,
+            max_width: None,
+            retries: 1,
+            retry_delay: Duration::from_secs(5),
+            events: None,
+            max_bytes_per_sec: None,
+            keep_folder: false,
+            group_by_manga: false,
+            prefer_working_mirror: false,
+            cookie: None,
+            allow_partial: false,
+            report_mangadex: false,
+            naming: PageNaming::Site,
+            record_sources: false,
+            max_pages: Some(DEFAULT_MAX_PAGES),
+            on_complete: None,
+            pages: None,
+        }
+    }
+}
+
+impl ChapterDownloadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remove pages whose content is identical to an earlier page, then renumber the remaining ones
+    pub fn set_dedupe(&mut self, dedupe: bool) -> &mut Self {
+        self.dedupe = dedupe;
+        self
+    }
+
+    /// Transcode webp pages to JPEG (or PNG if they have transparency) for older readers.
+    /// Animated webp pages are kept.
+    pub fn set_convert_webp(&mut self, convert_webp: bool) -> &mut Self {
+        self.convert_webp = convert_webp;
+        self
+    }
+
+    /// Re-encode JPEG pages at this quality (1-100) to make them smaller
+    pub fn set_jpeg_quality(&mut self, quality: Option<u8>) -> &mut Self {
+        self.jpeg_quality = quality.map(|q| q.clamp(1, 100));
+        self
+    }
+
+    /// Downscale pages wider than this, keeping their aspect ratio. Images with transparency are kept.
+    pub fn set_max_width(&mut self, max_width: Option<u32>) -> &mut Self {
+        self.max_width = max_width;
+        self
+    }
+
+    /// Number of times failed pages are downloaded again, 1 by default
+    pub fn set_retries(&mut self, retries: u32) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Wait time before retrying failed pages, 5 seconds by default
+    pub fn set_retry_delay(&mut self, retry_delay: Duration) -> &mut Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Receive the byte progress of every page
+    pub fn set_event_sender(&mut self, sender: UnboundedSender<DownloadEvent>) -> &mut Self {
+        self.events = Some(sender);
+        self
+    }
+
+    /// Cap the bandwidth of the chapter download, in bytes per second
+    pub fn set_max_bytes_per_sec(&mut self, max_bytes_per_sec: Option<usize>) -> &mut Self {
+        self.max_bytes_per_sec = max_bytes_per_sec;
+        self
+    }
+
+    /// When building a cbz, keep the page images in a folder next to it, named as the cbz
+    /// without extension
+    pub fn set_keep_folder(&mut self, keep_folder: bool) -> &mut Self {
+        self.keep_folder = keep_folder;
+        self
+    }
+
+    /// Once a page is downloaded from an alternative url, try its mirror first for the next pages
+    pub fn set_prefer_working_mirror(&mut self, prefer_working_mirror: bool) -> &mut Self {
+        self.prefer_working_mirror = prefer_working_mirror;
+        self
+    }
+
+    /// Send a raw Cookie header with the page requests, see also `manget::set_cookie` for the
+    /// chapter page itself
+    pub fn set_cookie(&mut self, cookie: Option<String>) -> &mut Self {
+        self.cookie = cookie;
+        self
+    }
+
+    /// When no path is given, download into a folder of the manga, see `chapter_path`
+    pub fn set_group_by_manga(&mut self, group_by_manga: bool) -> &mut Self {
+        self.group_by_manga = group_by_manga;
+        self
+    }
+
+    /// Keep the successfully downloaded pages instead of failing when some pages still fail
+    /// after the retries, a warning lists the missing ones. The chapter fails if no page is left.
+    pub fn set_allow_partial(&mut self, allow_partial: bool) -> &mut Self {
+        self.allow_partial = allow_partial;
+        self
+    }
+
+    /// Report MangaDex@Home page requests to MangaDex, see `DownloadOptions::set_report_mangadex`.
+    /// Off by default to avoid requests the user doesn't expect.
+    pub fn set_report_mangadex(&mut self, report_mangadex: bool) -> &mut Self {
+        self.report_mangadex = report_mangadex;
+        self
+    }
+
+    /// Name the pages the same way whatever the site, the extension is inferred from each page
+    pub fn set_naming(&mut self, naming: PageNaming) -> &mut Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Write a `sources.txt` in the chapter folder mapping each page file to its url, ex: to
+    /// download a bad page again or to report a scraper bug. Mirrors are not listed.
+    pub fn set_record_sources(&mut self, record_sources: bool) -> &mut Self {
+        self.record_sources = record_sources;
+        self
+    }
+
+    /// Fail before downloading a chapter with more pages than `max_pages` (1000 by default), as
+    /// a broken selector matching unrelated images can give hundreds of bogus pages. `None`
+    /// removes the limit.
+    pub fn set_max_pages(&mut self, max_pages: Option<usize>) -> &mut Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    /// Command run after a chapter is downloaded as cbz, ex: `kepubify {path}`. It is split on
+    /// whitespaces then `{path}` and `{name}` are replaced by the cbz path and the chapter full
+    /// name, without going through a shell. A failure of the command is only logged.
+    pub fn set_on_complete(&mut self, on_complete: Option<String>) -> &mut Self {
+        self.on_complete = on_complete;
+        self
+    }
+
+    /// Only download the pages in `pages`, 0-based indexes of `Chapter::pages_download_info`,
+    /// ex: `2..4` for the 3rd and 4th pages, to preview a chapter or fetch some pages again.
+    /// The pages keep the names they have in the whole chapter.
+    pub fn set_pages(&mut self, pages: Option<Range<usize>>) -> &mut Self {
+        self.pages = pages;
+        self
+    }
 }
 
 pub async fn download_chapter<P: Into<PathBuf>>(
     chapter: &dyn Chapter,
     path: Option<P>,
 ) -> Result<PathBuf, ChapterError> {
-    // let chapter = chapter.as_ref();
-    let download_path = path
-        .map(|x| x.into())
-        .unwrap_or(Path::new(".").join(chapter.full_name()));
+    download_chapter_typed(chapter, path).await
+}
+
+/// Same as `download_chapter` for a chapter type known at compile time, ex: a
+/// `MangadexChapter`, without going through dynamic dispatch
+pub async fn download_chapter_typed<C: Chapter + ?Sized, P: Into<PathBuf>>(
+    chapter: &C,
+    path: Option<P>,
+) -> Result<PathBuf, ChapterError> {
+    download_chapter_with_options(chapter, path, &ChapterDownloadOptions::default()).await
+}
+
+pub async fn download_chapter_with_options<C: Chapter + ?Sized, P: Into<PathBuf>>(
+    chapter: &C,
+    path: Option<P>,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<PathBuf, ChapterError> {
+    let download_path = path.map(|x| x.into()).unwrap_or(chapter_path(
+        chapter,
+        Path::new("."),
+        chapter_options.group_by_manga,
+    ));
+    download_chapter_pages(chapter, Some(&download_path), chapter_options).await?;
+    Ok(download_path)
+}
+
+/// Same as `download_chapter_with_options`, but returns the paths of the downloaded pages in
+/// order, with their final names after extension inference, conversion and dedupe.
+#[instrument(
+    name = "chapter",
+    skip_all,
+    fields(chapter = %chapter.full_name(), url = %chapter.url(), source = chapter.source())
+)]
+pub async fn download_chapter_pages<C: Chapter + ?Sized, P: Into<PathBuf>>(
+    chapter: &C,
+    path: Option<P>,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<Vec<PathBuf>, ChapterError> {
+    // an empty folder or archive would look like a successful download
+    if chapter.pages_download_info().is_empty() {
+        return Err(ChapterError::NoPages);
+    }
+    let page_count = chapter.pages_download_info().len();
+    if let Some(max) = chapter_options.max_pages.filter(|&max| page_count > max) {
+        return Err(ChapterError::TooManyPages {
+            pages: page_count,
+            max,
+        });
+    }
+    let range = chapter_options.pages.clone().unwrap_or(0..page_count);
+    if range.is_empty() || range.end > page_count {
+        return Err(ChapterError::InvalidPageRange {
+            start: range.start,
+            end: range.end,
+            pages: page_count,
+        });
+    }
+    let start = Instant::now();
+    let download_path = path.map(|x| x.into()).unwrap_or(chapter_path(
+        chapter,
+        Path::new("."),
+        chapter_options.group_by_manga,
+    ));
+    let mut options = page_download_options(chapter, &download_path, chapter_options)?;
+
+    // renamed before the range is taken, so pages keep their index in the chapter
+    let items =
+        chapter_options.naming.rename(chapter.pages_download_info())[range.clone()].to_vec();
+    let mut pages = vec![None; items.len()];
+    let mut pending = Vec::new();
+    let mut failed_sources = Vec::new();
+
+    // resume an interrupted download
+    let mut manifest = ProgressManifest::load(&download_path);
+    for (i, item) in items.iter().enumerate() {
+        match manifest.completed_page(&download_path, &page_name(item)) {
+            Some(page) => pages[i] = Some(page),
+            None => pending.push(i),
+        }
+    }
+    if pending.len() < items.len() {
+        info!(
+            "Resuming download, {} of {} pages are already downloaded",
+            items.len() - pending.len(),
+            items.len()
+        );
+    }
+
+    let mut retries = 0;
+    for attempt in 0..=chapter_options.retries {
+        if pending.is_empty() {
+            break;
+        }
+        if attempt > 0 {
+            retries += pending.len();
+            warn!(
+                "{} pages failed, retry in {:?}",
+                pending.len(),
+                chapter_options.retry_delay
+            );
+            tokio::time::sleep(chapter_options.retry_delay).await;
+        }
+        options.clear_download_items();
+        options.add_download_items(pending.iter().map(|&i| &items[i]));
+        failed_sources.clear();
+        let mut still_pending = Vec::new();
+        let mut results = download_stream(&options);
+        while let Some((j, result)) = results.next().await {
+            let i = pending[j];
+            match result {
+                Ok(page) => {
+                    manifest.record(&page_name(&items[i]), &page);
+                    manifest.save(&download_path)?;
+                    pages[i] = Some(page);
+                }
+                Err(e) => {
+                    still_pending.push(i);
+                    failed_sources.push((i, page_name(&items[i]), e));
+                }
+            }
+        }
+        still_pending.sort();
+        pending = still_pending;
+    }
+    failed_sources.sort_by_key(|(i, _, _)| *i);
+    let partial = chapter_options.allow_partial
+        && !failed_sources.is_empty()
+        && failed_sources.len() < items.len();
+    if partial {
+        let missing: Vec<_> = failed_sources
+            .iter()
+            .map(|(i, _, _)| (range.start + i + 1).to_string())
+            .collect();
+        warn!(
+            "Keeping a partial chapter, missing pages: {}",
+            missing.join(", ")
+        );
+    }
+    let failed_sources: Vec<_> = failed_sources
+        .into_iter()
+        .map(|(_, name, e)| (name, e))
+        .collect();
+    let complete = failed_sources.is_empty() || partial;
+    if complete {
+        ProgressManifest::remove(&download_path);
+    }
+
+    // the primary url of each downloaded page, for `record_sources`
+    let (mut pages, mut page_urls): (Vec<_>, Vec<_>) = pages
+        .into_iter()
+        .zip(&items)
+        .filter_map(|(page, item)| Some((page?, item.url())))
+        .unzip();
+    if let Some(sender) = &chapter_options.events {
+        let stats = DownloadStats {
+            pages: pages.len(),
+            bytes: pages
+                .iter()
+                .filter_map(|page| fs::metadata(page).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            duration: start.elapsed(),
+            retries,
+            failures: failed_sources.len(),
+        };
+        let _ = sender.send(DownloadEvent::ChapterFinished { stats });
+    }
+    // the pages are left as downloaded for a resume, ex: a converted webp page would no longer
+    // match the manifest and be fetched again
+    if !complete {
+        return Err(ChapterError::PagesDownloadError {
+            sources: failed_sources,
+        });
+    }
+    if chapter_options.convert_webp {
+        pages = pages
+            .iter()
+            .map(|page| convert_webp(page))
+            .collect::<std::io::Result<_>>()?;
+    }
+    if chapter_options.jpeg_quality.is_some() || chapter_options.max_width.is_some() {
+        for page in &pages {
+            recompress(
+                page,
+                chapter_options.jpeg_quality,
+                chapter_options.max_width,
+            )?;
+        }
+    }
+    if chapter_options.dedupe {
+        (page_urls, pages) = dedupe_pages(&pages, &chapter_options.naming)?
+            .into_iter()
+            .map(|(i, page)| (page_urls[i], page))
+            .unzip();
+    }
+    if chapter_options.record_sources {
+        write_sources(&download_path, &pages, &page_urls)?;
+    }
+    Ok(pages)
+}
+
+/// The options to download the pages of `chapter` into `download_path`
+fn page_download_options<C: Chapter + ?Sized>(
+    chapter: &C,
+    download_path: &Path,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<DownloadOptions, ChapterError> {
     let mut options = DownloadOptions::new()
-        .set_path(&download_path)
+        .set_path(download_path)
         .map_err(|e| ChapterError::PathError {
             path: download_path.to_path_buf(),
             source: e,
         })?;
 
-    options.add_download_items(chapter.pages_download_info());
-    if let Some(r) = chapter.referer() {
+    if let Some(r) = chapter_referer(chapter) {
         options.set_referer(&r);
     }
+    if let Some(sender) = &chapter_options.events {
+        options.set_event_sender(sender.clone());
+    }
+    // other sites may serve pages under the same paths as MangaDex@Home nodes
+    options
+        .set_max_bytes_per_sec(chapter_options.max_bytes_per_sec)
+        .set_prefer_working_mirror(chapter_options.prefer_working_mirror)
+        .set_cookie(chapter_options.cookie.clone())
+        .set_report_mangadex(chapter_options.report_mangadex && chapter.source() == "mangadex");
+    Ok(options)
+}
 
-    let mut failed_sources = Vec::new();
-
-    for result in download(&options).await {
-        if let Err(e) = result {
-            failed_sources.push(e);
+/// Same as `download_chapter`, but stops as soon as `token` is cancelled. In-flight page requests
+/// are dropped and the chapter folder is removed if it was created by this download.
+pub async fn download_chapter_cancellable<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    path: Option<P>,
+    token: CancellationToken,
+) -> Result<PathBuf, ChapterError> {
+    let download_path = path
+        .map(|x| x.into())
+        .unwrap_or(Path::new(".").join(chapter.full_name()));
+    let existed = download_path.exists();
+    tokio::select! {
+        result = download_chapter(chapter, Some(&download_path)) => result,
+        _ = token.cancelled() => {
+            info!("Cancelled download of {}", chapter.full_name());
+            if !existed {
+                let _ = fs::remove_dir_all(&download_path);
+            }
+            Err(ChapterError::Cancelled)
         }
     }
+}
 
-    if failed_sources.is_empty() {
-        Ok(download_path)
-    } else {
-        Err(ChapterError::PagesDownloadError {
-            sources: failed_sources,
+/// Prefix of the scratch folder created next to a cbz while its pages are downloaded
+const TEMP_DIR_PREFIX: &str = ".manget-";
+
+/// Download the pages of a chapter directly into `out_dir`, without a chapter folder, ex: to
+/// feed another tool. Pages are named "{chapter full name} - {page}" so several chapters can
+/// share `out_dir`. Returns the paths of the pages in order.
+pub async fn download_chapter_flattened<C: Chapter + ?Sized, P: Into<PathBuf>>(
+    chapter: &C,
+    out_dir: P,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<Vec<PathBuf>, ChapterError> {
+    let out_dir = out_dir.into();
+    fs::create_dir_all(&out_dir)?;
+    // the manifest and sources of the chapter stay in the scratch folder
+    let tempdir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir_in(&out_dir)?;
+    let pages = download_chapter_pages(chapter, Some(tempdir.path()), chapter_options).await?;
+    let name = chapter.full_name();
+    pages
+        .iter()
+        .map(|page| {
+            let file_name = page.file_name().unwrap_or_default().to_string_lossy();
+            let target = out_dir.join(format!("{name} - {file_name}"));
+            fs::rename(page, &target)?;
+            Ok(target)
         })
-    }
+        .collect()
 }
 
 pub async fn download_chapter_as_cbz<P: Into<PathBuf>>(
     chapter: &dyn Chapter,
     zip_path: Option<P>,
 ) -> Result<PathBuf, ChapterError> {
-    let tempdir = tempfile::tempdir()?;
-    let outdir = download_chapter(chapter, Some(tempdir.into_path())).await?;
+    download_chapter_as_cbz_with_options(chapter, zip_path, &ChapterDownloadOptions::default())
+        .await
+}
+
+pub async fn download_chapter_as_cbz_with_options<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    zip_path: Option<P>,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<PathBuf, ChapterError> {
     let zip_path = zip_path.map(|p| p.into()).unwrap_or(
+        chapter_path(chapter, Path::new("."), chapter_options.group_by_manga).with_extension("cbz"),
+    );
+    let parent = match zip_path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(parent)?;
+    // the guard removes the pages on every return path, errors included
+    let tempdir = tempfile::Builder::new()
+        .prefix(TEMP_DIR_PREFIX)
+        .tempdir_in(parent)?;
+    let outdir = if chapter_options.keep_folder {
+        zip_path.with_extension("")
+    } else {
+        tempdir.path().to_path_buf()
+    };
+    let outdir = download_chapter_with_options(chapter, Some(outdir), chapter_options).await?;
+    info!("Compressing to {}", zip_path.display());
+    zip_folder(&outdir, &zip_path)?;
+    if !chapter_options.keep_folder {
+        let _ = fs::remove_dir_all(outdir);
+    }
+    info!("Done.");
+    if let Some(on_complete) = &chapter_options.on_complete {
+        run_on_complete(on_complete, &zip_path, &chapter.full_name()).await;
+    }
+    Ok(zip_path)
+}
+
+/// Run the `on_complete` command of a downloaded chapter, logging its output
+async fn run_on_complete(template: &str, path: &Path, name: &str) {
+    let path = path.to_string_lossy();
+    let mut args = template
+        .split_whitespace()
+        .map(|arg| arg.replace("{path}", &path).replace("{name}", name));
+    let Some(program) = args.next() else {
+        return;
+    };
+    match tokio::process::Command::new(&program)
+        .args(args)
+        .output()
+        .await
+    {
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if output.status.success() {
+                info!("{program} done: {}{}", stdout.trim(), stderr.trim());
+            } else {
+                warn!(
+                    "{program} failed with {}: {}{}",
+                    output.status,
+                    stdout.trim(),
+                    stderr.trim()
+                );
+            }
+        }
+        Err(e) => warn!("Cannot run {program}: {e}"),
+    }
+}
+
+/// Download a chapter as a fixed-layout epub, one page image per spine item. The first page is
+/// also used as the cover. Webp pages are converted since most e-readers do not support them.
+pub async fn download_chapter_as_epub<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    epub_path: Option<P>,
+) -> Result<PathBuf, ChapterError> {
+    let tempdir = tempfile::tempdir()?;
+    let mut options = ChapterDownloadOptions::default();
+    options.set_convert_webp(true);
+    let pages = download_chapter_pages(chapter, Some(tempdir.path()), &options).await?;
+    let epub_path = epub_path.map(|p| p.into()).unwrap_or(
         PathBuf::from(".")
             .join(chapter.full_name())
-            .with_extension("cbz"),
+            .with_extension("epub"),
     );
-    if let Some(p) = zip_path.parent() {
+    if let Some(p) = epub_path.parent() {
         fs::create_dir_all(p)?;
     }
-    info!("Compressing to {}", zip_path.display());
-    zip_folder(&outdir, &zip_path)?;
-    let _ = fs::remove_dir_all(outdir);
+    info!("Building {}", epub_path.display());
+    let epub = build_image_epub(&chapter.full_name(), &pages)
+        .map_err(|e| ChapterError::EpubError(e.to_string()))?;
+    fs::write(&epub_path, epub)?;
     info!("Done.");
-    Ok(zip_path)
+    Ok(epub_path)
 }
 
-pub async fn get_chapter(
-    url: impl IntoUrl + Display + Clone,
-) -> Result<Box<dyn Chapter>, ChapterError> {
-    let url = url
-        .clone()
-        .into_url()
-        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
-    match url.domain() {
-        Some("mangapark.net") => Ok(Box::new(mangapark::MangaParkChapter::from_url(url).await?)),
-        Some("mangadex.org") => Ok(Box::new(mangadex::MangadexChapter::from_url(url).await?)),
-        Some("truyenqq.com.vn") => Ok(Box::new(nettruyen::NettruyenChapter::from_url(url).await?)),
-        Some("truyenqqne.com") => Ok(Box::new(nettruyen::NettruyenChapter::from_url(url).await?)),
-        Some(x) if x.contains("blogtruyen") => Ok(Box::new(
-            blogtruyen::BlogTruyenChapter::from_url(url).await?,
-        )),
-        Some("www.toptruyen.live") => {
-            Ok(Box::new(toptruyen::TopTruyenChapter::from_url(url).await?))
+fn build_image_epub(title: &str, pages: &[PathBuf]) -> epub_builder::Result<Vec<u8>> {
+    let mut builder = epub_builder::EpubBuilder::new(epub_builder::ZipLibrary::new()?)?;
+    builder
+        .metadata("title", title)?
+        .epub_version(epub_builder::EpubVersion::V30);
+    for (i, page) in pages.iter().enumerate() {
+        let ext = page
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let mime_type = match ext.as_str() {
+            "png" => "image/png",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => "image/jpeg",
+        };
+        let image = format!("Images/page_{:03}.{ext}", i + 1);
+        let data = fs::read(page)?;
+        if i == 0 {
+            builder.add_cover_image(&image, data.as_slice(), mime_type)?;
+        } else {
+            builder.add_resource(&image, data.as_slice(), mime_type)?;
         }
-        Some("truyentuan.com") => Ok(Box::new(
-            truyentranhtuan::TruyenTranhTuanChapter::from_url(url).await?,
-        )),
-        Some(x) if x.contains("nettruyen") => {
-            Ok(Box::new(nettruyen::NettruyenChapter::from_url(url).await?))
+        // the viewport makes each page fixed-layout, sized as its image
+        let viewport = image::image_dimensions(page)
+            .map(|(w, h)| format!(r#"<meta name="viewport" content="width={w}, height={h}"/>"#))
+            .unwrap_or_default();
+        let xhtml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head>
+  <title>{}</title>
+  {viewport}
+  <style>body {{ margin: 0; }} img {{ width: 100%; height: 100%; object-fit: contain; }}</style>
+</head>
+<body><img src="{image}" alt=""/></body>
+</html>
+"#,
+            i + 1
+        );
+        let mut content =
+            epub_builder::EpubContent::new(format!("page_{:03}.xhtml", i + 1), xhtml.as_bytes());
+        if i == 0 {
+            content = content
+                .title(title)
+                .reftype(epub_builder::ReferenceType::Text);
         }
-        Some(x) => Err(ChapterError::SiteNotSupported(x.to_string())),
-        None => Err(ChapterError::InvalidUrl(url.to_string())),
+        builder.add_content(content)?;
     }
+    let mut output = Vec::new();
+    builder.generate(&mut output)?;
+    Ok(output)
 }
 
-fn zip_folder<P: Into<PathBuf>>(
-    folder_path: P,
-    zip_path: P,
-) -> std::result::Result<(), std::io::Error> {
-    let folder_path = folder_path.into();
-    let output_path = zip_path.into();
-    let file: fs::File = fs::File::create(&output_path)?;
-    let writer = std::io::BufWriter::new(file);
-    let mut zip = ZipWriter::new(writer);
+/// Write an aria2c `--input-file` listing every page, so the chapter can be downloaded with
+/// `aria2c -i <path>`. Alternative urls of a page are added as mirrors of the same file.
+pub fn write_aria2_input<P: Into<PathBuf>>(
+    chapter: &dyn Chapter,
+    path: P,
+) -> Result<PathBuf, ChapterError> {
+    let path = path.into();
+    if let Some(p) = path.parent() {
+        fs::create_dir_all(p)?;
+    }
+    let referer = chapter_referer(chapter);
+    let mut content = String::new();
+    for (i, item) in chapter.pages_download_info().iter().enumerate() {
+        let urls: Vec<&str> = std::iter::once(item.url())
+            .chain(item.alt_urls().iter().map(String::as_str))
+            .collect();
+        content.push_str(&urls.join("\t"));
+        content.push('\n');
+        content.push_str(&format!("  out={}\n", aria2_out_name(item, i)));
+        if let Some(r) = &referer {
+            content.push_str(&format!("  referer={r}\n"));
+        }
+    }
+    fs::write(&path, content)?;
+    Ok(path)
+}
 
-    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+/// File name of a page in an aria2c input: its name, or its index name if it has none. aria2c
+/// cannot tell the extension from the response, so the one forced by the item or else the one of
+/// the url is added.
+fn aria2_out_name(item: &DownloadItem, index: usize) -> String {
+    let name = PathBuf::from(
+        item.name()
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("page_{:03}", index + 1)),
+    );
+    let url_extension = || {
+        let url = Url::parse(item.url()).ok()?;
+        let extension = Path::new(url.path()).extension()?;
+        Some(extension.to_string_lossy().to_string())
+    };
+    let name = match item.extension() {
+        Some(forced) => name.with_extension(forced),
+        None if name.extension().is_none() => match url_extension() {
+            Some(extension) => name.with_extension(extension),
+            None => name,
+        },
+        None => name,
+    };
+    name.to_string_lossy().to_string()
+}
+
+fn page_name(item: &DownloadItem) -> String {
+    item.name().unwrap_or(item.url()).to_string()
+}
+
+/// The referer derived from a chapter url: its scheme and host, ex: "https://weebcentral.com/"
+pub fn default_referer(url: &Url) -> Option<String> {
+    match url.scheme() {
+        "http" | "https" => Some(format!("{}://{}/", url.scheme(), url.host_str()?)),
+        _ => None,
+    }
+}
+
+/// Explicit referer of the chapter, or the default one of its url
+fn chapter_referer<C: Chapter + ?Sized>(chapter: &C) -> Option<String> {
+    chapter.referer().or_else(|| {
+        Url::parse(&chapter.url())
+            .ok()
+            .and_then(|url| default_referer(&url))
+    })
+}
 
-    let files = fs::read_dir(&folder_path)?;
-    for file in files {
-        let file = file?;
-        let path = file.path();
+/// Download several chapters into one cbz, each chapter in its own directory, with a
+/// `ComicInfo.xml` describing the whole volume. Chapters are downloaded one after another.
+pub async fn download_chapters_as_volume(
+    chapters: &[Box<dyn Chapter>],
+    out: &Path,
+) -> Result<PathBuf, ChapterError> {
+    write_volume(chapters, out, false)
+        .await
+        .map(|(path, _)| path)
+}
+
+/// Like [download_chapters_as_volume], but the chapters failing to download, ex: because of a
+/// missing page, are left out of the volume. Returns the index of each chapter left out with its
+/// error. The volume fails only if no chapter is left.
+pub async fn download_available_chapters_as_volume(
+    chapters: &[Box<dyn Chapter>],
+    out: &Path,
+) -> Result<(PathBuf, Vec<(usize, ChapterError)>), ChapterError> {
+    write_volume(chapters, out, true).await
+}
 
-        if path.is_file() {
-            let relative_path = path.strip_prefix(&folder_path).unwrap();
-            zip.start_file(relative_path.to_str().unwrap(), options)?;
-            let mut source_file = fs::File::open(path)?;
-            std::io::copy(&mut source_file, &mut zip)?;
+async fn write_volume(
+    chapters: &[Box<dyn Chapter>],
+    out: &Path,
+    skip_failed: bool,
+) -> Result<(PathBuf, Vec<(usize, ChapterError)>), ChapterError> {
+    let tempdir = tempfile::tempdir()?;
+    let mut folders = Vec::new();
+    let mut downloaded = Vec::new();
+    let mut failed = Vec::new();
+    let mut page_count = 0;
+    for (i, chapter) in chapters.iter().enumerate() {
+        let folder = tempdir.path().join(chapter.full_name());
+        match download_chapter_pages(
+            chapter.as_ref(),
+            Some(&folder),
+            &ChapterDownloadOptions::default(),
+        )
+        .await
+        {
+            Ok(pages) => {
+                page_count += pages.len();
+                folders.push(folder);
+                downloaded.push(chapter.as_ref());
+            }
+            Err(e) if skip_failed => {
+                warn!("Leaving {} out of the volume: {e}", chapter.full_name());
+                let _ = fs::remove_dir_all(&folder);
+                failed.push((i, e));
+            }
+            Err(e) => return Err(e),
         }
     }
+    if downloaded.is_empty() && !failed.is_empty() {
+        return Err(failed.swap_remove(0).1);
+    }
+
+    if let Some(p) = out.parent() {
+        fs::create_dir_all(p)?;
+    }
+    info!("Compressing to {}", out.display());
+    write_volume_cbz(out, &volume_comic_info(&downloaded, page_count), &folders)?;
+    info!("Done.");
+    Ok((out.to_path_buf(), failed))
+}
 
+fn write_volume_cbz(out: &Path, comic_info: &str, folders: &[PathBuf]) -> std::io::Result<()> {
+    let mut zip = ZipWriter::new(std::io::BufWriter::new(fs::File::create(out)?));
+    zip.start_file("ComicInfo.xml", FileOptions::default())?;
+    zip.write_all(comic_info.as_bytes())?;
+    add_folders_to_zip(&mut zip, folders)?;
     zip.finish()?;
     Ok(())
 }
+
+/// Write the `ComicInfo.xml` of `chapter` into its downloaded `folder`, counting the files
+/// already there as its pages
+pub fn write_chapter_comic_info(chapter: &dyn Chapter, folder: &Path) -> std::io::Result<()> {
+    let page_count = folder_files(folder)?.len();
+    fs::write(
+        folder.join("ComicInfo.xml"),
+        volume_comic_info(&[chapter], page_count),
+    )
+}
+
+/// ComicInfo.xml of a volume: the series is the manga of the first chapter and the title spans
+/// from the first to the last chapter
+fn volume_comic_info(chapters: &[&dyn Chapter], page_count: usize) -> String {
+    let escape = |s: String| html_escape::encode_text(&s).to_string();
+    let series = chapters.first().map(|c| c.manga()).unwrap_or_default();
+    let title = match (chapters.first(), chapters.last()) {
+        (Some(first), Some(last)) if chapters.len() > 1 => {
+            format!("{} - {}", first.chapter(), last.chapter())
+        }
+        (Some(first), _) => first.chapter(),
+        _ => String::new(),
+    };
+    let mut extra = String::new();
+    if let Some(summary) = chapters.first().and_then(|c| c.summary()) {
+        extra.push_str(&format!("  <Summary>{}</Summary>\n", escape(summary)));
+    }
+    let mut translators: Vec<String> = Vec::new();
+    for translator in chapters.iter().flat_map(|c| c.translators()) {
+        if !translators.contains(&translator) {
+            translators.push(translator);
+        }
+    }
+    if !translators.is_empty() {
+        extra.push_str(&format!(
+            "  <Translator>{}</Translator>\n",
+            escape(translators.join(", "))
+        ));
+    }
+    let genres = chapters.first().map(|c| c.genres()).unwrap_or_default();
+    if !genres.is_empty() {
+        extra.push_str(&format!("  <Genre>{}</Genre>\n", escape(genres.join(", "))));
+    }
+    if let Some(first) = chapters.first() {
+        extra.push_str(&format!("  <Web>{}</Web>\n", escape(first.url())));
+        extra.push_str(&format!(
+            "  <Notes>Source: {}</Notes>\n",
+            escape(first.source().to_string())
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <ComicInfo xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\">\n  \
+         <Title>{}</Title>\n  <Series>{}</Series>\n{}  <PageCount>{}</PageCount>\n\
+         </ComicInfo>\n",
+        escape(title),
+        escape(series),
+        extra,
+        page_count
+    )
+}
+
+/// Pages already downloaded into a chapter folder, kept until the whole chapter is downloaded
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ProgressManifest {
+    /// Page name (before extension inference) -> downloaded file name
+    pages: HashMap<String, String>,
+}
+
+impl ProgressManifest {
+    const FILE_NAME: &'static str = ".manget-progress.json";
+
+    fn load(folder: &Path) -> Self {
+        fs::read_to_string(folder.join(Self::FILE_NAME))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, folder: &Path) -> std::io::Result<()> {
+        fs::write(folder.join(Self::FILE_NAME), serde_json::to_string(self)?)
+    }
+
+    fn remove(folder: &Path) {
+        let _ = fs::remove_file(folder.join(Self::FILE_NAME));
+    }
+
+    fn record(&mut self, name: &str, page: &Path) {
+        if let Some(file_name) = page.file_name() {
+            self.pages
+                .insert(name.to_string(), file_name.to_string_lossy().to_string());
+        }
+    }
+
+    fn completed_page(&self, folder: &Path, name: &str) -> Option<PathBuf> {
+        let page = folder.join(self.pages.get(name)?);
+        page.is_file().then_some(page)
+    }
+}
+
+/// Download only the first page of a chapter into the folder `path`, e.g. as a preview.
+/// Returns the path of the image.
+pub async fn download_thumbnail<P: AsRef<Path>>(
+    chapter: &dyn Chapter,
+    path: P,
+) -> Result<PathBuf, ChapterError> {
+    let path = path.as_ref();
+    let first_page = chapter
+        .pages_download_info()
+        .first()
+        .ok_or(ChapterError::NoPages)?;
+    let mut options =
+        DownloadOptions::new()
+            .set_path(path)
+            .map_err(|e| ChapterError::PathError {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+    options.add_download_item(first_page);
+    if let Some(r) = chapter_referer(chapter) {
+        options.set_referer(&r);
+    }
+    match download(&options).await.pop() {
+        Some(Ok(page)) => Ok(page),
+        Some(Err(e)) => Err(ChapterError::PagesDownloadError {
+            sources: vec![(page_name(first_page), e)],
+        }),
+        None => Err(ChapterError::NoPages),
+    }
+}
+
+/// Name of the file listing the source url of each page, see `set_record_sources`
+const SOURCES_FILE: &str = "sources.txt";
+
+/// Write "{file name}\t{url}" lines to the sources file of `folder`
+fn write_sources(folder: &Path, pages: &[PathBuf], urls: &[&str]) -> std::io::Result<()> {
+    let mut content = String::new();
+    for (page, url) in pages.iter().zip(urls) {
+        let name = page.file_name().unwrap_or_default().to_string_lossy();
+        content.push_str(&format!("{name}\t{url}\n"));
+    }
+    fs::write(folder.join(SOURCES_FILE), content)
+}
+
+/// Remove files identical to an earlier one and number the rest again as `naming` does, keeping
+/// their order. Returns the index in `pages` and the new path of the remaining pages.
+fn dedupe_pages(pages: &[PathBuf], naming: &PageNaming) -> std::io::Result<Vec<(usize, PathBuf)>> {
+    let mut seen = HashSet::new();
+    let mut kept = Vec::new();
+    for (index, page) in pages.iter().enumerate() {
+        if seen.insert(blake3::hash(&fs::read(page)?)) {
+            kept.push((index, page));
+        } else {
+            info!("Removing duplicated page {}", page.display());
+            fs::remove_file(page)?;
+        }
+    }
+    if kept.len() == pages.len() {
+        return Ok(pages.iter().cloned().enumerate().collect());
+    }
+    if naming.deduped_name(0).is_none() {
+        return Ok(kept
+            .into_iter()
+            .map(|(index, page)| (index, page.clone()))
+            .collect());
+    }
+
+    // rename in two passes so a new name never overwrites a page not renamed yet
+    let mut temp_paths = Vec::new();
+    for (i, (index, page)) in kept.iter().enumerate() {
+        let temp_path = page.with_file_name(format!(".dedupe_{i}"));
+        fs::rename(page, &temp_path)?;
+        temp_paths.push((*index, temp_path, page.extension().map(|e| e.to_owned())));
+    }
+    let mut renamed = Vec::new();
+    for (i, (index, temp_path, ext)) in temp_paths.into_iter().enumerate() {
+        let name = naming.deduped_name(i).expect("checked before renaming");
+        let mut new_path = temp_path.with_file_name(name);
+        if let Some(ext) = ext {
+            new_path.set_extension(ext);
+        }
+        fs::rename(temp_path, &new_path)?;
+        renamed.push((index, new_path));
+    }
+    Ok(renamed)
+}
+
+/// Download a chapter and build the cbz in memory, so the archive is never written to disk.
+pub async fn download_chapter_to_memory(chapter: &dyn Chapter) -> Result<Vec<u8>, ChapterError> {
+    download_chapter_to_memory_with_options(chapter, &ChapterDownloadOptions::default()).await
+}
+
+/// Like [download_chapter_to_memory] with custom download options.
+pub async fn download_chapter_to_memory_with_options(
+    chapter: &dyn Chapter,
+    chapter_options: &ChapterDownloadOptions,
+) -> Result<Vec<u8>, ChapterError> {
+    let tempdir = tempfile::tempdir()?;
+    let outdir =
+        download_chapter_with_options(chapter, Some(tempdir.path()), chapter_options).await?;
+    let mut data = Cursor::new(Vec::new());
+    zip_folder_to_writer(&outdir, &mut data)?;
+    Ok(data.into_inner())
+}
+
+/// Number of times a chapter resolution failing with a transient error is retried
+const RESOLUTION_RETRIES: u32 = 2;
+/// Delay before the first retry of a chapter resolution, the next ones wait longer
+const RESOLUTION_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// The future returned by a site constructor registered in a `SiteDispatcher`
+pub type ChapterFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn Chapter>, ChapterError>> + Send>>;
+type SiteMatcher = Box<dyn Fn(&Url) -> bool + Send + Sync>;
+type SiteConstructor = Arc<dyn Fn(Url) -> ChapterFuture + Send + Sync>;
+/// The future returned by a chapter lister registered in a `SiteDispatcher`, with the chapter
+/// urls of a series or list
+pub type ChapterListFuture =
+    Pin<Box<dyn Future<Output = Result<Vec<String>, ChapterError>> + Send>>;
+type SiteLister = Arc<dyn Fn(Url) -> ChapterListFuture + Send + Sync>;
+
+/// Selectors of a site scraper checked by `diagnose`, with what they find
+#[cfg(feature = "html")]
+type DiagnosisSelectors = &'static [(&'static str, &'static str)];
+
+struct SiteEntry {
+    name: String,
+    matcher: SiteMatcher,
+    constructor: SiteConstructor,
+    /// Lists the chapters of the series or list urls, for the sites supporting it
+    lister: Option<SiteLister>,
+    /// Empty for sites using an api
+    #[cfg(feature = "html")]
+    diagnosis_selectors: DiagnosisSelectors,
+}
+
+/// A registry of sites. A url is resolved by the first registered site whose matcher accepts it.
+#[derive(Default)]
+pub struct SiteDispatcher {
+    sites: Vec<SiteEntry>,
+}
+
+impl SiteDispatcher {
+    /// Create a dispatcher without any site
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a dispatcher with all sites supported by this library, as enabled by the
+    /// `site-*` cargo features
+    pub fn with_builtin_sites() -> Self {
+        #[allow(unused_mut)]
+        let mut dispatcher = Self::new();
+        #[cfg(feature = "site-mangapark")]
+        dispatcher
+            .register_domain("mangapark.net", |url| {
+                Box::pin(async move {
+                    Ok(Box::new(mangapark::MangaParkChapter::from_url(url).await?)
+                        as Box<dyn Chapter>)
+                })
+            })
+            .set_diagnosis_selectors(mangapark::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-mangadex")]
+        dispatcher
+            .register_domain("mangadex.org", |url| {
+                Box::pin(async move {
+                    Ok(Box::new(mangadex::MangadexChapter::from_url(url).await?)
+                        as Box<dyn Chapter>)
+                })
+            })
+            .set_chapter_lister(|url| {
+                Box::pin(async move { Ok(mangadex::get_list_chapters(url).await?) })
+            });
+        #[cfg(feature = "site-nettruyen")]
+        dispatcher
+            .register_domain("truyenqq.com.vn", nettruyen_chapter)
+            .set_diagnosis_selectors(nettruyen::DIAGNOSIS_SELECTORS)
+            .register_domain("truyenqqne.com", nettruyen_chapter)
+            .set_diagnosis_selectors(nettruyen::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-blogtruyen")]
+        dispatcher
+            .register(
+                "blogtruyen",
+                |url| domain_contains(url, "blogtruyen"),
+                |url| {
+                    Box::pin(async move {
+                        Ok(
+                            Box::new(blogtruyen::BlogTruyenChapter::from_url(url).await?)
+                                as Box<dyn Chapter>,
+                        )
+                    })
+                },
+            )
+            .set_diagnosis_selectors(blogtruyen::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-toptruyen")]
+        dispatcher
+            .register_domain("toptruyen.live", |url| {
+                Box::pin(async move {
+                    Ok(Box::new(toptruyen::TopTruyenChapter::from_url(url).await?)
+                        as Box<dyn Chapter>)
+                })
+            })
+            .set_diagnosis_selectors(toptruyen::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-truyentranhtuan")]
+        dispatcher
+            .register_domain("truyentuan.com", truyentranhtuan_chapter)
+            .set_diagnosis_selectors(truyentranhtuan::DIAGNOSIS_SELECTORS)
+            .register_domain("truyentranhtuan.com", truyentranhtuan_chapter)
+            .set_diagnosis_selectors(truyentranhtuan::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-nettruyen")]
+        dispatcher
+            .register(
+                "nettruyen",
+                |url| domain_contains(url, "nettruyen"),
+                nettruyen_chapter,
+            )
+            .set_diagnosis_selectors(nettruyen::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-weebcentral")]
+        dispatcher
+            .register_domain("weebcentral.com", |url| {
+                Box::pin(async move {
+                    Ok(
+                        Box::new(weebcentral::WeebCentralChapter::from_url(url).await?)
+                            as Box<dyn Chapter>,
+                    )
+                })
+            })
+            .set_diagnosis_selectors(weebcentral::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-batoto")]
+        for domain in ["bato.to", "bato.org", "bato.si"] {
+            dispatcher
+                .register_domain(domain, batoto_chapter)
+                .set_diagnosis_selectors(batoto::DIAGNOSIS_SELECTORS);
+        }
+        #[cfg(feature = "site-comick")]
+        dispatcher.register_domain("comick.io", |url| {
+            Box::pin(async move {
+                Ok(Box::new(comick::ComickChapter::from_url(url).await?) as Box<dyn Chapter>)
+            })
+        });
+        #[cfg(feature = "site-asura")]
+        for domain in ["asuracomic.net", "asurascans.com", "asuratoon.com"] {
+            dispatcher
+                .register_domain(domain, asura_chapter)
+                .set_diagnosis_selectors(asura::DIAGNOSIS_SELECTORS);
+        }
+        #[cfg(feature = "site-webtoon")]
+        dispatcher
+            .register_domain("webtoons.com", |url| {
+                Box::pin(async move {
+                    Ok(Box::new(webtoon::WebtoonChapter::from_url(url).await?) as Box<dyn Chapter>)
+                })
+            })
+            .set_diagnosis_selectors(webtoon::DIAGNOSIS_SELECTORS);
+        #[cfg(feature = "site-mangakakalot")]
+        for domain in [
+            "mangakakalot.com",
+            "manganato.com",
+            "chapmanganato.to",
+            "chapmanganato.com",
+        ] {
+            dispatcher
+                .register_domain(domain, mangakakalot_chapter)
+                .set_diagnosis_selectors(mangakakalot::DIAGNOSIS_SELECTORS);
+        }
+        dispatcher
+    }
+
+    /// Register a site. `name` is what `supported_sites` reports for it.
+    pub fn register(
+        &mut self,
+        name: impl ToString,
+        matcher: impl Fn(&Url) -> bool + Send + Sync + 'static,
+        constructor: impl Fn(Url) -> ChapterFuture + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.sites.push(SiteEntry {
+            name: name.to_string(),
+            matcher: Box::new(matcher),
+            constructor: Arc::new(constructor),
+            lister: None,
+            #[cfg(feature = "html")]
+            diagnosis_selectors: &[],
+        });
+        self
+    }
+
+    /// List the chapters of the series or list urls of the last registered site with `lister`
+    pub fn set_chapter_lister(
+        &mut self,
+        lister: impl Fn(Url) -> ChapterListFuture + Send + Sync + 'static,
+    ) -> &mut Self {
+        if let Some(site) = self.sites.last_mut() {
+            site.lister = Some(Arc::new(lister));
+        }
+        self
+    }
+
+    /// Set the selectors checked by `diagnose` on the chapter pages of the last registered site
+    #[cfg(feature = "html")]
+    // unused when the html feature is enabled without a site
+    #[allow(dead_code)]
+    fn set_diagnosis_selectors(&mut self, selectors: DiagnosisSelectors) -> &mut Self {
+        if let Some(site) = self.sites.last_mut() {
+            site.diagnosis_selectors = selectors;
+        }
+        self
+    }
+
+    /// The name and the diagnosis selectors of the first site matching the url
+    #[cfg(feature = "html")]
+    fn diagnosis_selectors(&self, url: &Url) -> Option<(String, DiagnosisSelectors)> {
+        self.sites
+            .iter()
+            .find(|site| (site.matcher)(url))
+            .map(|site| (site.name.clone(), site.diagnosis_selectors))
+    }
+
+    /// Register a site matching urls whose host is `domain` or one of its subdomains
+    pub fn register_domain(
+        &mut self,
+        domain: &str,
+        constructor: impl Fn(Url) -> ChapterFuture + Send + Sync + 'static,
+    ) -> &mut Self {
+        let site_domain = domain.to_string();
+        self.register(
+            domain,
+            move |url| {
+                url.domain()
+                    .is_some_and(|host| is_same_site(host, &site_domain))
+            },
+            constructor,
+        )
+    }
+
+    /// Get the names of registered sites
+    pub fn supported_sites(&self) -> Vec<String> {
+        self.sites.iter().map(|site| site.name.clone()).collect()
+    }
+
+    /// Check if a registered site matches the url, without any request
+    pub fn is_supported(&self, url: &Url) -> bool {
+        self.sites.iter().any(|site| (site.matcher)(url))
+    }
+
+    /// Start resolving the chapter with the first site matching the url. Transient failures are
+    /// retried with a growing delay.
+    fn dispatch(&self, url: Url) -> Result<ChapterFuture, ChapterError> {
+        let domain = url
+            .host_str()
+            .ok_or_else(|| ChapterError::InvalidUrl(url.to_string()))?;
+        let site = self
+            .sites
+            .iter()
+            .find(|site| (site.matcher)(&url))
+            .ok_or_else(|| ChapterError::SiteNotSupported(domain.to_string()))?;
+        // cloned so the lock of the dispatcher is not held while resolving
+        let constructor = site.constructor.clone();
+        Ok(Box::pin(async move {
+            let mut attempt = 0;
+            let result = loop {
+                match constructor(url.clone()).await {
+                    Err(e) if e.is_transient() && attempt < RESOLUTION_RETRIES => {
+                        attempt += 1;
+                        let delay = RESOLUTION_RETRY_DELAY * attempt;
+                        warn!("Cannot resolve {url}: {e}, retry in {delay:?}");
+                        tokio::time::sleep(delay).await;
+                    }
+                    result => break result,
+                }
+            };
+            // a missing page is a deleted chapter rather than a site error
+            result.map_err(|e| match e.status() {
+                Some(404 | 410) => ChapterError::ChapterNotFound(url.to_string()),
+                _ => e,
+            })
+        }))
+    }
+
+    pub async fn get_chapter(
+        &self,
+        url: impl IntoUrl + Display + Clone,
+    ) -> Result<Box<dyn Chapter>, ChapterError> {
+        let url = url
+            .clone()
+            .into_url()
+            .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+        self.dispatch(url)?.await
+    }
+
+    /// Start listing the chapters of a series or list url with the first site matching it
+    fn dispatch_list(&self, url: Url) -> Result<ChapterListFuture, ChapterError> {
+        let domain = url
+            .host_str()
+            .ok_or_else(|| ChapterError::InvalidUrl(url.to_string()))?;
+        let lister = self
+            .sites
+            .iter()
+            .find(|site| (site.matcher)(&url))
+            .and_then(|site| site.lister.clone())
+            .ok_or_else(|| ChapterError::SiteNotSupported(domain.to_string()))?;
+        Ok(lister(url))
+    }
+
+    pub async fn get_list_chapters(
+        &self,
+        url: impl IntoUrl + Display + Clone,
+    ) -> Result<Vec<String>, ChapterError> {
+        let url = url
+            .clone()
+            .into_url()
+            .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+        self.dispatch_list(url)?.await
+    }
+}
+
+/// Check if `host` is `domain` or one of its subdomains, ex: "api.mangadex.org" for "mangadex.org"
+fn is_same_site(host: &str, domain: &str) -> bool {
+    host.strip_suffix(domain)
+        .is_some_and(|subdomain| subdomain.is_empty() || subdomain.ends_with('.'))
+}
+
+/// Check if the host of `url` contains `word`, for sites moving between many domains
+#[cfg(any(feature = "site-blogtruyen", feature = "site-nettruyen"))]
+fn domain_contains(url: &Url, word: &str) -> bool {
+    url.domain().is_some_and(|host| host.contains(word))
+}
+
+#[cfg(feature = "site-nettruyen")]
+fn nettruyen_chapter(url: Url) -> ChapterFuture {
+    Box::pin(async move {
+        Ok(Box::new(nettruyen::NettruyenChapter::from_url(url).await?) as Box<dyn Chapter>)
+    })
+}
+
+#[cfg(feature = "site-truyentranhtuan")]
+fn truyentranhtuan_chapter(url: Url) -> ChapterFuture {
+    Box::pin(async move {
+        Ok(
+            Box::new(truyentranhtuan::TruyenTranhTuanChapter::from_url(url).await?)
+                as Box<dyn Chapter>,
+        )
+    })
+}
+
+#[cfg(feature = "site-batoto")]
+fn batoto_chapter(url: Url) -> ChapterFuture {
+    Box::pin(async move {
+        Ok(Box::new(batoto::BatoToChapter::from_url(url).await?) as Box<dyn Chapter>)
+    })
+}
+
+#[cfg(feature = "site-asura")]
+fn asura_chapter(url: Url) -> ChapterFuture {
+    Box::pin(
+        async move { Ok(Box::new(asura::AsuraChapter::from_url(url).await?) as Box<dyn Chapter>) },
+    )
+}
+
+#[cfg(feature = "site-mangakakalot")]
+fn mangakakalot_chapter(url: Url) -> ChapterFuture {
+    Box::pin(async move {
+        Ok(Box::new(mangakakalot::MangakakalotChapter::from_url(url).await?) as Box<dyn Chapter>)
+    })
+}
+
+/// The dispatcher used by `get_chapter`
+fn global_dispatcher() -> &'static RwLock<SiteDispatcher> {
+    static DISPATCHER: OnceLock<RwLock<SiteDispatcher>> = OnceLock::new();
+    DISPATCHER.get_or_init(|| RwLock::new(SiteDispatcher::with_builtin_sites()))
+}
+
+/// Register a site to be used by `get_chapter`, after the builtin sites
+pub fn register_site(
+    name: impl ToString,
+    matcher: impl Fn(&Url) -> bool + Send + Sync + 'static,
+    constructor: impl Fn(Url) -> ChapterFuture + Send + Sync + 'static,
+) {
+    global_dispatcher()
+        .write()
+        .unwrap()
+        .register(name, matcher, constructor);
+}
+
+/// Register a site to be used by `get_chapter` and `get_list_chapters`, after the builtin sites
+pub fn register_series_site(
+    name: impl ToString,
+    matcher: impl Fn(&Url) -> bool + Send + Sync + 'static,
+    constructor: impl Fn(Url) -> ChapterFuture + Send + Sync + 'static,
+    lister: impl Fn(Url) -> ChapterListFuture + Send + Sync + 'static,
+) {
+    global_dispatcher()
+        .write()
+        .unwrap()
+        .register(name, matcher, constructor)
+        .set_chapter_lister(lister);
+}
+
+/// Get the names of supported sites, ex: "mangadex.org" or "nettruyen" for all its mirrors
+pub fn supported_sites() -> Vec<String> {
+    global_dispatcher().read().unwrap().supported_sites()
+}
+
+/// Check if a site supports the url, without any request, ex: to validate user input. The
+/// chapter may still not exist.
+pub fn is_supported(url: impl IntoUrl) -> bool {
+    url.into_url()
+        .is_ok_and(|url| global_dispatcher().read().unwrap().is_supported(&url))
+}
+
+pub async fn get_chapter(
+    url: impl IntoUrl + Display + Clone,
+) -> Result<Box<dyn Chapter>, ChapterError> {
+    let url = url
+        .clone()
+        .into_url()
+        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+    // the lock must be released before awaiting
+    let chapter_future = global_dispatcher().read().unwrap().dispatch(url)?;
+    chapter_future.await
+}
+
+/// Number of chapters resolved at the same time by `get_chapters`
+const MAX_CONCURRENT_CHAPTER_RESOLUTIONS: usize = 8;
+
+/// Resolve several chapters concurrently, ex: to show what a batch download will get before
+/// downloading. Results are in the order of `urls`.
+pub async fn get_chapters<U: IntoUrl + Display + Clone>(
+    urls: impl IntoIterator<Item = U>,
+) -> Vec<Result<Box<dyn Chapter>, ChapterError>> {
+    futures::stream::iter(urls)
+        .map(get_chapter)
+        .buffered(MAX_CONCURRENT_CHAPTER_RESOLUTIONS)
+        .collect()
+        .await
+}
+
+/// Same as `get_chapters`, but each url is checked with `precheck_chapter` first, so dead links
+/// of a batch fail fast with `ChapterError::ChapterNotFound`
+pub async fn get_chapters_with_precheck<U: IntoUrl + Display + Clone>(
+    urls: impl IntoIterator<Item = U>,
+) -> Vec<Result<Box<dyn Chapter>, ChapterError>> {
+    futures::stream::iter(urls)
+        .map(|url| async move {
+            precheck_chapter(url.clone()).await?;
+            get_chapter(url).await
+        })
+        .buffered(MAX_CONCURRENT_CHAPTER_RESOLUTIONS)
+        .collect()
+        .await
+}
+
+/// Check with a HEAD request that a chapter page exists, without getting and parsing it. Only a
+/// 404 or 410 fails, a server not supporting HEAD or not answering is left to `get_chapter`.
+pub async fn precheck_chapter(url: impl IntoUrl + Display + Clone) -> Result<(), ChapterError> {
+    let url = url
+        .clone()
+        .into_url()
+        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+    match crate::client::head_status(url.clone()).await {
+        Ok(StatusCode::NOT_FOUND | StatusCode::GONE) => {
+            Err(ChapterError::ChapterNotFound(url.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get the chapter metadata, skipping the page requests where the site allows it (MangaDex).
+/// Other sites resolve the whole chapter.
+pub async fn get_chapter_metadata(
+    url: impl IntoUrl + Display + Clone,
+) -> Result<ChapterMeta, ChapterError> {
+    let url = url
+        .clone()
+        .into_url()
+        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+    #[cfg(feature = "site-mangadex")]
+    if url
+        .domain()
+        .is_some_and(|d| is_same_site(d, "mangadex.org"))
+    {
+        return Ok(mangadex::get_chapter_metadata(url).await?);
+    }
+    let chapter = get_chapter(url).await?;
+    Ok(ChapterMeta::from_chapter(chapter.as_ref()))
+}
+
+/// Get the chapter urls of a series or list, ex: a MangaDex title "https://mangadex.org/title/{id}"
+/// or custom list "https://mangadex.org/list/{id}/name", to download a whole series or reading
+/// list. Only English chapters of MangaDex are listed. Sites without listing fail with
+/// `ChapterError::SiteNotSupported`.
+pub async fn get_list_chapters(
+    url: impl IntoUrl + Display + Clone,
+) -> Result<Vec<String>, ChapterError> {
+    let url = url
+        .clone()
+        .into_url()
+        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+    // the lock must be released before awaiting
+    let list_future = global_dispatcher().read().unwrap().dispatch_list(url)?;
+    list_future.await
+}
+
+/// Result of `verify_cbz`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CbzReport {
+    /// Names of all entries of the archive
+    pub entries: Vec<String>,
+    /// Pages which cannot be decoded as images, ex: truncated downloads
+    pub corrupt_pages: Vec<String>,
+    /// Gaps in the `page_NNN` numbering, ex: "chap 1/page_003"
+    pub missing_pages: Vec<String>,
+}
+
+impl CbzReport {
+    pub fn is_ok(&self) -> bool {
+        self.corrupt_pages.is_empty() && self.missing_pages.is_empty()
+    }
+}
+
+impl Display for CbzReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} entries", self.entries.len())?;
+        for page in &self.corrupt_pages {
+            writeln!(f, "Corrupt page: {page}")?;
+        }
+        for page in &self.missing_pages {
+            writeln!(f, "Missing page: {page}")?;
+        }
+        if self.is_ok() {
+            writeln!(f, "OK")?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that every page of a cbz decodes as an image and that no `page_NNN` is missing. Pages
+/// are numbered per folder, as in volumes.
+pub fn verify_cbz(path: impl AsRef<Path>) -> Result<CbzReport, ChapterError> {
+    let to_io_error = |e: zip::result::ZipError| match e {
+        zip::result::ZipError::Io(e) => e,
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+    };
+    static PAGE_PATTERN: OnceLock<Regex> = OnceLock::new();
+    let page_pattern = PAGE_PATTERN.get_or_init(|| Regex::new(r"^page_(\d+)$").unwrap());
+    let mut archive = zip::ZipArchive::new(fs::File::open(path)?).map_err(to_io_error)?;
+    let mut report = CbzReport::default();
+    // page numbers found in each folder
+    let mut numbers: HashMap<String, Vec<usize>> = HashMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(to_io_error)?;
+        let name = entry.name().to_string();
+        report.entries.push(name.clone());
+        if entry.is_dir() || name.ends_with("ComicInfo.xml") || name.ends_with(SOURCES_FILE) {
+            continue;
+        }
+        let mut data = Vec::new();
+        std::io::Read::read_to_end(&mut entry, &mut data)?;
+        if image::load_from_memory(&data).is_err() {
+            report.corrupt_pages.push(name.clone());
+        }
+        let (folder, file_name) = name.rsplit_once('/').unwrap_or(("", &name));
+        let stem = Path::new(file_name)
+            .file_stem()
+            .map(|s| s.to_string_lossy())
+            .unwrap_or_default();
+        if let Some(number) = page_pattern.captures(&stem).and_then(|c| c[1].parse().ok()) {
+            numbers.entry(folder.to_string()).or_default().push(number);
+        }
+    }
+    let mut folders: Vec<_> = numbers.into_iter().collect();
+    folders.sort();
+    for (folder, numbers) in folders {
+        let found: HashSet<usize> = numbers.iter().copied().collect();
+        let max = numbers.into_iter().max().unwrap_or_default();
+        for number in (1..max).filter(|n| !found.contains(n)) {
+            let page = format!("page_{number:03}");
+            report.missing_pages.push(if folder.is_empty() {
+                page
+            } else {
+                format!("{folder}/{page}")
+            });
+        }
+    }
+    Ok(report)
+}
+
+fn zip_folder<P: Into<PathBuf>>(
+    folder_path: P,
+    zip_path: P,
+) -> std::result::Result<(), std::io::Error> {
+    let output_path = zip_path.into();
+    let file: fs::File = fs::File::create(&output_path)?;
+    let mut writer = std::io::BufWriter::new(file);
+    zip_folder_to_writer(folder_path, &mut writer)
+}
+
+fn zip_folder_to_writer<P: Into<PathBuf>, W: Write + Seek>(
+    folder_path: P,
+    writer: W,
+) -> std::result::Result<(), std::io::Error> {
+    let mut zip = ZipWriter::new(writer);
+    add_folder_to_zip(&mut zip, &folder_path.into(), None)?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Pack several folders (ex: downloaded chapters) into one cbz. Each folder becomes a directory
+/// named `{index:05}_{folder name}` in the archive, so readers keep the order of `folders`.
+pub fn pack_folders_to_cbz(folders: &[PathBuf], out: &Path) -> std::io::Result<()> {
+    if let Some(p) = out.parent() {
+        fs::create_dir_all(p)?;
+    }
+    let file = fs::File::create(out)?;
+    let mut zip = ZipWriter::new(std::io::BufWriter::new(file));
+    add_folders_to_zip(&mut zip, folders)?;
+    zip.finish()?;
+    Ok(())
+}
+
+fn add_folders_to_zip<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    folders: &[PathBuf],
+) -> std::io::Result<()> {
+    for (i, folder) in folders.iter().enumerate() {
+        let name = folder
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        add_folder_to_zip(zip, folder, Some(&format!("{:05}_{}", i, name)))?;
+    }
+    Ok(())
+}
+
+/// Add the files of `folder_path` (not recursive) in natural name order, so non-padded pages
+/// keep the reading order, under `prefix` directory if any
+fn add_folder_to_zip<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    folder_path: &Path,
+    prefix: Option<&str>,
+) -> std::io::Result<()> {
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for path in folder_files(folder_path)? {
+        let relative_path = path.strip_prefix(folder_path).unwrap().to_string_lossy();
+        let entry_name = match prefix {
+            Some(prefix) => format!("{prefix}/{relative_path}"),
+            None => relative_path.to_string(),
+        };
+        zip.start_file(entry_name, options)?;
+        let mut source_file = fs::File::open(path)?;
+        std::io::copy(&mut source_file, zip)?;
+    }
+    Ok(())
+}
+
+/// The files of `folder_path` (not recursive) in natural name order
+fn folder_files(folder_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(folder_path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|path| path.is_file());
+    files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+    Ok(files)
+}
+
+/// Zip the files of `folder_path` like a downloaded chapter into a writer which cannot seek, ex:
+/// a response body. Only the file being compressed is kept in memory, the previous ones are
+/// written out as soon as the next one starts.
+pub fn zip_folder_to_stream<W: Write>(folder_path: &Path, writer: W) -> std::io::Result<()> {
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let stream = Rc::new(RefCell::new(ForwardWriter::new(writer)));
+    let mut zip = ZipWriter::new(SharedForwardWriter(Rc::clone(&stream)));
+    for path in folder_files(folder_path)? {
+        let relative_path = path.strip_prefix(folder_path).unwrap().to_string_lossy();
+        let previous_end = stream.borrow().position;
+        // the header of the previous file is updated when the next one starts
+        zip.start_file(relative_path, options)?;
+        stream.borrow_mut().commit(previous_end)?;
+        let mut source_file = fs::File::open(path)?;
+        std::io::copy(&mut source_file, &mut zip)?;
+    }
+    zip.finish()?;
+    drop(zip);
+    let mut stream = stream.borrow_mut();
+    let end = stream.position;
+    stream.commit(end)?;
+    stream.inner.flush()
+}
+
+/// Give `ZipWriter` a seekable writer over a forward only one, by keeping the bytes which may
+/// still be rewritten until they are committed
+struct ForwardWriter<W> {
+    inner: W,
+    /// Bytes after `committed`, not written to `inner` yet
+    buffer: Vec<u8>,
+    committed: u64,
+    position: u64,
+}
+
+impl<W: Write> ForwardWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            buffer: Vec::new(),
+            committed: 0,
+            position: 0,
+        }
+    }
+
+    /// Write the bytes before `offset` to the inner writer, they cannot be sought anymore
+    fn commit(&mut self, offset: u64) -> std::io::Result<()> {
+        let count = (offset.saturating_sub(self.committed) as usize).min(self.buffer.len());
+        self.inner.write_all(&self.buffer[..count])?;
+        self.buffer.drain(..count);
+        self.committed += count as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ForwardWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let start = (self.position - self.committed) as usize;
+        let overwritten = buf.len().min(self.buffer.len().saturating_sub(start));
+        self.buffer[start..start + overwritten].copy_from_slice(&buf[..overwritten]);
+        self.buffer.extend_from_slice(&buf[overwritten..]);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W> Seek for ForwardWriter<W> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let end = self.committed + self.buffer.len() as u64;
+        let position = match pos {
+            std::io::SeekFrom::Start(offset) => Some(offset),
+            std::io::SeekFrom::End(offset) => end.checked_add_signed(offset),
+            std::io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) if (self.committed..=end).contains(&position) => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "cannot seek outside of the uncommitted bytes",
+            )),
+        }
+    }
+}
+
+struct SharedForwardWriter<W>(Rc<RefCell<ForwardWriter<W>>>);
+
+impl<W: Write> Write for SharedForwardWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+impl<W> Seek for SharedForwardWriter<W> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        self.0.borrow_mut().seek(pos)
+    }
+}
+
+/// Compare names with their digit runs as numbers, ex: "page_2" < "page_10"
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let a_end = a.find(|c: char| !c.is_ascii_digit()).unwrap_or(a.len());
+                let b_end = b.find(|c: char| !c.is_ascii_digit()).unwrap_or(b.len());
+                let a_number = a[..a_end].trim_start_matches('0');
+                let b_number = b[..b_end].trim_start_matches('0');
+                // equal numbers are ordered by padding to keep the order total
+                let ordering = a_number
+                    .len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(b_number))
+                    .then_with(|| a_end.cmp(&b_end));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                (a, b) = (&a[a_end..], &b[b_end..]);
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// A mock server answering every page with a small png, and a folder to download into
+    struct Fixture {
+        server: MockServer,
+        dir: tempfile::TempDir,
+    }
+
+    impl Fixture {
+        async fn new() -> Self {
+            let fixture = Fixture {
+                server: MockServer::start().await,
+                dir: tempfile::tempdir().unwrap(),
+            };
+            fixture.mount_pages().await;
+            fixture
+        }
+
+        /// Mount the fallback page, the mocks of the tests take precedence over it
+        async fn mount_pages(&self) {
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("page", "image/png"))
+                .with_priority(u8::MAX)
+                .mount(&self.server)
+                .await;
+        }
+
+        fn url(&self, page: usize) -> String {
+            format!("{}/{page}", self.server.uri())
+        }
+
+        fn chapter(&self, pages: usize) -> ManualChapter {
+            ManualChapter::new("Manga", "chap 1", (1..=pages).map(|i| self.url(i)), None)
+        }
+
+        fn path(&self) -> &Path {
+            self.dir.path()
+        }
+    }
+
+    #[test]
+    fn test_zip_folder_natural_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("chapter");
+        fs::create_dir(&folder).unwrap();
+        for i in [10, 2, 0, 1, 11] {
+            fs::write(folder.join(format!("page_{i}.jpg")), "page").unwrap();
+        }
+        let zip_path = dir.path().join("chapter.cbz");
+        zip_folder(&folder, &zip_path).unwrap();
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(zip_path).unwrap()).unwrap();
+        let names: Vec<_> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "page_0.jpg",
+                "page_1.jpg",
+                "page_2.jpg",
+                "page_10.jpg",
+                "page_11.jpg"
+            ]
+        );
+        assert_eq!(natural_cmp("page_01", "page_1"), Ordering::Greater);
+        assert_eq!(natural_cmp("a2b", "a2c"), Ordering::Less);
+    }
+
+    #[tokio::test]
+    async fn test_resume_after_failed_convert_webp() {
+        let mut webp = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut webp), image::ImageFormat::WebP)
+            .unwrap();
+        let fixture = Fixture::new().await;
+        Mock::given(path("/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(webp, "image/webp"))
+            .expect(1)
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("2", "image/png"))
+            .expect(1)
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = ManualChapter::new("Manga", "chap 1", [fixture.url(1), fixture.url(2)], None);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_convert_webp(true).set_retries(0);
+        assert!(matches!(
+            download_chapter_pages(&chapter, Some(fixture.path()), &options).await,
+            Err(ChapterError::PagesDownloadError { .. })
+        ));
+        // the downloaded page is kept as is for the resume
+        assert!(fixture.path().join("page_001.webp").exists());
+
+        let pages = download_chapter_pages(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            [
+                fixture.path().join("page_001.jpg"),
+                fixture.path().join("page_002.png")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_report_only_mangadex_pages() {
+        struct MangadexChapter {
+            pages: Vec<DownloadItem>,
+        }
+        impl Chapter for MangadexChapter {
+            fn source(&self) -> &'static str {
+                "mangadex"
+            }
+            fn url(&self) -> String {
+                String::from("https://mangadex.org/chapter/1")
+            }
+            fn manga(&self) -> String {
+                String::from("Manga")
+            }
+            fn chapter(&self) -> String {
+                String::from("chap 1")
+            }
+            fn pages_download_info(&self) -> &Vec<DownloadItem> {
+                &self.pages
+            }
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let page = "https://uploads.example.com/data/hash/1.png";
+        let mut chapter_options = ChapterDownloadOptions::new();
+        chapter_options.set_report_mangadex(true);
+        // a page of another site with a MangaDex@Home like path
+        let manual = ManualChapter::new("Manga", "chap 1", [page], None);
+        let options = page_download_options(&manual, dir.path(), &chapter_options).unwrap();
+        assert!(!options.reports_mangadex());
+
+        let mangadex = MangadexChapter {
+            pages: manual.pages_download_info().clone(),
+        };
+        let options = page_download_options(&mangadex, dir.path(), &chapter_options).unwrap();
+        assert!(options.reports_mangadex());
+    }
+
+    #[test]
+    fn test_zip_folder_to_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in [2, 1, 10] {
+            let page: Vec<u8> = (0..50_000u32).map(|n| (n * i) as u8).collect();
+            fs::write(dir.path().join(format!("page_{i}.jpg")), page).unwrap();
+        }
+        // a Vec has no Seek impl when it is not in a Cursor
+        let mut data = Vec::new();
+        zip_folder_to_stream(dir.path(), &mut data).unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(data)).unwrap();
+        let names: Vec<_> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(names, ["page_1.jpg", "page_2.jpg", "page_10.jpg"]);
+        let mut page = Vec::new();
+        std::io::Read::read_to_end(&mut archive.by_name("page_10.jpg").unwrap(), &mut page)
+            .unwrap();
+        assert_eq!(page, fs::read(dir.path().join("page_10.jpg")).unwrap());
+    }
+
+    #[test]
+    fn test_pack_folders_to_cbz() {
+        let dir = tempfile::tempdir().unwrap();
+        let folders: Vec<_> = ["chap 2", "chap 1"]
+            .iter()
+            .map(|name| {
+                let folder = dir.path().join(name);
+                fs::create_dir(&folder).unwrap();
+                fs::write(folder.join("page_002.jpg"), name).unwrap();
+                fs::write(folder.join("page_001.jpg"), name).unwrap();
+                folder
+            })
+            .collect();
+        let out = dir.path().join("volume.cbz");
+        pack_folders_to_cbz(&folders, &out).unwrap();
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(out).unwrap()).unwrap();
+        let names: Vec<_> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "00000_chap 2/page_001.jpg",
+                "00000_chap 2/page_002.jpg",
+                "00001_chap 1/page_001.jpg",
+                "00001_chap 1/page_002.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_supported() {
+        #[cfg(feature = "site-mangadex")]
+        assert!(is_supported(
+            "https://mangadex.org/chapter/f9a8fc1f-1fb5-43af-8844-1672ee6c7290"
+        ));
+        assert!(!is_supported("https://unrelated-site.test/chapter/1"));
+        assert!(!is_supported("not a url"));
+    }
+
+    #[test]
+    fn test_default_referer() {
+        let referer = |url: &str| default_referer(&Url::parse(url).unwrap());
+        assert_eq!(
+            referer("https://weebcentral.com/chapters/01J76XZ").as_deref(),
+            Some("https://weebcentral.com/")
+        );
+        assert_eq!(
+            referer("http://www.toptruyen.live:8080/truyen/a/chapter-1?x=1").as_deref(),
+            Some("http://www.toptruyen.live/")
+        );
+        assert_eq!(referer("file:///tmp/chapter"), None);
+
+        let explicit = ManualChapter::new("manga", "chap 1", [""; 0], Some("https://a.com".into()))
+            .with_url("https://b.com/chap-1");
+        assert_eq!(chapter_referer(&explicit).as_deref(), Some("https://a.com"));
+        let derived =
+            ManualChapter::new("manga", "chap 1", [""; 0], None).with_url("https://b.com/chap-1");
+        assert_eq!(chapter_referer(&derived).as_deref(), Some("https://b.com/"));
+        let no_url = ManualChapter::new("manga", "chap 1", [""; 0], None);
+        assert_eq!(chapter_referer(&no_url), None);
+    }
+
+    #[cfg(feature = "site-webtoon")]
+    #[tokio::test]
+    async fn test_chapter_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(path("/removed"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(path("/malformed"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        let mut dispatcher = SiteDispatcher::new();
+        dispatcher.register(
+            "mock",
+            |_| true,
+            |url| {
+                Box::pin(async move {
+                    Ok(Box::new(webtoon::WebtoonChapter::from_url(url).await?) as Box<dyn Chapter>)
+                })
+            },
+        );
+
+        let removed_url = format!("{}/removed", server.uri());
+        assert!(matches!(
+            dispatcher.get_chapter(removed_url.as_str()).await,
+            Err(ChapterError::ChapterNotFound(url)) if url == removed_url
+        ));
+        assert!(matches!(
+            dispatcher
+                .get_chapter(format!("{}/malformed", server.uri()))
+                .await,
+            Err(ChapterError::WebtoonError(
+                webtoon::WebtoonError::ParseError(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_resolution() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = MockServer::start().await;
+        Mock::given(path("/chapter"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/chapter"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("chap 1"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/malformed"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut dispatcher = SiteDispatcher::new();
+        dispatcher.register("mock", |_| true, {
+            let attempts = attempts.clone();
+            move |url| {
+                let attempts = attempts.clone();
+                Box::pin(async move {
+                    attempts.fetch_add(1, Ordering::SeqCst);
+                    let chapter = async {
+                        reqwest::get(url.clone())
+                            .await?
+                            .error_for_status()?
+                            .text()
+                            .await
+                    }
+                    .await
+                    .map_err(|e| ChapterError::SiteError(e.into()))?;
+                    if chapter.is_empty() {
+                        return Err(ChapterError::SiteError("cannot parse chapter".into()));
+                    }
+                    Ok(
+                        Box::new(ManualChapter::new("Manga", chapter, [""; 0], None))
+                            as Box<dyn Chapter>,
+                    )
+                })
+            }
+        });
+
+        let chapter = dispatcher
+            .get_chapter(format!("{}/chapter", server.uri()))
+            .await
+            .unwrap();
+        assert_eq!(chapter.chapter(), "chap 1");
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 2);
+
+        // parse errors are not retried
+        let result = dispatcher
+            .get_chapter(format!("{}/malformed", server.uri()))
+            .await;
+        assert!(matches!(result, Err(ChapterError::SiteError(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_single_retry_layer() {
+        let server = MockServer::start().await;
+        // the first request and the retries of the dispatcher, none from the scraper client
+        Mock::given(path("/chapter"))
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1 + RESOLUTION_RETRIES as u64)
+            .mount(&server)
+            .await;
+        let mut dispatcher = SiteDispatcher::new();
+        dispatcher.register(
+            "mock",
+            |_| true,
+            |url| {
+                Box::pin(async move {
+                    let chapter = crate::client::fetch_text(url)
+                        .await
+                        .map_err(|e| ChapterError::SiteError(e.into()))?;
+                    Ok(
+                        Box::new(ManualChapter::new("Manga", chapter, [""; 0], None))
+                            as Box<dyn Chapter>,
+                    )
+                })
+            },
+        );
+
+        let result = dispatcher
+            .get_chapter(format!("{}/chapter", server.uri()))
+            .await;
+        assert!(matches!(result, Err(e) if e.status() == Some(503)));
+        server.verify().await;
+    }
+
+    #[cfg(all(
+        feature = "site-blogtruyen",
+        feature = "site-nettruyen",
+        feature = "site-toptruyen",
+        feature = "site-truyentranhtuan"
+    ))]
+    #[tokio::test]
+    async fn test_vietnamese_accept_language() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        // the pages are empty, only the requests matter
+        let url = |site: &str| format!("{}/{site}/chapter-1", server.uri());
+        let _ = nettruyen::NettruyenChapter::from_url(url("nettruyen")).await;
+        let _ = toptruyen::TopTruyenChapter::from_url(url("toptruyen")).await;
+        let _ = blogtruyen::BlogTruyenChapter::from_url(url("blogtruyen")).await;
+        let _ = truyentranhtuan::TruyenTranhTuanChapter::from_url(url("truyentuan")).await;
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 4);
+        for request in requests {
+            assert_eq!(
+                request.headers.get("accept-language").unwrap(),
+                "vi-VN,vi;q=0.9",
+                "{}",
+                request.url
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_aria2_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapter = ManualChapter::new(
+            "Manga",
+            "chap 1",
+            ["https://a.com/1.jpg", "https://a.com/2.jpg"],
+            Some(String::from("https://a.com/")),
+        );
+        let path = write_aria2_input(&chapter, dir.path().join("input.txt")).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "https://a.com/1.jpg\n  out=page_001.jpg\n  referer=https://a.com/\n\
+             https://a.com/2.jpg\n  out=page_002.jpg\n  referer=https://a.com/\n"
+        );
+
+        let chapter = ManualChapter::new("Manga", "chap 1", ["https://a.com/1.jpg"], None);
+        let path = write_aria2_input(&chapter, dir.path().join("input.txt")).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "https://a.com/1.jpg\n  out=page_001.jpg\n"
+        );
+
+        // unnamed pages are named by index, the forced extension wins over the url one
+        struct UnnamedPages(Vec<DownloadItem>);
+        impl Chapter for UnnamedPages {
+            fn url(&self) -> String {
+                String::new()
+            }
+            fn manga(&self) -> String {
+                String::from("Manga")
+            }
+            fn chapter(&self) -> String {
+                String::from("chap 1")
+            }
+            fn pages_download_info(&self) -> &Vec<DownloadItem> {
+                &self.0
+            }
+        }
+        let chapter = UnnamedPages(vec![
+            DownloadItem::new("https://a.com/image?id=1", None::<String>),
+            DownloadItem::new("https://a.com/2.webp", None::<String>),
+            DownloadItem::new("https://a.com/3.webp", None::<String>).with_extension("png"),
+        ]);
+        let path = write_aria2_input(&chapter, dir.path().join("input.txt")).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "https://a.com/image?id=1\n  out=page_001\n\
+             https://a.com/2.webp\n  out=page_002.webp\n\
+             https://a.com/3.webp\n  out=page_003.png\n"
+        );
+    }
+
+    #[test]
+    fn test_dedupe_pages() {
+        let write_pages = |dir: &Path| -> Vec<_> {
+            [
+                ("a.png", "end card"),
+                ("b.jpg", "page"),
+                ("c.png", "end card"),
+            ]
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.join(name);
+                fs::write(&path, content).unwrap();
+                path
+            })
+            .collect()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let pages = write_pages(dir.path());
+
+        let (indexes, kept): (Vec<_>, Vec<_>) = dedupe_pages(&pages, &PageNaming::Site)
+            .unwrap()
+            .into_iter()
+            .unzip();
+        assert_eq!(indexes, [0, 1]);
+        assert_eq!(
+            kept,
+            [
+                dir.path().join("page_001.png"),
+                dir.path().join("page_002.jpg")
+            ]
+        );
+        assert_eq!(fs::read_to_string(&kept[0]).unwrap(), "end card");
+        assert_eq!(fs::read_to_string(&kept[1]).unwrap(), "page");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // the other namings are kept
+        let dir = tempfile::tempdir().unwrap();
+        let pages = write_pages(dir.path());
+        let kept: Vec<_> = dedupe_pages(&pages, &PageNaming::OriginalName)
+            .unwrap()
+            .into_iter()
+            .map(|(_, page)| page)
+            .collect();
+        assert_eq!(kept, pages[..2]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        let dir = tempfile::tempdir().unwrap();
+        let pages = write_pages(dir.path());
+        let kept: Vec<_> = dedupe_pages(&pages, &PageNaming::IndexZeroPadded { width: 2 })
+            .unwrap()
+            .into_iter()
+            .map(|(_, page)| page)
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                dir.path().join("page_00.png"),
+                dir.path().join("page_01.jpg")
+            ]
+        );
+    }
+
+    struct NamedChapter {
+        url: &'static str,
+        manga: &'static str,
+        chapter: &'static str,
+        pages: Vec<DownloadItem>,
+    }
+
+    impl Chapter for NamedChapter {
+        fn url(&self) -> String {
+            self.url.to_string()
+        }
+
+        fn manga(&self) -> String {
+            self.manga.to_string()
+        }
+
+        fn chapter(&self) -> String {
+            self.chapter.to_string()
+        }
+
+        fn pages_download_info(&self) -> &Vec<DownloadItem> {
+            &self.pages
+        }
+    }
+
+    #[cfg(feature = "site-mangadex")]
+    #[tokio::test]
+    async fn test_chapter_metadata_rejects_look_alike_hosts() {
+        // not sent to the MangaDex api, and no site matches it
+        assert!(matches!(
+            get_chapter_metadata("https://mangadex.org.evil.test/chapter/1").await,
+            Err(ChapterError::SiteNotSupported(host)) if host == "mangadex.org.evil.test"
+        ));
+    }
+
+    #[test]
+    fn test_register_domain_rejects_look_alike_hosts() {
+        let mut dispatcher = SiteDispatcher::new();
+        for domain in [
+            "mangadex.org",
+            "bato.to",
+            "comick.io",
+            "webtoons.com",
+            "weebcentral.com",
+        ] {
+            dispatcher.register_domain(domain, |_| {
+                Box::pin(async { Err(ChapterError::SiteError("unused".into())) })
+            });
+        }
+        let is_supported = |url: &str| dispatcher.is_supported(&Url::parse(url).unwrap());
+        for domain in [
+            "mangadex.org",
+            "bato.to",
+            "comick.io",
+            "webtoons.com",
+            "weebcentral.com",
+        ] {
+            assert!(is_supported(&format!("https://{domain}/chapter/1")));
+            assert!(is_supported(&format!("https://api.{domain}/chapter/1")));
+            assert!(!is_supported(&format!(
+                "https://{domain}.evil.test/chapter/1"
+            )));
+            assert!(!is_supported(&format!("https://not{domain}/chapter/1")));
+            assert!(!is_supported(&format!(
+                "https://not{domain}.example/chapter/1"
+            )));
+        }
+    }
+
+    #[cfg(all(
+        feature = "site-blogtruyen",
+        feature = "site-nettruyen",
+        feature = "site-toptruyen"
+    ))]
+    #[test]
+    fn test_builtin_sites_moving_domains() {
+        let dispatcher = SiteDispatcher::with_builtin_sites();
+        let is_supported = |url: &str| dispatcher.is_supported(&Url::parse(url).unwrap());
+        assert!(is_supported("https://blogtruyenmoi.com/c1/chap-1"));
+        assert!(is_supported("https://www.nettruyenus.com/truyen/chap-1"));
+        assert!(is_supported("https://www.toptruyen.live/truyen/chap-1"));
+        assert!(!is_supported("https://mangadex.org.evil.test/chapter/1"));
+    }
+
+    #[tokio::test]
+    async fn test_register_site() {
+        fn fake_chapter(url: Url) -> ChapterFuture {
+            Box::pin(async move {
+                if url.path() == "/missing" {
+                    return Err(ChapterError::SiteError("chapter is missing".into()));
+                }
+                Ok(Box::new(NamedChapter {
+                    url: "https://fake-site.test/chapter/1",
+                    manga: "Fake",
+                    chapter: "chap 1",
+                    pages: Vec::new(),
+                }) as Box<dyn Chapter>)
+            })
+        }
+
+        let mut dispatcher = SiteDispatcher::new();
+        dispatcher.register_domain("fake-site.test", fake_chapter);
+        let chapter = dispatcher
+            .get_chapter("https://fake-site.test/chapter/1")
+            .await
+            .unwrap();
+        assert_eq!(chapter.full_name(), "Fake - chap 1");
+        assert!(matches!(
+            dispatcher
+                .get_chapter("https://fake-site.test/missing")
+                .await,
+            Err(ChapterError::SiteError(_))
+        ));
+        assert!(matches!(
+            dispatcher
+                .get_chapter("https://mangadex.org/chapter/1")
+                .await,
+            Err(ChapterError::SiteNotSupported(_))
+        ));
+
+        register_site(
+            "global-fake-site.test",
+            |url| url.domain() == Some("global-fake-site.test"),
+            fake_chapter,
+        );
+        #[cfg(feature = "site-mangadex")]
+        assert!(supported_sites().contains(&String::from("mangadex.org")));
+        assert!(supported_sites().contains(&String::from("global-fake-site.test")));
+        let chapter = get_chapter("https://global-fake-site.test/chapter/1")
+            .await
+            .unwrap();
+        assert_eq!(chapter.manga(), "Fake");
+    }
+
+    #[test]
+    fn test_page_naming() {
+        let items = [
+            DownloadItem::new("https://a.test/x/001.jpg", Some("page_001")),
+            DownloadItem::new("https://b.test/y/cover.png", Some("page_00.png")),
+            DownloadItem::new("https://c.test/z/3.webp", None::<String>)
+                .add_url("https://d.test/z/3.webp"),
+        ];
+        let names = |naming: PageNaming| -> Vec<Option<String>> {
+            naming
+                .rename(&items)
+                .iter()
+                .map(|item| item.name().map(|x| x.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            names(PageNaming::IndexZeroPadded { width: 3 }),
+            [
+                Some(String::from("page_000")),
+                Some(String::from("page_001")),
+                Some(String::from("page_002"))
+            ]
+        );
+        assert_eq!(names(PageNaming::OriginalName), [None, None, None]);
+        assert_eq!(
+            names(PageNaming::Site),
+            [
+                Some(String::from("page_001")),
+                Some(String::from("page_00.png")),
+                None
+            ]
+        );
+        let renamed = PageNaming::IndexZeroPadded { width: 2 }.rename(&items);
+        assert_eq!(renamed[2].alt_urls(), ["https://d.test/z/3.webp"]);
+        assert_eq!(renamed[2].url(), "https://c.test/z/3.webp");
+    }
+
+    #[test]
+    fn test_chapter_source() {
+        let sources: Vec<(&str, Box<dyn Chapter>)> = vec![
+            #[cfg(feature = "site-asura")]
+            ("asura", Box::<asura::AsuraChapter>::default()),
+            #[cfg(feature = "site-batoto")]
+            ("batoto", Box::<batoto::BatoToChapter>::default()),
+            #[cfg(feature = "site-blogtruyen")]
+            (
+                "blogtruyen",
+                Box::<blogtruyen::BlogTruyenChapter>::default(),
+            ),
+            #[cfg(feature = "site-comick")]
+            ("comick", Box::<comick::ComickChapter>::default()),
+            #[cfg(feature = "site-mangadex")]
+            ("mangadex", Box::<mangadex::MangadexChapter>::default()),
+            #[cfg(feature = "site-mangakakalot")]
+            (
+                "mangakakalot",
+                Box::<mangakakalot::MangakakalotChapter>::default(),
+            ),
+            #[cfg(feature = "site-mangapark")]
+            ("mangapark", Box::<mangapark::MangaParkChapter>::default()),
+            #[cfg(feature = "site-nettruyen")]
+            ("nettruyen", Box::<nettruyen::NettruyenChapter>::default()),
+            #[cfg(feature = "site-toptruyen")]
+            ("toptruyen", Box::<toptruyen::TopTruyenChapter>::default()),
+            #[cfg(feature = "site-truyentranhtuan")]
+            (
+                "truyentranhtuan",
+                Box::<truyentranhtuan::TruyenTranhTuanChapter>::default(),
+            ),
+            #[cfg(feature = "site-webtoon")]
+            ("webtoon", Box::<webtoon::WebtoonChapter>::default()),
+            #[cfg(feature = "site-weebcentral")]
+            (
+                "weebcentral",
+                Box::<weebcentral::WeebCentralChapter>::default(),
+            ),
+            (
+                "manual",
+                Box::new(ManualChapter::new("Manga", "chap 1", [""; 0], None)),
+            ),
+        ];
+        for (source, chapter) in sources {
+            assert_eq!(chapter.source(), source);
+        }
+    }
+
+    #[cfg(all(feature = "site-mangadex", not(feature = "site-mangapark")))]
+    #[tokio::test]
+    async fn test_single_site_build() {
+        // run with `--no-default-features --features site-mangadex`
+        let dispatcher = SiteDispatcher::with_builtin_sites();
+        assert_eq!(dispatcher.supported_sites(), ["mangadex.org"]);
+        assert!(matches!(
+            dispatcher
+                .get_chapter("https://mangapark.net/title/1/2")
+                .await,
+            Err(ChapterError::SiteNotSupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_chapters() {
+        register_site(
+            "slow-fake-site.test",
+            |url| url.domain() == Some("slow-fake-site.test"),
+            |url| {
+                Box::pin(async move {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    let chapter =
+                        ManualChapter::new("Fake", url.path(), ["https://a.com/1.jpg"], None);
+                    Ok(Box::new(chapter) as Box<dyn Chapter>)
+                })
+            },
+        );
+
+        let urls: Vec<String> = (1..=5)
+            .map(|i| format!("https://slow-fake-site.test/{i}"))
+            .collect();
+        let start = std::time::Instant::now();
+        let chapters = get_chapters(&urls).await;
+        assert!(start.elapsed() < Duration::from_millis(800));
+        let names: Vec<String> = chapters.into_iter().map(|c| c.unwrap().chapter()).collect();
+        assert_eq!(names, ["/1", "/2", "/3", "/4", "/5"]);
+    }
+
+    #[tokio::test]
+    async fn test_precheck_chapters() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/missing"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/no-head"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+        static PARSED: AtomicUsize = AtomicUsize::new(0);
+        let port = server.address().port();
+        register_site(
+            "precheck-fake-site",
+            move |url| url.port() == Some(port),
+            |url| {
+                Box::pin(async move {
+                    PARSED.fetch_add(1, Ordering::SeqCst);
+                    let chapter =
+                        ManualChapter::new("Fake", url.path(), ["https://a.com/1.jpg"], None);
+                    Ok(Box::new(chapter) as Box<dyn Chapter>)
+                })
+            },
+        );
+
+        let urls: Vec<String> = ["/chapter", "/missing", "/no-head"]
+            .iter()
+            .map(|p| format!("{}{p}", server.uri()))
+            .collect();
+        let chapters = get_chapters_with_precheck(&urls).await;
+        assert_eq!(chapters[0].as_ref().unwrap().chapter(), "/chapter");
+        assert!(matches!(&chapters[1], Err(ChapterError::ChapterNotFound(url)) if *url == urls[1]));
+        assert_eq!(chapters[2].as_ref().unwrap().chapter(), "/no-head");
+        // the missing chapter is not parsed
+        assert_eq!(PARSED.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_chapter_path() {
+        let out_dir = Path::new("out");
+        let chapter = ManualChapter::new("Manga", "chap 1", ["https://a.com/1.jpg"], None);
+        assert_eq!(
+            chapter_path(&chapter, out_dir, false),
+            out_dir.join("Manga - chap 1")
+        );
+        assert_eq!(
+            chapter_path(&chapter, out_dir, true),
+            out_dir.join("Manga").join("Manga - chap 1")
+        );
+
+        let who = ManualChapter::new("Who", "chap 1", ["https://a.com/1.jpg"], None);
+        let who_question = ManualChapter::new("Who?", "chap 1", ["https://a.com/1.jpg"], None);
+        let who_star = ManualChapter::new("Who*", "chap 1", ["https://a.com/1.jpg"], None);
+        let dirs: HashSet<String> = [&who, &who_question, &who_star]
+            .into_iter()
+            .map(manga_dir_name)
+            .collect();
+        assert_eq!(dirs.len(), 3);
+        assert!(dirs.contains("Who"));
+        assert!(dirs.iter().all(|d| d.starts_with("Who")));
+    }
+
+    #[test]
+    fn test_verify_cbz() {
+        let mut png = Vec::new();
+        image::RgbImage::new(2, 2)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manga.cbz");
+        let mut zip = ZipWriter::new(fs::File::create(&path).unwrap());
+        for (name, data) in [
+            ("ComicInfo.xml", &b"<ComicInfo/>"[..]),
+            ("page_001.png", &png[..]),
+            ("page_002.png", &png[..png.len() / 2]),
+            ("page_004.png", &png[..]),
+        ] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let report = verify_cbz(&path).unwrap();
+        assert_eq!(report.entries.len(), 4);
+        assert_eq!(report.corrupt_pages, ["page_002.png"]);
+        assert_eq!(report.missing_pages, ["page_003"]);
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_full_name_fallback() {
+        let full_name = |url, manga, chapter| {
+            NamedChapter {
+                url,
+                manga,
+                chapter,
+                pages: Vec::new(),
+            }
+            .full_name()
+        };
+        let url = "https://example.com/one-piece/chap-1086/";
+        assert_eq!(
+            full_name(url, "One Piece", "chap 1086"),
+            "One Piece - chap 1086"
+        );
+        assert_eq!(
+            full_name(url, "One Piece", " "),
+            "One Piece - Unknown Chapter"
+        );
+        assert_eq!(full_name(url, "", "chap 1086"), "chap-1086 - chap 1086");
+        assert_eq!(full_name(url, "", ""), "chap-1086 - Unknown Chapter");
+        assert_eq!(
+            full_name("https://example.com/", "", ""),
+            "example.com - Unknown Chapter"
+        );
+        assert_eq!(
+            full_name("not a url", "", ""),
+            "Unknown Manga - Unknown Chapter"
+        );
+    }
+
+    #[test]
+    fn test_parse_chapter_number() {
+        assert_eq!(parse_chapter_number("vol 7 chap 99"), Some(99.0));
+        assert_eq!(
+            parse_chapter_number("Vol.13 Ch.106: Bell's Tears"),
+            Some(106.0)
+        );
+        assert_eq!(parse_chapter_number("chap 267.5 - Extra"), Some(267.5));
+        assert_eq!(parse_chapter_number("Chương 168: Lời hứa"), Some(168.0));
+        assert_eq!(parse_chapter_number("85"), Some(85.0));
+        assert_eq!(parse_chapter_number("Oneshot"), None);
+    }
+
+    #[test]
+    fn test_chapter_filter() {
+        let series = [
+            "chap 4",
+            "chap 5",
+            "chap 5.5",
+            "chap 7",
+            "chap 9",
+            "chap 10",
+            "chap 15.5",
+            "chap 20",
+            "chap 21",
+        ];
+        let select = |filter: &str| {
+            let filter: ChapterFilter = filter.parse().unwrap();
+            series
+                .iter()
+                .filter(|c| parse_chapter_number(c).is_some_and(|n| filter.matches(n)))
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(select("10-20"), ["chap 10", "chap 15.5", "chap 20"]);
+        assert_eq!(select("5,7,9"), ["chap 5", "chap 7", "chap 9"]);
+        assert_eq!(
+            select("4-5.5, 21"),
+            ["chap 4", "chap 5", "chap 5.5", "chap 21"]
+        );
+        assert!("20-10".parse::<ChapterFilter>().is_err());
+        assert!("abc".parse::<ChapterFilter>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cbz_error_removes_temp_dir() {
+        let fixture = Fixture::new().await;
+
+        let chapter = fixture.chapter(1);
+        // a folder in place of the archive makes the zipping fail after the download
+        let zip_path = fixture.path().join("manga.cbz");
+        std::fs::create_dir(&zip_path).unwrap();
+        download_chapter_as_cbz(&chapter, Some(&zip_path))
+            .await
+            .unwrap_err();
+
+        let entries: Vec<_> = std::fs::read_dir(fixture.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, ["manga.cbz"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_chapter_as_epub() {
+        let fixture = Fixture::new().await;
+
+        let chapter = fixture.chapter(3);
+        let epub_path = download_chapter_as_epub(&chapter, Some(fixture.path().join("manga.epub")))
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(epub_path).unwrap()).unwrap();
+        for i in 1..=3 {
+            assert_eq!(
+                archive
+                    .by_name(&format!("OEBPS/Images/page_{i:03}.png"))
+                    .unwrap()
+                    .size(),
+                4
+            );
+            archive
+                .by_name(&format!("OEBPS/page_{i:03}.xhtml"))
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_pages_as_cbz() {
+        let source = tempfile::tempdir().unwrap();
+        let urls: Vec<String> = ["1.png", "2.jpg"]
+            .iter()
+            .map(|name| {
+                let page = source.path().join(name);
+                std::fs::write(&page, name).unwrap();
+                reqwest::Url::from_file_path(page).unwrap().to_string()
+            })
+            .collect();
+
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = download_chapter_as_cbz(&chapter, Some(dir.path().join("manga.cbz")))
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(zip_path).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().map(|s| s.to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["page_001.png", "page_002.jpg"]);
+        assert_eq!(archive.by_name("page_002.jpg").unwrap().size(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_keep_folder() {
+        let fixture = Fixture::new().await;
+
+        let chapter = fixture.chapter(1);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_keep_folder(true);
+        let zip_path = download_chapter_as_cbz_with_options(
+            &chapter,
+            Some(fixture.path().join("manga.cbz")),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(zip_path.exists());
+        assert!(fixture.path().join("manga").join("page_001.png").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_on_complete() {
+        let fixture = Fixture::new().await;
+
+        let chapter = fixture.chapter(1);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_on_complete(Some(format!(
+            "cp {{path}} {}/{{name}}.copy",
+            fixture.path().display()
+        )));
+        let zip_path = download_chapter_as_cbz_with_options(
+            &chapter,
+            Some(fixture.path().join("chap 1.cbz")),
+            &options,
+        )
+        .await
+        .unwrap();
+        let copy = fixture.path().join(format!("{}.copy", chapter.full_name()));
+        assert_eq!(
+            std::fs::read(copy).unwrap(),
+            std::fs::read(zip_path).unwrap()
+        );
+
+        // a failing command does not fail the download
+        options.set_on_complete(Some("false {path}".to_string()));
+        let result = download_chapter_as_cbz_with_options(
+            &chapter,
+            Some(fixture.path().join("chap 2.cbz")),
+            &options,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flattened() {
+        let fixture = Fixture::new().await;
+
+        let urls: Vec<_> = (1..=2).map(|i| fixture.url(i)).collect();
+        let options = ChapterDownloadOptions::new();
+        for chapter in ["chap 1", "chap 2"] {
+            let chapter = ManualChapter::new("Manga", chapter, urls.clone(), None);
+            let pages = download_chapter_flattened(&chapter, fixture.path(), &options)
+                .await
+                .unwrap();
+            assert_eq!(pages.len(), 2);
+        }
+
+        let mut entries: Vec<_> = std::fs::read_dir(fixture.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        assert!(entries.iter().all(|path| path.is_file()));
+        let names: Vec<_> = entries
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "Manga - chap 1 - page_001.png",
+                "Manga - chap 1 - page_002.png",
+                "Manga - chap 2 - page_001.png",
+                "Manga - chap 2 - page_002.png"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_pages() {
+        let chapter = ManualChapter::new("Manga", "chap 1", [""; 0], None);
+        let dir = tempfile::tempdir().unwrap();
+        let result = download_chapter_as_cbz(&chapter, Some(dir.path().join("manga.cbz"))).await;
+        assert!(matches!(result, Err(ChapterError::NoPages)));
+        assert!(!dir.path().join("manga.cbz").exists());
+    }
+
+    #[tokio::test]
+    async fn test_record_sources() {
+        let fixture = Fixture::new().await;
+
+        let urls: Vec<_> = (1..=3).map(|i| fixture.url(i)).collect();
+        let chapter = ManualChapter::new("Manga", "chap 1", urls.clone(), None);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_record_sources(true);
+        let path = download_chapter_with_options(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+
+        let sources = std::fs::read_to_string(path.join("sources.txt")).unwrap();
+        let expected: String = urls
+            .iter()
+            .enumerate()
+            .map(|(i, url)| format!("page_{:03}.png\t{url}\n", i + 1))
+            .collect();
+        assert_eq!(sources, expected);
+    }
+
+    #[tokio::test]
+    async fn test_max_pages() {
+        let urls = (1..=5).map(|i| format!("https://pages.test/{i}.png"));
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        let dir = tempfile::tempdir().unwrap();
+        let mut options = ChapterDownloadOptions::new();
+        options.set_max_pages(Some(4));
+        let result = download_chapter_with_options(&chapter, Some(dir.path()), &options).await;
+        assert!(matches!(
+            result,
+            Err(ChapterError::TooManyPages { pages: 5, max: 4 })
+        ));
+        // nothing is downloaded
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_page_range() {
+        let fixture = Fixture::new().await;
+
+        let urls: Vec<_> = (1..=10).map(|i| fixture.url(i)).collect();
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_pages(Some(2..4));
+        let pages = download_chapter_pages(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            [
+                fixture.path().join("page_003.png"),
+                fixture.path().join("page_004.png")
+            ]
+        );
+        let mut files: Vec<_> = std::fs::read_dir(fixture.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["page_003.png", "page_004.png"]);
+
+        for range in [4..4, 8..11] {
+            options.set_pages(Some(range));
+            assert!(matches!(
+                download_chapter_pages(&chapter, Some(fixture.path()), &options).await,
+                Err(ChapterError::InvalidPageRange { pages: 10, .. })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allow_partial() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = fixture.chapter(3);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_retries(0);
+        let result = download_chapter_as_cbz_with_options(
+            &chapter,
+            Some(fixture.path().join("a.cbz")),
+            &options,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(ChapterError::PagesDownloadError { .. })
+        ));
+
+        options.set_allow_partial(true);
+        let zip_path = download_chapter_as_cbz_with_options(
+            &chapter,
+            Some(fixture.path().join("b.cbz")),
+            &options,
+        )
+        .await
+        .unwrap();
+        let archive = zip::ZipArchive::new(std::fs::File::open(zip_path).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["page_001.png", "page_003.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_index_page_naming() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/a/cover.jpg"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("page", "image/jpeg"))
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/b/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("page", "image/jpeg"))
+            .mount(&fixture.server)
+            .await;
+
+        let urls = [
+            format!("{}/a/cover.jpg", fixture.server.uri()),
+            format!("{}/b/2", fixture.server.uri()),
+        ];
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_naming(PageNaming::IndexZeroPadded { width: 3 });
+        let pages = download_chapter_pages(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            [
+                fixture.path().join("page_000.jpg"),
+                fixture.path().join("page_001.jpg")
+            ]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        options.set_naming(PageNaming::OriginalName);
+        let pages = download_chapter_pages(&chapter, Some(dir.path()), &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            pages,
+            [dir.path().join("cover.jpg"), dir.path().join("2.jpg")]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_chapter_typed() {
+        let fixture = Fixture::new().await;
+
+        let chapter = fixture.chapter(1);
+        let path = download_chapter_typed::<ManualChapter, _>(&chapter, Some(fixture.path()))
+            .await
+            .unwrap();
+        assert_eq!(path, fixture.path());
+        assert!(path.join("page_001.png").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_chapter_pages() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/jpeg"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("page", "image/jpeg"))
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/png"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("page", "image/png"))
+            .mount(&fixture.server)
+            .await;
+
+        let urls = (1..=12).map(|i| {
+            let kind = if i % 2 == 0 { "png" } else { "jpeg" };
+            format!("{}/{kind}?page={i}", fixture.server.uri())
+        });
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        let pages = download_chapter_pages(&chapter, Some(fixture.path()), &Default::default())
+            .await
+            .unwrap();
+
+        assert_eq!(pages.len(), 12);
+        assert_eq!(pages[0], fixture.path().join("page_001.jpg"));
+        assert_eq!(pages[11], fixture.path().join("page_012.png"));
+        assert!(pages
+            .windows(2)
+            .all(|w| w[0].file_name() < w[1].file_name()));
+        let mut files: Vec<_> = std::fs::read_dir(fixture.path())
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files, pages);
+    }
+
+    #[tokio::test]
+    async fn test_download_thumbnail() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/1"))
+            .and(header("referer", "https://example.com/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("1", "image/jpeg"))
+            .expect(1)
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("2", "image/jpeg"))
+            .expect(0)
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = ManualChapter::new(
+            "Manga",
+            "chap 1",
+            [fixture.url(1), fixture.url(2)],
+            Some(String::from("https://example.com/")),
+        );
+        let thumbnail = download_thumbnail(&chapter, fixture.path()).await.unwrap();
+        assert_eq!(thumbnail, fixture.path().join("page_001.jpg"));
+        assert_eq!(std::fs::read_dir(fixture.path()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed_pages() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = ManualChapter::new("Manga", "chap 1", [fixture.url(1), fixture.url(2)], None);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_retries(0);
+        let result = download_chapter_with_options(&chapter, Some(fixture.path()), &options).await;
+        let error = result.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("failed to download page_002 ("));
+        match error {
+            ChapterError::PagesDownloadError { sources } => {
+                assert_eq!(sources.len(), 1);
+                assert_eq!(sources[0].0, "page_002");
+            }
+            _ => panic!("unexpected error {error}"),
+        }
+
+        fixture.server.reset().await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&fixture.server)
+            .await;
+        fixture.mount_pages().await;
+        options.set_retries(1).set_retry_delay(Duration::ZERO);
+        let path = download_chapter_with_options(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        assert!(path.join("page_002.png").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_stats() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&fixture.server)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("page", "image/png")
+                    .set_delay(Duration::from_millis(10)),
+            )
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = fixture.chapter(3);
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut options = ChapterDownloadOptions::new();
+        options
+            .set_retry_delay(Duration::ZERO)
+            .set_event_sender(sender);
+        download_chapter_with_options(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        drop(options);
+
+        let mut stats = None;
+        while let Some(event) = receiver.recv().await {
+            if let DownloadEvent::ChapterFinished { stats: s } = event {
+                stats = Some(s);
+            }
+        }
+        let stats = stats.unwrap();
+        assert_eq!(stats.pages, 3);
+        assert_eq!(stats.bytes, 3 * "page".len() as u64);
+        assert_eq!(stats.retries, 1);
+        assert_eq!(stats.failures, 0);
+        assert!(!stats.duration.is_zero());
+    }
+
+    #[tokio::test]
+    async fn test_download_chapters_as_volume() {
+        let fixture = Fixture::new().await;
+
+        let chapters: Vec<Box<dyn Chapter>> = vec![
+            Box::new(ManualChapter::new(
+                "Manga & Co",
+                "chap 1",
+                [fixture.url(1), fixture.url(2)],
+                None,
+            )),
+            Box::new(ManualChapter::new(
+                "Manga & Co",
+                "chap 2",
+                [fixture.url(3)],
+                None,
+            )),
+        ];
+        let out = download_chapters_as_volume(&chapters, &fixture.path().join("vol 1.cbz"))
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(out).unwrap()).unwrap();
+        let names: Vec<_> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "ComicInfo.xml",
+                "00000_Manga & Co - chap 1/page_001.png",
+                "00000_Manga & Co - chap 1/page_002.png",
+                "00001_Manga & Co - chap 2/page_001.png",
+            ]
+        );
+        let mut comic_info = String::new();
+        std::io::Read::read_to_string(
+            &mut archive.by_name("ComicInfo.xml").unwrap(),
+            &mut comic_info,
+        )
+        .unwrap();
+        assert!(comic_info.contains("<Series>Manga &amp; Co</Series>"));
+        assert!(comic_info.contains("<Title>chap 1 - chap 2</Title>"));
+        assert!(comic_info.contains("<PageCount>3</PageCount>"));
+        assert!(comic_info.contains("<Notes>Source: manual</Notes>"));
+    }
+
+    #[tokio::test]
+    async fn test_convert_webp() {
+        let mut webp = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut webp),
+                image::ImageFormat::WebP,
+            )
+            .unwrap();
+        let fixture = Fixture::new().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(webp, "image/webp"))
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = fixture.chapter(1);
+        let mut options = ChapterDownloadOptions::new();
+        options.set_convert_webp(true);
+        let path = download_chapter_with_options(&chapter, Some(fixture.path()), &options)
+            .await
+            .unwrap();
+        assert!(path.join("page_001.jpg").exists());
+        assert!(!path.join("page_001.webp").exists());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_download() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("1", "image/png"))
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw("2", "image/png")
+                    .set_delay(Duration::from_secs(30)),
+            )
+            .mount(&fixture.server)
+            .await;
+
+        let chapter = ManualChapter::new("Manga", "chap 1", [fixture.url(1), fixture.url(2)], None);
+        let chapter_dir = fixture.path().join("chap 1");
+        let token = tokio_util::sync::CancellationToken::new();
+        let first_page = chapter_dir.join("page_001.png");
+        let cancel = {
+            let token = token.clone();
+            async move {
+                while !first_page.exists() {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                token.cancel();
+            }
+        };
+
+        let (result, _) = tokio::join!(
+            download_chapter_cancellable(&chapter, Some(&chapter_dir), token),
+            cancel
+        );
+        assert!(matches!(result, Err(ChapterError::Cancelled)));
+        assert!(!chapter_dir.exists());
+    }
+
+    #[tokio::test]
+    async fn test_resume_download() {
+        let fixture = Fixture::new().await;
+        Mock::given(path("/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("1", "image/png"))
+            .expect(0)
+            .mount(&fixture.server)
+            .await;
+        Mock::given(path("/2"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("2", "image/png"))
+            .expect(1)
+            .mount(&fixture.server)
+            .await;
+
+        std::fs::write(fixture.path().join("page_001.png"), "1").unwrap();
+        std::fs::write(
+            fixture.path().join(".manget-progress.json"),
+            r#"{"pages":{"page_001":"page_001.png"}}"#,
+        )
+        .unwrap();
+
+        let chapter = ManualChapter::new("Manga", "chap 1", [fixture.url(1), fixture.url(2)], None);
+        let path = crate::manga::download_chapter(&chapter, Some(fixture.path()))
+            .await
+            .unwrap();
+        assert!(path.join("page_002.png").exists());
+        assert!(!path.join(".manget-progress.json").exists());
+    }
+}
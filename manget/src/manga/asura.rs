@@ -0,0 +1,208 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use reqwest::IntoUrl;
+use scraper::{Html, Selector};
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+const MANGA_SELECTOR: &str = "div.allc > a";
+const CHAPTER_SELECTOR: &str = "h1.entry-title";
+const TITLE_SELECTOR: &str = "title";
+const IMAGE_SELECTOR: &str = "div#readerarea img";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] = &[
+    ("manga (legacy reader)", MANGA_SELECTOR),
+    ("chapter (legacy reader)", CHAPTER_SELECTOR),
+    ("title", TITLE_SELECTOR),
+    ("images (legacy reader)", IMAGE_SELECTOR),
+];
+
+type Result<T> = std::result::Result<T, AsuraError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AsuraError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error("Parse error: {0}")]
+    ParseError(&'static str),
+}
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct AsuraChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    pages: Vec<DownloadItem>,
+}
+
+impl AsuraChapter {
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
+        let url = url.into_url()?;
+        let html = crate::client::fetch_text(url.clone()).await?;
+        let (manga, chapter) = get_title_and_chapter_name(&html)?;
+        let pages = get_pages(&html)?;
+        Ok(Self {
+            url: url.to_string(),
+            manga,
+            chapter,
+            pages,
+        })
+    }
+}
+
+impl Chapter for AsuraChapter {
+    fn source(&self) -> &'static str {
+        "asura"
+    }
+
+    fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+}
+
+/// The legacy reader has breadcrumb elements, the current one only has "{manga} Chapter {n} - Asura Scans" as title.
+fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
+    let doc = Html::parse_document(html);
+    let manga_selector = Selector::parse(MANGA_SELECTOR).unwrap();
+    let chapter_selector = Selector::parse(CHAPTER_SELECTOR).unwrap();
+    if let (Some(manga), Some(chapter)) = (
+        doc.select(&manga_selector).next(),
+        doc.select(&chapter_selector).next(),
+    ) {
+        let manga = manga.text().collect::<String>().trim().to_string();
+        let chapter = chapter
+            .text()
+            .collect::<String>()
+            .replacen(&manga, "", 1)
+            .trim()
+            .to_string();
+        return Ok((manga, chapter));
+    }
+
+    let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
+    let title = doc
+        .select(&title_selector)
+        .next()
+        .ok_or(AsuraError::ParseError("cannot find title"))?
+        .text()
+        .collect::<String>();
+    let title = title.split(" - Asura").next().unwrap_or_default();
+    let (manga, number) = title
+        .rsplit_once(" Chapter ")
+        .ok_or(AsuraError::ParseError("cannot find chapter"))?;
+    Ok((
+        manga.trim().to_string(),
+        format!("Chapter {}", number.trim()),
+    ))
+}
+
+fn get_pages(html: &str) -> Result<Vec<DownloadItem>> {
+    let mut urls = get_reader_area_images(html);
+    if urls.is_empty() {
+        urls = get_next_data_images(html);
+    }
+    if urls.is_empty() {
+        return Err(AsuraError::ParseError("cannot find images"));
+    }
+    Ok(urls
+        .iter()
+        .enumerate()
+        .map(|(i, src)| DownloadItem::new(src, Some(format!("page_{:03}", i + 1))))
+        .collect())
+}
+
+/// Legacy reader, images may be lazy loaded with `data-src`
+fn get_reader_area_images(html: &str) -> Vec<String> {
+    let doc = Html::parse_document(html);
+    let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
+    doc.select(&img_selector)
+        .filter_map(|img| {
+            img.value()
+                .attr("data-src")
+                .or_else(|| img.value().attr("src"))
+        })
+        .map(|src| src.trim().to_string())
+        .filter(|src| src.starts_with("http"))
+        .collect()
+}
+
+/// Current reader, pages are in the (escaped) Next.js payload as {"order":1,"url":"..."}
+fn get_next_data_images(html: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r#"\\?"order\\?":(\d+),\\?"url\\?":\\?"(https://[^"\\]+)\\?""#).unwrap()
+    });
+    let mut pages: Vec<(usize, String)> = pattern
+        .captures_iter(html)
+        .filter_map(|c| Some((c[1].parse().ok()?, c[2].to_string())))
+        .collect();
+    pages.sort_by_key(|(order, _)| *order);
+    pages.dedup_by_key(|(order, _)| *order);
+    pages.into_iter().map(|(_, url)| url).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_legacy_reader() {
+        let html = r#"<html><body>
+        <div class="allc">All chapters are in <a href="https://asuratoon.com/manga/solo-leveling/">Solo Leveling</a></div>
+        <h1 class="entry-title">Solo Leveling Chapter 110</h1>
+        <div id="readerarea">
+            <p><img src="https://asuratoon.com/wp-content/lazy.gif" data-src="https://asuratoon.com/wp-content/uploads/01.webp"></p>
+            <p><img src="https://asuratoon.com/wp-content/uploads/02.webp"></p>
+            <p><img src="https://asuratoon.com/wp-content/uploads/03.jpg"></p>
+        </div></body></html>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (String::from("Solo Leveling"), String::from("Chapter 110"))
+        );
+        let pages = get_pages(html).unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            pages[0].url(),
+            "https://asuratoon.com/wp-content/uploads/01.webp"
+        );
+        assert_eq!(pages[0].name(), Some("page_001"));
+        assert_eq!(pages[2].name(), Some("page_003"));
+    }
+
+    #[test]
+    fn test_next_reader() {
+        let html = r#"<html><head><title>Solo Leveling Chapter 110 - Asura Scans</title></head><body>
+        <script>self.__next_f.push([1,"{\"pages\":[{\"order\":2,\"url\":\"https://gg.asuracomic.net/storage/media/2.webp\"},{\"order\":1,\"url\":\"https://gg.asuracomic.net/storage/media/1.webp\"}]}"])</script>
+        <script>self.__next_f.push([1,"{\"order\":1,\"url\":\"https://gg.asuracomic.net/storage/media/1.webp\"}"])</script>
+        </body></html>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (String::from("Solo Leveling"), String::from("Chapter 110"))
+        );
+        let pages = get_pages(html).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(
+            pages[0].url(),
+            "https://gg.asuracomic.net/storage/media/1.webp"
+        );
+        assert_eq!(
+            pages[1].url(),
+            "https://gg.asuracomic.net/storage/media/2.webp"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use regex::Regex;
+use reqwest::IntoUrl;
+use scraper::{Html, Selector};
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+use super::mangapark;
+
+const MANGA_SELECTOR: &str = "h3.nav-title > a";
+const CHAPTER_SELECTOR: &str = "h6.nav-epis > span";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] =
+    &[("manga", MANGA_SELECTOR), ("chapter", CHAPTER_SELECTOR)];
+
+type Result<T> = std::result::Result<T, BatoToError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatoToError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error(transparent)]
+    CannotDeserialize(#[from] serde_json::Error),
+    #[error("Parse error: {0}")]
+    ParseError(&'static str),
+}
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct BatoToChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    pages: Vec<DownloadItem>,
+}
+
+impl BatoToChapter {
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
+        let url = url.into_url()?;
+        let html = crate::client::fetch_text(url.clone()).await?;
+        let (manga, chapter) = get_title_and_chapter_name(&html)?;
+        let pages = get_chapter_download_info(&html)?;
+        Ok(Self {
+            url: url.to_string(),
+            manga,
+            chapter,
+            pages,
+        })
+    }
+}
+
+impl Chapter for BatoToChapter {
+    fn source(&self) -> &'static str {
+        "batoto"
+    }
+
+    fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+}
+
+/// Bato v3 shares its page layout with MangaPark, the legacy reader has its own.
+fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
+    if let Ok(names) = mangapark::get_title_and_chapter_name(html) {
+        return Ok(names);
+    }
+    let doc = Html::parse_document(html);
+    let title_selector = Selector::parse(MANGA_SELECTOR).unwrap();
+    let chapter_selector = Selector::parse(CHAPTER_SELECTOR).unwrap();
+    let title = doc
+        .select(&title_selector)
+        .next()
+        .ok_or(BatoToError::ParseError("cannot find title"))?
+        .text()
+        .collect::<String>();
+    let chapter = doc
+        .select(&chapter_selector)
+        .next()
+        .ok_or(BatoToError::ParseError("cannot find chapter"))?
+        .text()
+        .collect::<String>();
+    Ok((title.trim().to_string(), chapter.trim().to_string()))
+}
+
+/// The legacy reader embeds the page list as `const imgHttps = [...]` (or `imgHttpLis` in older pages).
+fn get_chapter_download_info(html: &str) -> Result<Vec<DownloadItem>> {
+    if let Ok(pages) = mangapark::get_chapter_download_info(html) {
+        return Ok(pages);
+    }
+    let pattern = Regex::new(r#"const\s+(?:imgHttps|imgHttpLis)\s*=\s*(\[.*?\])\s*;"#).unwrap();
+    let url_list_str = pattern
+        .captures(html)
+        .and_then(|c| c.get(1))
+        .ok_or(BatoToError::ParseError("cannot find image list"))?
+        .as_str();
+    let url_list: Vec<String> = serde_json::from_str(url_list_str)?;
+    Ok(url_list
+        .iter()
+        .enumerate()
+        .map(|(i, url)| DownloadItem::new(url, Some(format!("page_{:03}", i))))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_download_info_v3() {
+        let html = r#"<script type="qwik/json">{"objs":["/title/86663-en-spy-x-family/1539125-ch_1","https://xfs-n03.xfsbb.com/comic/7006/b0e/a1.webp","https://xfs-n03.xfsbb.com/comic/7006/b0e/a2.webp",""]}</script>"#;
+        let pages = get_chapter_download_info(html).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(
+            pages[1].url(),
+            "https://xfs-n03.xfsbb.com/comic/7006/b0e/a2.webp"
+        );
+    }
+
+    #[test]
+    fn test_get_download_info_legacy() {
+        let html = r#"<script>
+        const imgHttps = ["https://xfs-207.batcg.org/comic/7006/b0e/1.jpeg","https://xfs-207.batcg.org/comic/7006/b0e/2.jpeg","https://xfs-207.batcg.org/comic/7006/b0e/3.jpeg"];
+        const batoPass = "";
+        </script>"#;
+        let pages = get_chapter_download_info(html).unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            pages[0].url(),
+            "https://xfs-207.batcg.org/comic/7006/b0e/1.jpeg"
+        );
+        assert_eq!(pages[0].name(), Some("page_000"));
+    }
+
+    #[test]
+    fn test_get_title_and_chapter_name_legacy() {
+        let html = r#"<div><h3 class="nav-title"><a href="/series/86663">Spy x Family</a></h3>
+        <h6 class="nav-epis"><span> Chapter 1 </span></h6></div>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (String::from("Spy x Family"), String::from("Chapter 1"))
+        );
+    }
+}
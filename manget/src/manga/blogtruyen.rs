@@ -3,6 +3,13 @@ use scraper::{Html, Selector};
 
 use crate::{download::DownloadItem, manga::Chapter};
 
+const TITLE_SELECTOR: &str = "header > div.breadcrumbs";
+const IMAGE_SELECTOR: &str = "article#content > img";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] =
+    &[("title", TITLE_SELECTOR), ("images", IMAGE_SELECTOR)];
+
 #[derive(Debug, thiserror::Error)]
 pub enum BlogTruyenError {
     #[error(transparent)]
@@ -12,6 +19,7 @@ pub enum BlogTruyenError {
 }
 
 #[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct BlogTruyenChapter {
     url: String,
     manga: String,
@@ -26,18 +34,10 @@ impl BlogTruyenChapter {
         if url.domain().is_some_and(|x| x.starts_with("m.")) {
             url.set_host(Some("blogtruyenmoi.com")).unwrap();
         }
-        let response = reqwest::Client::new()
-            .get(url.clone())
-            .header("Accept", "*/*")
-            .header("User-Agent", "Manget")
-            .send()
-            .await?
-            .error_for_status()?;
-        // let response = reqwest::get(url.clone()).await?.error_for_status()?;
-        let html_content = response.text().await?;
+        let html_content = crate::client::fetch_vietnamese_text(url.clone()).await?;
 
         let html = Html::parse_document(&html_content);
-        let title_selector = Selector::parse("header > div.breadcrumbs").unwrap();
+        let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
 
         let title_elem = html
             .select(&title_selector)
@@ -57,7 +57,7 @@ impl BlogTruyenChapter {
             .trim()
             .to_string();
 
-        let img_selector = Selector::parse("article#content > img").unwrap();
+        let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
         let mut pages = Vec::new();
         for (i, img_elem) in html.select(&img_selector).enumerate() {
             let src = img_elem.value().attr("src").unwrap();
@@ -79,6 +79,10 @@ impl BlogTruyenChapter {
 }
 
 impl Chapter for BlogTruyenChapter {
+    fn source(&self) -> &'static str {
+        "blogtruyen"
+    }
+
     fn url(&self) -> String {
         self.url.to_string()
     }
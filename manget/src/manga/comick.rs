@@ -0,0 +1,198 @@
+use reqwest::IntoUrl;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+const API_URL: &str = "https://api.comick.io";
+const IMAGE_URL: &str = "https://meo.comick.pictures";
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct ComickChapter {
+    manga_title: String,
+    chapter_title: Option<String>,
+    chapter: Option<String>,
+    volume: Option<String>,
+    url: String,
+    pages: Vec<DownloadItem>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ComickError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error("cannot parse chapter id from '{0}'")]
+    CannotParseChapterId(String),
+    #[error("cannot deserialize the response")]
+    DeserializeError,
+}
+
+#[derive(Debug, PartialEq)]
+struct ChapterInfo {
+    manga_title: String,
+    chapter_title: Option<String>,
+    chapter: Option<String>,
+    volume: Option<String>,
+}
+
+impl ComickChapter {
+    /// Chapter urls look like https://comick.io/comic/{slug}/{chapter_hid}-chapter-{number}-{lang}
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self, ComickError> {
+        let url = url.into_url()?;
+        let mut segments = url
+            .path_segments()
+            .ok_or_else(|| ComickError::CannotParseChapterId(url.to_string()))?;
+        if segments.next() != Some("comic") {
+            return Err(ComickError::CannotParseChapterId(url.to_string()));
+        }
+        let chapter_hid = segments
+            .nth(1)
+            .and_then(|s| s.split('-').next())
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| ComickError::CannotParseChapterId(url.to_string()))?;
+
+        let info =
+            parse_chapter_info(&get_json(&format!("{API_URL}/chapter/{chapter_hid}/")).await?)?;
+        let pages =
+            parse_pages(&get_json(&format!("{API_URL}/chapter/{chapter_hid}/get_images")).await?)?;
+
+        Ok(Self {
+            url: url.to_string(),
+            manga_title: info.manga_title,
+            chapter_title: info.chapter_title,
+            chapter: info.chapter,
+            volume: info.volume,
+            pages,
+        })
+    }
+}
+
+async fn get_json(url: &str) -> Result<String, ComickError> {
+    Ok(crate::client::fetch_text(url).await?)
+}
+
+fn deserialize<'a, T: Deserialize<'a>>(json: &'a str) -> Result<T, ComickError> {
+    serde_json::from_str(json).map_err(|e| {
+        error!("Cannot deserialize {}. Error: {}", json, e);
+        ComickError::DeserializeError
+    })
+}
+
+fn parse_chapter_info(json: &str) -> Result<ChapterInfo, ComickError> {
+    #[derive(Debug, Deserialize)]
+    struct ResponseBody {
+        chapter: ChapterData,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ChapterData {
+        title: Option<String>,
+        chap: Option<String>,
+        vol: Option<String>,
+        md_comics: ComicData,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ComicData {
+        title: String,
+    }
+
+    let body: ResponseBody = deserialize(json)?;
+    Ok(ChapterInfo {
+        manga_title: body.chapter.md_comics.title,
+        chapter_title: body.chapter.title.filter(|t| !t.is_empty()),
+        chapter: body.chapter.chap,
+        volume: body.chapter.vol,
+    })
+}
+
+fn parse_pages(json: &str) -> Result<Vec<DownloadItem>, ComickError> {
+    #[derive(Debug, Deserialize)]
+    struct Image {
+        b2key: String,
+    }
+
+    let images: Vec<Image> = deserialize(json)?;
+    Ok(images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            DownloadItem::new(
+                format!("{IMAGE_URL}/{}", image.b2key),
+                Some(format!("page_{:03}", i + 1)),
+            )
+        })
+        .collect())
+}
+
+impl Chapter for ComickChapter {
+    fn source(&self) -> &'static str {
+        "comick"
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn manga(&self) -> String {
+        self.manga_title.clone()
+    }
+
+    fn chapter(&self) -> String {
+        let chapter = self.chapter.clone().unwrap_or(String::from("0"));
+        match (self.volume.as_ref(), self.chapter_title.as_ref()) {
+            (Some(v), Some(t)) => format!("vol {v} chap {chapter} - {t}"),
+            (Some(v), None) => format!("vol {v} chap {chapter}"),
+            (None, Some(t)) => format!("chap {chapter} - {t}"),
+            (None, None) => format!("chap {chapter}"),
+        }
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_chapter_info() {
+        let json = r#"{
+            "chapter": {
+                "id": 1234567,
+                "chap": "57",
+                "vol": "7",
+                "title": "The Test",
+                "hid": "xYz12AbC",
+                "lang": "en",
+                "md_comics": { "id": 42, "title": "Spy x Family", "slug": "spy-x-family", "hid": "aBc" }
+            },
+            "next": null,
+            "prev": null
+        }"#;
+        assert_eq!(
+            parse_chapter_info(json).unwrap(),
+            ChapterInfo {
+                manga_title: String::from("Spy x Family"),
+                chapter_title: Some(String::from("The Test")),
+                chapter: Some(String::from("57")),
+                volume: Some(String::from("7")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_pages() {
+        let json = r#"[
+            { "h": 1600, "w": 1100, "name": "001.jpg", "s": 312345, "b2key": "0Xk1-abc.jpg" },
+            { "h": 1600, "w": 1100, "name": "002.jpg", "s": 298765, "b2key": "0Xk2-def.jpg" }
+        ]"#;
+        let pages = parse_pages(json).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].url(), "https://meo.comick.pictures/0Xk1-abc.jpg");
+        assert_eq!(pages[1].name(), Some("page_002"));
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Display;
+
+use reqwest::{IntoUrl, Url};
+use scraper::{Html, Selector};
+
+use super::{get_chapter, ChapterError};
+
+/// Number of characters of the page kept in a report
+const SNIPPET_LENGTH: usize = 500;
+
+/// How many elements a selector of the scraper matched
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorCheck {
+    pub name: String,
+    pub selector: String,
+    pub matches: usize,
+}
+
+/// What a scraper finds on a chapter page, to tell a site layout change from a wrong url
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiagnosisReport {
+    pub url: String,
+    /// Site handling the url, none if it is not supported
+    pub site: Option<String>,
+    /// Error of the request to the chapter page
+    pub fetch_error: Option<String>,
+    /// Content of the `<title>` tag
+    pub page_title: Option<String>,
+    pub checks: Vec<SelectorCheck>,
+    /// Number of pages found by the scraper
+    pub page_count: Option<usize>,
+    /// Error of the scraper
+    pub chapter_error: Option<String>,
+    /// Start of the page
+    pub snippet: String,
+}
+
+impl DiagnosisReport {
+    /// Checks whose selector matched nothing
+    pub fn failed_checks(&self) -> impl Iterator<Item = &SelectorCheck> {
+        self.checks.iter().filter(|check| check.matches == 0)
+    }
+}
+
+impl Display for DiagnosisReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "URL: {}", self.url)?;
+        writeln!(
+            f,
+            "Site: {}",
+            self.site.as_deref().unwrap_or("not supported")
+        )?;
+        if let Some(e) = &self.fetch_error {
+            writeln!(f, "Fetch error: {e}")?;
+        }
+        if let Some(title) = &self.page_title {
+            writeln!(f, "Page title: {title}")?;
+        }
+        if !self.checks.is_empty() {
+            writeln!(f, "Selectors:")?;
+        }
+        for check in &self.checks {
+            let status = if check.matches == 0 { "MISSING" } else { "ok" };
+            writeln!(
+                f,
+                "  [{status}] {} `{}`: {} match(es)",
+                check.name, check.selector, check.matches
+            )?;
+        }
+        match (&self.page_count, &self.chapter_error) {
+            (Some(count), _) => writeln!(f, "Pages found: {count}")?,
+            (None, Some(e)) => writeln!(f, "Scraper error: {e}")?,
+            (None, None) => (),
+        }
+        if !self.snippet.is_empty() {
+            writeln!(f, "Snippet:\n{}", self.snippet)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fetch the chapter page and report which selectors of its site scraper match, then resolve the
+/// chapter to report its page count or the scraper error
+pub async fn diagnose(
+    url: impl IntoUrl + Display + Clone,
+) -> Result<DiagnosisReport, ChapterError> {
+    let url = url
+        .clone()
+        .into_url()
+        .map_err(|_| ChapterError::InvalidUrl(url.to_string()))?;
+    let (site, selectors) = match site_selectors(&url) {
+        Some((site, selectors)) => (Some(site), selectors),
+        None => (None, &[][..]),
+    };
+    let mut report = match crate::client::fetch_text(url.clone()).await {
+        Ok(html) => diagnose_html(&html, selectors),
+        Err(e) => DiagnosisReport {
+            fetch_error: Some(e.to_string()),
+            ..Default::default()
+        },
+    };
+    report.url = url.to_string();
+    report.site = site;
+    match get_chapter(url).await {
+        Ok(chapter) => report.page_count = Some(chapter.pages_download_info().len()),
+        Err(e) => report.chapter_error = Some(e.to_string()),
+    }
+    Ok(report)
+}
+
+fn diagnose_html(html: &str, selectors: &[(&str, &str)]) -> DiagnosisReport {
+    let doc = Html::parse_document(html);
+    let title_selector = Selector::parse("title").unwrap();
+    let page_title = doc
+        .select(&title_selector)
+        .next()
+        .map(|title| title.text().collect::<String>().trim().to_string());
+    let checks = selectors
+        .iter()
+        .map(|&(name, selector)| SelectorCheck {
+            name: name.to_string(),
+            selector: selector.to_string(),
+            matches: Selector::parse(selector)
+                .map(|s| doc.select(&s).count())
+                .unwrap_or_default(),
+        })
+        .collect();
+    DiagnosisReport {
+        page_title,
+        checks,
+        snippet: html.trim().chars().take(SNIPPET_LENGTH).collect(),
+        ..Default::default()
+    }
+}
+
+/// The site matching the url in the dispatcher of `get_chapter`, with the selectors of its
+/// scraper. Sites using an api have no selector.
+fn site_selectors(url: &Url) -> Option<(String, &'static [(&'static str, &'static str)])> {
+    super::global_dispatcher()
+        .read()
+        .unwrap()
+        .diagnosis_selectors(url)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_diagnose_missing_images() {
+        let html = r#"<html><head><title>Chapter 1 - Nettruyen</title></head><body>
+            <h1 class="txt-primary">Manga - Chapter 1</h1>
+            <div class="reading-detail"><img src="https://a.com/1.jpg"></div>
+        </body></html>"#;
+        let selectors = [
+            ("title", "h1.txt-primary"),
+            ("images", "div.page-chapter > img"),
+        ];
+        let report = diagnose_html(html, &selectors);
+        assert_eq!(report.page_title.as_deref(), Some("Chapter 1 - Nettruyen"));
+        assert_eq!(report.checks[0].matches, 1);
+        let failed: Vec<_> = report.failed_checks().map(|c| c.name.as_str()).collect();
+        assert_eq!(failed, ["images"]);
+        assert!(report.to_string().contains("[MISSING] images"));
+        assert!(report.snippet.starts_with("<html>"));
+    }
+
+    #[cfg(feature = "site-asura")]
+    #[test]
+    fn test_site_selectors() {
+        let url = Url::parse("https://asuracomic.net/series/a/chapter/1").unwrap();
+        let (site, selectors) = site_selectors(&url).unwrap();
+        assert_eq!(site, "asuracomic.net");
+        assert_eq!(selectors, super::super::asura::DIAGNOSIS_SELECTORS);
+        // a look-alike host is not diagnosed as the site
+        let url = Url::parse("https://asuracomic.net.evil.test/series/a/chapter/1").unwrap();
+        assert!(site_selectors(&url).is_none());
+        let url = Url::parse("https://unknown.com/chapter/1").unwrap();
+        assert!(site_selectors(&url).is_none());
+    }
+}
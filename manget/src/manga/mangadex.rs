@@ -1,17 +1,28 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
 
-use log::error;
-use reqwest::IntoUrl;
+use reqwest::{IntoUrl, Url};
 use serde::Deserialize;
+use tracing::{error, warn};
 
-use crate::{download::DownloadItem, manga::Chapter};
+use crate::{
+    download::DownloadItem,
+    manga::{Chapter, ChapterMeta},
+};
 
 #[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct MangadexChapter {
     manga_title: String,
     chapter_title: Option<String>,
     chapter: Option<String>,
     volume: Option<String>,
+    summary: Option<String>,
+    genres: Vec<String>,
+    scanlation_groups: Vec<String>,
     url: String,
     pages: Vec<DownloadItem>,
 }
@@ -26,38 +37,258 @@ pub enum MangadexError {
     DeserializeError,
     #[error("cannot get manga title")]
     CannotGetManga,
+    /// The chapter is hosted by its scanlator, MangaDex has no page of it
+    #[error("chapter is hosted on an external site: {0}")]
+    ExternalChapter(String),
 }
 
 impl MangadexChapter {
     pub async fn from_url(url: impl IntoUrl) -> Result<Self, MangadexError> {
+        Self::from_url_with_api(url, API_URL).await
+    }
+
+    async fn from_url_with_api(url: impl IntoUrl, api: &str) -> Result<Self, MangadexError> {
         let url = url.into_url()?;
-        let mut segments = url
-            .path_segments()
-            .ok_or_else(|| MangadexError::UrlParseError(url.to_string()))?;
-        if segments.next() != Some("chapter") {
-            return Err(MangadexError::UrlParseError(url.to_string()));
+        let chapter_id = chapter_id(&url)?;
+        let info = get_chapter_info(api, chapter_id).await?;
+        if let Some(external_url) = info.external_url {
+            return Err(MangadexError::ExternalChapter(external_url));
         }
-        let chapter_id = segments
-            .next()
-            .ok_or_else(|| MangadexError::UrlParseError(url.to_string()))?;
-
-        let (manga_title, chapter_title, volume, chapter) = get_chapter_info(chapter_id).await?;
-        let pages = get_chapter_pages(chapter_id).await?;
+        let pages = get_chapter_pages(api, chapter_id).await?;
 
         Ok(Self {
             url: url.to_string(),
-            manga_title,
-            chapter_title,
-            volume,
-            chapter,
+            manga_title: info.manga_title,
+            chapter_title: info.chapter_title,
+            volume: info.volume,
+            chapter: info.chapter,
+            summary: info.summary,
+            genres: info.genres,
+            scanlation_groups: info.scanlation_groups,
             pages,
         })
     }
+
+    /// Names of the groups which translated the chapter
+    pub fn scanlation_groups(&self) -> Vec<String> {
+        self.scanlation_groups.clone()
+    }
 }
 
-async fn get_chapter_info(
-    chapter_id: &str,
-) -> Result<(String, Option<String>, Option<String>, Option<String>), MangadexError> {
+const API_URL: &str = "https://api.mangadex.org";
+/// Global rate limit of the MangaDex api, see <https://api.mangadex.org/docs/2-limitations/>
+const API_REQUESTS_PER_SEC: f64 = 5.0;
+
+/// Token bucket pacing requests to `per_sec`, allowing bursts of `per_sec` requests
+#[derive(Debug)]
+struct RateLimiter {
+    per_sec: f64,
+    /// Available tokens, negative when requests are waiting, and when they were counted
+    state: Mutex<(f64, tokio::time::Instant)>,
+}
+
+impl RateLimiter {
+    fn new(per_sec: f64) -> Self {
+        Self {
+            per_sec,
+            state: Mutex::new((per_sec, tokio::time::Instant::now())),
+        }
+    }
+
+    /// Wait for the turn of one request
+    async fn acquire(&self) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, counted_at) = &mut *state;
+            let now = tokio::time::Instant::now();
+            *tokens =
+                (*tokens + (now - *counted_at).as_secs_f64() * self.per_sec).min(self.per_sec);
+            *counted_at = now;
+            *tokens -= 1.0;
+            Duration::from_secs_f64((-*tokens).max(0.0) / self.per_sec)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Request the api, shared by all chapter resolutions so batches stay under the rate limit
+async fn fetch_api(url: impl IntoUrl) -> reqwest::Result<String> {
+    static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+    LIMITER
+        .get_or_init(|| RateLimiter::new(API_REQUESTS_PER_SEC))
+        .acquire()
+        .await;
+    crate::client::fetch_text(url).await
+}
+
+/// Get the chapter id from an url like "https://mangadex.org/chapter/{id}"
+fn chapter_id(url: &Url) -> Result<&str, MangadexError> {
+    let mut segments = url
+        .path_segments()
+        .ok_or_else(|| MangadexError::UrlParseError(url.to_string()))?;
+    if segments.next() != Some("chapter") {
+        return Err(MangadexError::UrlParseError(url.to_string()));
+    }
+    segments
+        .next()
+        .ok_or_else(|| MangadexError::UrlParseError(url.to_string()))
+}
+
+/// Language of the chapters of a list
+const LIST_LANGUAGE: &str = "en";
+/// Chapters per request of a manga feed, the most allowed by the api
+const FEED_PAGE_SIZE: usize = 500;
+
+/// Get the chapter urls of a manga, like "https://mangadex.org/title/{id}/name", or of every manga
+/// of a custom list, like "https://mangadex.org/list/{id}/name", in the order of the list then of
+/// the chapters
+pub(super) async fn get_list_chapters(url: Url) -> Result<Vec<String>, MangadexError> {
+    list_chapters_with_api(url, API_URL, FEED_PAGE_SIZE).await
+}
+
+async fn list_chapters_with_api(
+    url: Url,
+    api: &str,
+    page_size: usize,
+) -> Result<Vec<String>, MangadexError> {
+    let mut segments = url
+        .path_segments()
+        .ok_or_else(|| MangadexError::UrlParseError(url.to_string()))?;
+    let manga_ids = match (segments.next(), segments.next()) {
+        (Some("title"), Some(manga_id)) => vec![manga_id.to_string()],
+        (Some("list"), Some(list_id)) => {
+            parse_list_manga_ids(&fetch_api(format!("{api}/list/{list_id}")).await?)?
+        }
+        _ => return Err(MangadexError::UrlParseError(url.to_string())),
+    };
+    let mut seen = HashSet::new();
+    let mut urls = Vec::new();
+    for manga_id in manga_ids {
+        let mut offset = 0;
+        loop {
+            let feed = parse_feed(
+                &fetch_api(format!(
+                    "{api}/manga/{manga_id}/feed?translatedLanguage[]={LIST_LANGUAGE}\
+                     &order[volume]=asc&order[chapter]=asc&limit={page_size}&offset={offset}"
+                ))
+                .await?,
+            )?;
+            let received = feed.data.len();
+            for chapter in feed.data {
+                if seen.insert(chapter.id.clone()) {
+                    urls.push(format!("https://mangadex.org/chapter/{}", chapter.id));
+                }
+            }
+            offset += received;
+            if received == 0 || offset >= feed.total {
+                break;
+            }
+        }
+    }
+    Ok(urls)
+}
+
+#[derive(Debug, Deserialize)]
+struct Entity {
+    id: String,
+    #[serde(rename = "type")]
+    entity_type: String,
+}
+
+/// Ids of the manga of a list, without duplicates
+fn parse_list_manga_ids(json: &str) -> Result<Vec<String>, MangadexError> {
+    #[derive(Debug, Deserialize)]
+    struct ResponseBody {
+        data: ListData,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct ListData {
+        relationships: Vec<Entity>,
+    }
+
+    let list: ResponseBody = serde_json::from_str(json).map_err(|e| {
+        error!("{e}");
+        MangadexError::DeserializeError
+    })?;
+    let mut seen = HashSet::new();
+    Ok(list
+        .data
+        .relationships
+        .into_iter()
+        .filter(|entity| entity.entity_type == "manga")
+        .map(|entity| entity.id)
+        .filter(|id| seen.insert(id.clone()))
+        .collect())
+}
+
+/// A page of a manga feed
+#[derive(Debug, Deserialize)]
+struct Feed {
+    data: Vec<Entity>,
+    total: usize,
+}
+
+fn parse_feed(json: &str) -> Result<Feed, MangadexError> {
+    serde_json::from_str(json).map_err(|e| {
+        error!("{e}");
+        MangadexError::DeserializeError
+    })
+}
+
+/// Get the chapter metadata from the chapter api only, without the at-home pages request
+pub(super) async fn get_chapter_metadata(url: Url) -> Result<ChapterMeta, MangadexError> {
+    chapter_metadata_with_api(url, API_URL).await
+}
+
+async fn chapter_metadata_with_api(url: Url, api: &str) -> Result<ChapterMeta, MangadexError> {
+    let info = get_chapter_info(api, chapter_id(&url)?).await?;
+    Ok(ChapterMeta {
+        url: url.to_string(),
+        manga: info.manga_title,
+        chapter: chapter_label(
+            info.volume.as_deref(),
+            info.chapter.as_deref(),
+            info.chapter_title.as_deref(),
+        ),
+        volume: info.volume,
+        page_count: info.pages,
+    })
+}
+
+/// Chapter name like "vol 1 chap 2 - Title"
+fn chapter_label(volume: Option<&str>, chapter: Option<&str>, title: Option<&str>) -> String {
+    let chapter = chapter.unwrap_or("0");
+    match (volume, title) {
+        (Some(v), Some(t)) => format!("vol {v} chap {chapter} - {t}"),
+        (Some(v), None) => format!("vol {v} chap {chapter}"),
+        (None, Some(t)) => format!("chap {chapter} - {t}"),
+        (None, None) => format!("chap {chapter}"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct ChapterInfo {
+    manga_title: String,
+    chapter_title: Option<String>,
+    volume: Option<String>,
+    chapter: Option<String>,
+    summary: Option<String>,
+    genres: Vec<String>,
+    scanlation_groups: Vec<String>,
+    pages: Option<usize>,
+    external_url: Option<String>,
+}
+
+async fn get_chapter_info(api: &str, chapter_id: &str) -> Result<ChapterInfo, MangadexError> {
+    let json = fetch_api(format!(
+        "{api}/chapter/{chapter_id}?includes[]=manga&includes[]=scanlation_group"
+    ))
+    .await?;
+    parse_chapter_info(&json)
+}
+
+fn parse_chapter_info(json: &str) -> Result<ChapterInfo, MangadexError> {
     #[derive(Debug, Deserialize)]
     #[serde(rename_all = "camelCase")]
     struct ResponseBody {
@@ -83,8 +314,29 @@ async fn get_chapter_info(
     #[derive(Debug, Deserialize)]
     #[serde(rename_all = "camelCase")]
     struct RelationshipAttributes {
+        // manga attributes
+        #[serde(default)]
         title: HashMap<String, String>,
         // alt_titles: Vec<HashMap<String, String>>,
+        #[serde(default)]
+        description: HashMap<String, String>,
+        #[serde(default)]
+        tags: Vec<Tag>,
+        // scanlation group attributes
+        name: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Tag {
+        attributes: TagAttributes,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TagAttributes {
+        name: HashMap<String, String>,
+        group: String,
     }
 
     #[derive(Debug, Deserialize)]
@@ -93,86 +345,130 @@ async fn get_chapter_info(
         title: Option<String>,
         volume: Option<String>,
         chapter: Option<String>,
+        pages: Option<usize>,
+        external_url: Option<String>,
     }
 
-    let response = reqwest::Client::new()
-        .get(format!(
-            "https://api.mangadex.org/chapter/{chapter_id}?includes[]=manga"
-        ))
-        .header("User-Agent", "Manget")
-        .send()
-        .await?
-        .error_for_status()?;
-    let json = response.text().await?;
-    let chapter_info: ResponseBody = serde_json::from_str(&json).map_err(|e| {
+    let chapter_info: ResponseBody = serde_json::from_str(json).map_err(|e| {
         error!("Cannot deserialize {}. Error: {}", json, e);
         MangadexError::DeserializeError
     })?;
 
-    let manga_title = chapter_info
+    let manga = chapter_info
         .data
         .relationships
         .iter()
         .find(|x| x.relationship_type == "manga")
         .and_then(|x| x.attributes.as_ref())
-        .and_then(|attr| attr.title.values().next().map(|x| x.to_string()))
         .ok_or(MangadexError::CannotGetManga)?;
+    let manga_title = manga
+        .title
+        .values()
+        .next()
+        .map(|x| x.to_string())
+        .ok_or(MangadexError::CannotGetManga)?;
+    // localized texts, prefer english
+    let localized = |texts: &HashMap<String, String>| {
+        texts
+            .get("en")
+            .or_else(|| texts.values().next())
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    };
+    let scanlation_groups = chapter_info
+        .data
+        .relationships
+        .iter()
+        .filter(|x| x.relationship_type == "scanlation_group")
+        .filter_map(|x| x.attributes.as_ref()?.name.clone())
+        .collect();
+    let genres = manga
+        .tags
+        .iter()
+        .filter(|tag| tag.attributes.group == "genre")
+        .filter_map(|tag| localized(&tag.attributes.name))
+        .collect();
 
-    Ok((
+    Ok(ChapterInfo {
         manga_title,
-        chapter_info.data.attributes.title,
-        chapter_info.data.attributes.volume,
-        chapter_info.data.attributes.chapter,
-    ))
+        chapter_title: chapter_info.data.attributes.title,
+        volume: chapter_info.data.attributes.volume,
+        chapter: chapter_info.data.attributes.chapter,
+        summary: localized(&manga.description),
+        genres,
+        scanlation_groups,
+        pages: chapter_info.data.attributes.pages,
+        external_url: chapter_info
+            .data
+            .attributes
+            .external_url
+            .filter(|url| !url.trim().is_empty()),
+    })
 }
 
-async fn get_chapter_pages(chapter_id: &str) -> Result<Vec<DownloadItem>, MangadexError> {
-    #[derive(Debug, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    struct ResponseBody {
-        base_url: String,
-        chapter: ChapterData,
-    }
-
-    #[derive(Debug, Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    struct ChapterData {
-        hash: String,
-        data_saver: Vec<String>,
-    }
-
-    let response = reqwest::Client::new()
-        .get(format!(
-            "https://api.mangadex.org/at-home/server/{chapter_id}"
-        ))
-        .header("User-Agent", "Manget")
-        .send()
-        .await?
-        .error_for_status()?;
-    let json = response.text().await?;
-    let chapter_json: ResponseBody = serde_json::from_str(&json).map_err(|e| {
-        error!("Cannot deserialize {}. Error: {}", json, e);
-        MangadexError::DeserializeError
-    })?;
-    let pages: Vec<_> = chapter_json
+/// Get the page urls from an at-home server. The server is requested twice since MangaDex
+/// load-balances between nodes, a second node is added as an alternative url of each page.
+async fn get_chapter_pages(
+    api: &str,
+    chapter_id: &str,
+) -> Result<Vec<DownloadItem>, MangadexError> {
+    let url = format!("{api}/at-home/server/{chapter_id}");
+    let at_home = parse_at_home(&fetch_api(&url).await?)?;
+    let alt_base_url = match fetch_api(&url).await {
+        Ok(json) => parse_at_home(&json)
+            .ok()
+            .map(|alt| alt.base_url)
+            .filter(|base_url| *base_url != at_home.base_url),
+        Err(e) => {
+            warn!("Cannot get an alternative at-home server: {e}");
+            None
+        }
+    };
+    let pages: Vec<_> = at_home
         .chapter
         .data_saver
         .iter()
         .enumerate()
         .map(|(index, page_hash)| {
+            let page_url = |base_url: &str| {
+                format!("{base_url}/data-saver/{}/{page_hash}", at_home.chapter.hash)
+            };
             DownloadItem::new(
-                format!(
-                    "{}/data-saver/{}/{}",
-                    chapter_json.base_url, chapter_json.chapter.hash, page_hash
-                ),
+                page_url(&at_home.base_url),
                 Some(&format!("page_{:03}", index + 1)),
             )
+            .add_option_url(alt_base_url.as_deref().map(page_url))
         })
         .collect();
     Ok(pages)
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtHomeResponse {
+    base_url: String,
+    chapter: AtHomeChapter,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtHomeChapter {
+    hash: String,
+    data_saver: Vec<String>,
+}
+
+fn parse_at_home(json: &str) -> Result<AtHomeResponse, MangadexError> {
+    serde_json::from_str(json).map_err(|e| {
+        error!("Cannot deserialize {}. Error: {}", json, e);
+        MangadexError::DeserializeError
+    })
+}
+
 impl Chapter for MangadexChapter {
+    fn source(&self) -> &'static str {
+        "mangadex"
+    }
+
     fn url(&self) -> String {
         self.url.clone()
     }
@@ -182,18 +478,28 @@ impl Chapter for MangadexChapter {
     }
 
     fn chapter(&self) -> String {
-        let chapter = self.chapter.clone().unwrap_or(String::from("0"));
-        match (self.volume.as_ref(), self.chapter_title.as_ref()) {
-            (Some(v), Some(t)) => format!("vol {v} chap {chapter} - {t}"),
-            (Some(v), None) => format!("vol {v} chap {chapter}"),
-            (None, Some(t)) => format!("chap {chapter} - {t}"),
-            (None, None) => format!("chap {chapter}"),
-        }
+        chapter_label(
+            self.volume.as_deref(),
+            self.chapter.as_deref(),
+            self.chapter_title.as_deref(),
+        )
     }
 
     fn pages_download_info(&self) -> &Vec<DownloadItem> {
         &self.pages
     }
+
+    fn summary(&self) -> Option<String> {
+        self.summary.clone()
+    }
+
+    fn genres(&self) -> Vec<String> {
+        self.genres.clone()
+    }
+
+    fn translators(&self) -> Vec<String> {
+        self.scanlation_groups()
+    }
 }
 
 #[cfg(test)]
@@ -209,3 +515,270 @@ async fn test_mangadex() {
     assert!(chapter.chapter().contains("267.5"));
     assert!(!chapter.pages.is_empty());
 }
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_rate_limiter() {
+    let limiter = std::sync::Arc::new(RateLimiter::new(API_REQUESTS_PER_SEC));
+    let start = tokio::time::Instant::now();
+    let requests = (0..12).map(|_| {
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            limiter.acquire().await;
+            start.elapsed()
+        })
+    });
+    let mut times = Vec::new();
+    for request in requests {
+        times.push(request.await.unwrap());
+    }
+    times.sort();
+    // a burst of 5, then one request every 200ms
+    let tolerance = Duration::from_millis(20);
+    for (i, time) in times.iter().enumerate() {
+        let expected = Duration::from_millis(200 * i.saturating_sub(4) as u64);
+        assert!(
+            *time + tolerance >= expected,
+            "request {i} sent at {time:?}"
+        );
+    }
+    // never more than 5 requests in a second after the first burst
+    for window in times[5..].windows(6) {
+        assert!(window[5] - window[0] + tolerance >= Duration::from_secs(1));
+    }
+}
+
+#[cfg(test)]
+const CHAPTER_JSON: &str = r#"{
+        "result": "ok",
+        "data": {
+            "id": "ffb86fb7-3e14-4314-9f84-a5784750bf7a",
+            "type": "chapter",
+            "attributes": { "volume": null, "chapter": "267.5", "title": "Extra", "translatedLanguage": "en", "pages": 2 },
+            "relationships": [
+                { "id": "a1c7c817-4e59-43b7-9365-09675a149a6f", "type": "scanlation_group", "attributes": { "name": "Iruma Scans", "website": null } },
+                { "id": "b2c7c817-4e59-43b7-9365-09675a149a6f", "type": "scanlation_group", "attributes": { "name": "Demon School TL" } },
+                { "id": "c3c7c817-4e59-43b7-9365-09675a149a6f", "type": "user" },
+                {
+                    "id": "d9e3a2a8-2d4f-4b36-9c3c-c5a3b1a2b8a1",
+                    "type": "manga",
+                    "attributes": {
+                        "title": { "en": "Mairimashita! Iruma-kun" },
+                        "description": { "ja": "悪魔学校", "en": "Iruma Suzuki is sold to a demon." },
+                        "tags": [
+                            { "id": "1", "type": "tag", "attributes": { "name": { "en": "Comedy" }, "group": "genre" } },
+                            { "id": "2", "type": "tag", "attributes": { "name": { "en": "School Life" }, "group": "theme" } },
+                            { "id": "3", "type": "tag", "attributes": { "name": { "en": "Fantasy" }, "group": "genre" } }
+                        ]
+                    }
+                }
+            ]
+        }
+    }"#;
+
+#[cfg(test)]
+#[test]
+fn test_parse_chapter_info() {
+    let info = parse_chapter_info(CHAPTER_JSON).unwrap();
+    assert_eq!(info.manga_title, "Mairimashita! Iruma-kun");
+    assert_eq!(info.chapter.as_deref(), Some("267.5"));
+    assert_eq!(info.pages, Some(2));
+    assert_eq!(
+        info.summary.as_deref(),
+        Some("Iruma Suzuki is sold to a demon.")
+    );
+    assert_eq!(info.genres, ["Comedy", "Fantasy"]);
+    assert_eq!(info.scanlation_groups, ["Iruma Scans", "Demon School TL"]);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_chapter_metadata_skips_pages_request() {
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(path("/chapter/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(CHAPTER_JSON))
+        .mount(&server)
+        .await;
+    Mock::given(path("/at-home/server/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(
+            r#"{"baseUrl": "https://uploads.mangadex.org", "chapter": {"hash": "h", "dataSaver": ["1.jpg", "2.jpg"]}}"#,
+        ))
+        .mount(&server)
+        .await;
+    let url = Url::parse("https://mangadex.org/chapter/ffb86fb7").unwrap();
+
+    let chapter = MangadexChapter::from_url_with_api(url.clone(), &server.uri())
+        .await
+        .unwrap();
+    let full_requests = server.received_requests().await.unwrap().len();
+    server.reset().await;
+    Mock::given(path("/chapter/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(CHAPTER_JSON))
+        .mount(&server)
+        .await;
+    let meta = chapter_metadata_with_api(url, &server.uri()).await.unwrap();
+    let meta_requests = server.received_requests().await.unwrap().len();
+
+    assert!(full_requests > 1);
+    assert_eq!(meta_requests, 1);
+    assert_eq!(meta.full_name(), chapter.full_name());
+    assert_eq!(meta.page_count, Some(chapter.pages.len()));
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_external_chapter() {
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let external_url = "https://mangaplus.shueisha.co.jp/viewer/1000486";
+    let json = CHAPTER_JSON.replace(
+        r#""pages": 2 }"#,
+        &format!(r#""pages": 0, "externalUrl": "{external_url}" }}"#),
+    );
+    let server = MockServer::start().await;
+    Mock::given(path("/chapter/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(json))
+        .mount(&server)
+        .await;
+    let url = Url::parse("https://mangadex.org/chapter/ffb86fb7").unwrap();
+
+    let result = MangadexChapter::from_url_with_api(url, &server.uri()).await;
+    assert!(matches!(result, Err(MangadexError::ExternalChapter(url)) if url == external_url));
+    // the at-home server is not asked for pages
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_at_home_failover() {
+    use wiremock::matchers::path;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let at_home = |base_url: &str| {
+        format!(
+            r#"{{"baseUrl": "{base_url}", "chapter": {{"hash": "h", "dataSaver": ["1.jpg", "2.jpg"]}}}}"#
+        )
+    };
+    let server = MockServer::start().await;
+    Mock::given(path("/at-home/server/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(at_home("https://node1.test")))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(path("/at-home/server/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(at_home("https://node2.test")))
+        .mount(&server)
+        .await;
+
+    let pages = get_chapter_pages(&server.uri(), "ffb86fb7").await.unwrap();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[1].url(), "https://node1.test/data-saver/h/2.jpg");
+    for page in &pages {
+        assert!(!page.alt_urls().is_empty());
+        assert!(page.alt_urls()[0].starts_with("https://node2.test/data-saver/h/"));
+    }
+
+    // the same node twice gives no alternative
+    server.reset().await;
+    Mock::given(path("/at-home/server/ffb86fb7"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(at_home("https://node1.test")))
+        .mount(&server)
+        .await;
+    let pages = get_chapter_pages(&server.uri(), "ffb86fb7").await.unwrap();
+    assert!(pages.iter().all(|page| page.alt_urls().is_empty()));
+}
+
+#[cfg(test)]
+const LIST_JSON: &str = r#"{
+    "result": "ok",
+    "response": "entity",
+    "data": {
+        "id": "8018a70b-1492-4f91-a584-7451d7787f7a",
+        "type": "custom_list",
+        "attributes": { "name": "Reading", "visibility": "public", "version": 3 },
+        "relationships": [
+            { "id": "a96676e5-8ae2-425e-b549-7f15dd34a6d8", "type": "manga" },
+            { "id": "d8a959f7-648e-4c8d-8f23-f1f3f8e129f3", "type": "user" },
+            { "id": "32d76d19-8a05-4db0-9fc2-e0b0648fe9d0", "type": "manga" },
+            { "id": "a96676e5-8ae2-425e-b549-7f15dd34a6d8", "type": "manga" }
+        ]
+    }
+}"#;
+
+#[cfg(test)]
+#[test]
+fn test_parse_list_manga_ids() {
+    assert_eq!(
+        parse_list_manga_ids(LIST_JSON).unwrap(),
+        [
+            "a96676e5-8ae2-425e-b549-7f15dd34a6d8",
+            "32d76d19-8a05-4db0-9fc2-e0b0648fe9d0"
+        ]
+    );
+}
+
+#[cfg(test)]
+#[tokio::test]
+async fn test_list_chapters() {
+    use wiremock::matchers::{path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    let feed = |ids: &[&str], total: usize| {
+        let data: Vec<_> = ids
+            .iter()
+            .map(|id| format!(r#"{{"id": "{id}", "type": "chapter"}}"#))
+            .collect();
+        format!(
+            r#"{{"result": "ok", "data": [{}], "total": {total}}}"#,
+            data.join(",")
+        )
+    };
+    let server = MockServer::start().await;
+    Mock::given(path("/list/8018a70b-1492-4f91-a584-7451d7787f7a"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(LIST_JSON))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let first = "/manga/a96676e5-8ae2-425e-b549-7f15dd34a6d8/feed";
+    for (offset, ids) in [("0", ["c1", "c2"].as_slice()), ("2", &["c3"])] {
+        Mock::given(path(first))
+            .and(query_param("offset", offset))
+            .respond_with(ResponseTemplate::new(200).set_body_string(feed(ids, 3)))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    // a chapter in both manga is only listed once, the feed is also the one of the title below
+    Mock::given(path("/manga/32d76d19-8a05-4db0-9fc2-e0b0648fe9d0/feed"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(feed(&["c3", "c4"], 2)))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let url = Url::parse("https://mangadex.org/list/8018a70b-1492-4f91-a584-7451d7787f7a/reading")
+        .unwrap();
+    let urls = list_chapters_with_api(url, &server.uri(), 2).await.unwrap();
+    assert_eq!(
+        urls,
+        ["c1", "c2", "c3", "c4"].map(|id| format!("https://mangadex.org/chapter/{id}"))
+    );
+
+    // a title lists its own feed
+    let url =
+        Url::parse("https://mangadex.org/title/32d76d19-8a05-4db0-9fc2-e0b0648fe9d0/name").unwrap();
+    let urls = list_chapters_with_api(url, &server.uri(), 2).await.unwrap();
+    assert_eq!(
+        urls,
+        ["c3", "c4"].map(|id| format!("https://mangadex.org/chapter/{id}"))
+    );
+
+    let url = Url::parse("https://mangadex.org/chapter/a96676e5").unwrap();
+    assert!(matches!(
+        list_chapters_with_api(url, &server.uri(), 2).await,
+        Err(MangadexError::UrlParseError(_))
+    ));
+}
@@ -0,0 +1,203 @@
+use reqwest::IntoUrl;
+use scraper::{Html, Selector};
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+/// mangakakalot uses `div.breadcrumb`, the manganato reader `div.panel-breadcrumb`
+const BREADCRUMB_SELECTOR: &str = "div.breadcrumb a, div.panel-breadcrumb a";
+const HEADING_SELECTOR: &str = "div.info-top-chapter h2, div.panel-chapter-info-top h1";
+const IMAGE_SELECTOR: &str = "div.container-chapter-reader img";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] = &[
+    ("breadcrumb", BREADCRUMB_SELECTOR),
+    ("chapter heading", HEADING_SELECTOR),
+    ("images", IMAGE_SELECTOR),
+];
+
+type Result<T> = std::result::Result<T, MangakakalotError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MangakakalotError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error("Parse error: {0}")]
+    ParseError(&'static str),
+}
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct MangakakalotChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    referer: String,
+    pages: Vec<DownloadItem>,
+}
+
+impl MangakakalotChapter {
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
+        let url = url.into_url()?;
+        let html = crate::client::fetch_text(url.clone()).await?;
+        let (manga, chapter) = get_title_and_chapter_name(&html)?;
+        let pages = get_pages(&html)?;
+        // images are refused without the referer of the reader
+        let referer = format!("https://{}/", url.domain().unwrap_or_default());
+        Ok(Self {
+            url: url.to_string(),
+            manga,
+            chapter,
+            referer,
+            pages,
+        })
+    }
+}
+
+impl Chapter for MangakakalotChapter {
+    fn source(&self) -> &'static str {
+        "mangakakalot"
+    }
+
+    fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn referer(&self) -> Option<String> {
+        Some(self.referer.clone())
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+}
+
+/// The breadcrumb is "home > manga > chapter", the heading is "{MANGA} CHAPTER {n}: {title}"
+fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
+    let doc = Html::parse_document(html);
+    let breadcrumb_selector = Selector::parse(BREADCRUMB_SELECTOR).unwrap();
+    let crumbs: Vec<String> = doc
+        .select(&breadcrumb_selector)
+        .map(|a| a.text().collect::<String>().trim().to_string())
+        .filter(|text| !text.is_empty())
+        .collect();
+    let manga = crumbs
+        .get(1)
+        .ok_or(MangakakalotError::ParseError("cannot find manga"))?
+        .clone();
+
+    let heading_selector = Selector::parse(HEADING_SELECTOR).unwrap();
+    let heading = doc
+        .select(&heading_selector)
+        .next()
+        .map(|h| h.text().collect::<String>().trim().to_string())
+        .unwrap_or_default();
+    let chapter = match heading
+        .to_lowercase()
+        .find("chapter")
+        .and_then(|i| heading.get(i..))
+    {
+        Some(chapter) => chapter.trim().to_string(),
+        None if !heading.is_empty() => heading,
+        None => crumbs
+            .get(2)
+            .ok_or(MangakakalotError::ParseError("cannot find chapter"))?
+            .clone(),
+    };
+    Ok((manga, chapter))
+}
+
+/// Images may be lazy loaded with `data-src`
+fn get_pages(html: &str) -> Result<Vec<DownloadItem>> {
+    let doc = Html::parse_document(html);
+    let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
+    let urls: Vec<String> = doc
+        .select(&img_selector)
+        .filter_map(|img| {
+            img.value()
+                .attr("data-src")
+                .or_else(|| img.value().attr("src"))
+        })
+        .map(|src| src.trim().to_string())
+        .filter(|src| src.starts_with("http"))
+        .collect();
+    if urls.is_empty() {
+        return Err(MangakakalotError::ParseError("cannot find images"));
+    }
+    Ok(urls
+        .iter()
+        .enumerate()
+        .map(|(i, src)| DownloadItem::new(src, Some(format!("page_{:03}", i + 1))))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mangakakalot_reader() {
+        let html = r#"<html><body>
+        <div class="breadcrumb breadcrumbs bred_doc"><p>
+            <span><a href="https://mangakakalot.com/" title="Read Manga Online"><span>Mangakakalot</span></a></span> &gt;
+            <span><a href="https://mangakakalot.com/manga/solo_leveling" title="Solo Leveling"><span>Solo Leveling</span></a></span> &gt;
+            <span><a href="https://mangakakalot.com/chapter/solo_leveling/chapter_110" title="Chapter 110"><span>Chapter 110</span></a></span>
+        </p></div>
+        <div class="info-top-chapter"><h2>SOLO LEVELING CHAPTER 110 : The Return</h2></div>
+        <div class="container-chapter-reader">
+            <img src="https://v1.mkklcdnv6.com/img/1.jpg" alt="page 1">
+            <img src="https://mangakakalot.com/lazy.gif" data-src="https://v1.mkklcdnv6.com/img/2.webp" alt="page 2">
+        </div></body></html>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (
+                String::from("Solo Leveling"),
+                String::from("CHAPTER 110 : The Return")
+            )
+        );
+        let pages = get_pages(html).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].url(), "https://v1.mkklcdnv6.com/img/1.jpg");
+        assert_eq!(pages[1].url(), "https://v1.mkklcdnv6.com/img/2.webp");
+        assert_eq!(pages[1].name(), Some("page_002"));
+    }
+
+    #[test]
+    fn test_manganato_reader() {
+        let html = r#"<html><body>
+        <div class="panel-breadcrumb">
+            <a class="a-h" href="https://manganato.com/" title="Read Manga Online">Read Manga Online</a>
+            <span>»</span>
+            <a class="a-h" href="https://chapmanganato.to/manga-dr980474" title="Solo Leveling">Solo Leveling</a>
+            <span>»</span>
+            <a class="a-h" href="https://chapmanganato.to/manga-dr980474/chapter-110" title="Chapter 110">Chapter 110</a>
+        </div>
+        <div class="panel-chapter-info-top"><h1>SOLO LEVELING CHAPTER 110</h1></div>
+        <div class="container-chapter-reader">
+            <img src="https://v9.mkklcdnv6tempv4.com/img/1.png" alt="page 1">
+        </div></body></html>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (String::from("Solo Leveling"), String::from("CHAPTER 110"))
+        );
+        let pages = get_pages(html).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].name(), Some("page_001"));
+    }
+
+    #[test]
+    fn test_missing_images() {
+        let html = r#"<div class="container-chapter-reader"></div>"#;
+        assert!(matches!(
+            get_pages(html),
+            Err(MangakakalotError::ParseError(_))
+        ));
+    }
+}
@@ -4,6 +4,13 @@ use scraper::{Html, Selector};
 
 use crate::{download::DownloadItem, manga::Chapter};
 
+const MANGA_SELECTOR: &str = "h3 > a[href^=\"/title\"]";
+const CHAPTER_SELECTOR: &str = "h6 > a[href^=\"/title\"]";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] =
+    &[("manga", MANGA_SELECTOR), ("chapter", CHAPTER_SELECTOR)];
+
 type Result<T> = std::result::Result<T, MangaParkError>;
 
 #[derive(Debug, thiserror::Error)]
@@ -14,6 +21,7 @@ pub enum MangaParkError {
     ParseError,
 }
 
+#[cfg_attr(test, derive(Default))]
 pub struct MangaParkChapter {
     url: String,
     manga_title: String,
@@ -24,11 +32,7 @@ pub struct MangaParkChapter {
 impl MangaParkChapter {
     pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
         let url = url.into_url()?;
-        let html = reqwest::get(url.clone())
-            .await?
-            .error_for_status()?
-            .text()
-            .await?;
+        let html = crate::client::fetch_text(url.clone()).await?;
         let download_items = get_chapter_download_info(&html)?;
         let (title, chapter) = get_title_and_chapter_name(&html)?;
         Ok(Self {
@@ -41,6 +45,10 @@ impl MangaParkChapter {
 }
 
 impl Chapter for MangaParkChapter {
+    fn source(&self) -> &'static str {
+        "mangapark"
+    }
+
     fn url(&self) -> String {
         self.url.to_string()
     }
@@ -58,10 +66,10 @@ impl Chapter for MangaParkChapter {
     }
 }
 
-fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
+pub(super) fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
     let doc = Html::parse_document(html);
-    let title_selector = Selector::parse("h3 > a[href^=\"/title\"]").unwrap();
-    let chapter_selector = Selector::parse("h6 > a[href^=\"/title\"]").unwrap();
+    let title_selector = Selector::parse(MANGA_SELECTOR).unwrap();
+    let chapter_selector = Selector::parse(CHAPTER_SELECTOR).unwrap();
     let title = doc
         .select(&title_selector)
         .next()
@@ -79,7 +87,7 @@ fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
     Ok((title, chapter))
 }
 
-fn get_chapter_download_info(html: &str) -> Result<Vec<DownloadItem>> {
+pub(super) fn get_chapter_download_info(html: &str) -> Result<Vec<DownloadItem>> {
     let pattern = Regex::new(r#""/title/[^"]+",(?:"https://[^"]+\.[a-z]{3,4}",)+"#).unwrap();
     let captured = pattern
         .captures(html)
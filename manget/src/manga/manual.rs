@@ -0,0 +1,116 @@
+use crate::{download::DownloadItem, manga::Chapter};
+
+/// A chapter built from a known list of page urls, without any site scraper.
+#[derive(Debug, Clone)]
+pub struct ManualChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    pages: Vec<DownloadItem>,
+    referer: Option<String>,
+}
+
+impl ManualChapter {
+    /// Pages are named `page_001`, `page_002`... following the order of `urls`
+    pub fn new<T: ToString>(
+        manga: impl ToString,
+        chapter: impl ToString,
+        urls: impl IntoIterator<Item = T>,
+        referer: Option<String>,
+    ) -> Self {
+        let pages = urls
+            .into_iter()
+            .enumerate()
+            .map(|(i, url)| DownloadItem::new(url, Some(format!("page_{:03}", i + 1))))
+            .collect();
+        Self {
+            url: String::new(),
+            manga: manga.to_string(),
+            chapter: chapter.to_string(),
+            pages,
+            referer,
+        }
+    }
+
+    /// Set the url reported by `Chapter::url`, empty by default
+    pub fn with_url(mut self, url: impl ToString) -> Self {
+        self.url = url.to_string();
+        self
+    }
+}
+
+impl Chapter for ManualChapter {
+    fn source(&self) -> &'static str {
+        "manual"
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+
+    fn referer(&self) -> Option<String> {
+        self.referer.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manga::download_chapter_as_cbz;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_page_urls() {
+        let urls = ["https://a.com/1.jpg", "https://a.com/2.jpg"];
+        let chapter = ManualChapter::new("Manga", "chap 1", urls, None);
+        assert_eq!(
+            chapter.page_urls().len(),
+            chapter.pages_download_info().len()
+        );
+        assert_eq!(chapter.page_urls(), urls);
+    }
+
+    #[tokio::test]
+    async fn test_manual_chapter_as_cbz() {
+        let server = MockServer::start().await;
+        for page in ["/1", "/2"] {
+            Mock::given(method("GET"))
+                .and(path(page))
+                .and(header("referer", "https://example.com/"))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(page.as_bytes(), "image/png"))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let chapter = ManualChapter::new(
+            "Manga",
+            "chap 1",
+            [format!("{}/1", server.uri()), format!("{}/2", server.uri())],
+            Some(String::from("https://example.com/")),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = download_chapter_as_cbz(&chapter, Some(dir.path().join("manga.cbz")))
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(zip_path).unwrap()).unwrap();
+        let mut names: Vec<_> = archive.file_names().map(|s| s.to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["page_001.png", "page_002.png"]);
+        assert_eq!(archive.by_name("page_002.png").unwrap().size(), 2);
+    }
+}
@@ -3,6 +3,13 @@ use scraper::{Html, Selector};
 
 use crate::{download::DownloadItem, manga::Chapter};
 
+const TITLE_SELECTOR: &str = "h1.txt-primary";
+const IMAGE_SELECTOR: &str = "div.page-chapter > img";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] =
+    &[("title", TITLE_SELECTOR), ("images", IMAGE_SELECTOR)];
+
 #[derive(Debug, thiserror::Error)]
 pub enum NettruyenError {
     #[error(transparent)]
@@ -12,6 +19,7 @@ pub enum NettruyenError {
 }
 
 #[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct NettruyenChapter {
     url: String,
     manga: String,
@@ -22,16 +30,10 @@ pub struct NettruyenChapter {
 
 impl NettruyenChapter {
     pub async fn from_url(url: impl IntoUrl + Clone + ToString) -> Result<Self, NettruyenError> {
-        let response = reqwest::Client::new()
-            .get(url.clone())
-            .header("User-Agent", "Manget")
-            .send()
-            .await?
-            .error_for_status()?;
-        let html_content = response.text().await?;
+        let html_content = crate::client::fetch_vietnamese_text(url.clone()).await?;
 
         let html = Html::parse_document(&html_content);
-        let title_selector = Selector::parse("h1.txt-primary").unwrap();
+        let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
 
         let h1_elm = html
             .select(&title_selector)
@@ -61,7 +63,7 @@ impl NettruyenChapter {
             }
         }
 
-        let img_selector = Selector::parse("div.page-chapter > img").unwrap();
+        let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
         let mut pages = Vec::new();
         let mut has_referer = true;
         for (i, img_elem) in html.select(&img_selector).enumerate() {
@@ -122,6 +124,10 @@ impl NettruyenChapter {
 }
 
 impl Chapter for NettruyenChapter {
+    fn source(&self) -> &'static str {
+        "nettruyen"
+    }
+
     fn url(&self) -> String {
         self.url.to_string()
     }
@@ -3,6 +3,13 @@ use scraper::{Html, Selector};
 
 use crate::{download::DownloadItem, manga::Chapter};
 
+const TITLE_SELECTOR: &str = "h1.chapter-info";
+const IMAGE_SELECTOR: &str = "div.page-chapter[id^=\"page\"] > img";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] =
+    &[("title", TITLE_SELECTOR), ("images", IMAGE_SELECTOR)];
+
 #[derive(Debug, thiserror::Error)]
 pub enum TopTruyenError {
     #[error(transparent)]
@@ -12,6 +19,7 @@ pub enum TopTruyenError {
 }
 
 #[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct TopTruyenChapter {
     url: String,
     manga: String,
@@ -21,11 +29,10 @@ pub struct TopTruyenChapter {
 
 impl TopTruyenChapter {
     pub async fn from_url(url: impl IntoUrl + Clone + ToString) -> Result<Self, TopTruyenError> {
-        let response = reqwest::get(url.clone()).await?.error_for_status()?;
-        let html_content = response.text().await?;
+        let html_content = crate::client::fetch_vietnamese_text(url.clone()).await?;
 
         let html = Html::parse_document(&html_content);
-        let title_selector = Selector::parse("h1.chapter-info").unwrap();
+        let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
 
         let h1_elm = html
             .select(&title_selector)
@@ -42,7 +49,7 @@ impl TopTruyenChapter {
             .trim_start_matches("- ")
             .to_string();
 
-        let img_selector = Selector::parse("div.page-chapter[id^=\"page\"] > img").unwrap();
+        let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
         let mut pages = Vec::new();
         for (i, img_elem) in html.select(&img_selector).enumerate() {
             let src = img_elem.value().attr("src").unwrap();
@@ -62,6 +69,10 @@ impl TopTruyenChapter {
 }
 
 impl Chapter for TopTruyenChapter {
+    fn source(&self) -> &'static str {
+        "toptruyen"
+    }
+
     fn url(&self) -> String {
         self.url.to_string()
     }
@@ -77,10 +88,6 @@ impl Chapter for TopTruyenChapter {
     fn pages_download_info(&self) -> &Vec<DownloadItem> {
         &self.pages
     }
-
-    fn referer(&self) -> Option<String> {
-        Some("https://www.toptruyen.live/".to_string())
-    }
 }
 
 #[cfg(test)]
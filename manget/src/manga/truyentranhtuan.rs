@@ -6,6 +6,11 @@ use scraper::{Html, Selector};
 
 use crate::{download::DownloadItem, manga::Chapter};
 
+const TITLE_SELECTOR: &str = "div#read-title";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] = &[("title", TITLE_SELECTOR)];
+
 #[derive(Debug, thiserror::Error)]
 pub enum TruyenTranhTuanError {
     #[error(transparent)]
@@ -19,6 +24,7 @@ pub enum TruyenTranhTuanError {
 }
 
 #[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
 pub struct TruyenTranhTuanChapter {
     url: String,
     manga: String,
@@ -30,46 +36,9 @@ impl TruyenTranhTuanChapter {
     pub async fn from_url(
         url: impl IntoUrl + Clone + ToString,
     ) -> Result<Self, TruyenTranhTuanError> {
-        let response = reqwest::get(url.clone()).await?.error_for_status()?;
-        let html_content = response.text().await?;
-
-        let html = Html::parse_document(&html_content);
-        let title_selector = Selector::parse("div#read-title").unwrap();
-
-        let h1_elm = html
-            .select(&title_selector)
-            .next()
-            .ok_or(TruyenTranhTuanError::ParseError("cannot find title"))?;
-        let mut text_iter = h1_elm.text();
-        text_iter.next(); // to ignore newline
-        text_iter.next(); // to ignore newline
-        let manga = text_iter.next().unwrap_or("").trim().to_string();
-        let chapter = text_iter
-            .next()
-            .unwrap_or("")
-            .trim()
-            .trim_start_matches("> ")
-            .to_string();
-
-        let mut pages = Vec::new();
-        let url_list_str = RegexBuilder::new(r#"slides_page_path = (\[.*?\])"#)
-            .multi_line(true)
-            .dot_matches_new_line(true)
-            .build()?
-            .captures(&html_content)
-            .ok_or(TruyenTranhTuanError::ParseError("cannot find chapter list"))?
-            .get(1)
-            .ok_or(TruyenTranhTuanError::ParseError(
-                "cannot parse chapter list",
-            ))?
-            .as_str();
-        let url_list: Vec<String> = serde_json::from_str(url_list_str)?;
-        for page_url in url_list {
-            let file_name = Path::new(&page_url)
-                .file_name()
-                .map(|x| x.to_string_lossy().into_owned());
-            pages.push(DownloadItem::new(&page_url, file_name.as_deref()));
-        }
+        let html_content = crate::client::fetch_vietnamese_text(url.clone()).await?;
+        let (manga, chapter) = get_title_and_chapter_name(&html_content)?;
+        let pages = get_pages(&html_content)?;
         Ok(Self {
             url: url.to_string(),
             manga,
@@ -79,7 +48,56 @@ impl TruyenTranhTuanChapter {
     }
 }
 
+/// The title looks like "\n\n{manga}\n> {chapter}", the texts are split by the manga link
+fn get_title_and_chapter_name(html: &str) -> Result<(String, String), TruyenTranhTuanError> {
+    let doc = Html::parse_document(html);
+    let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
+    let title = doc
+        .select(&title_selector)
+        .next()
+        .ok_or(TruyenTranhTuanError::ParseError("cannot find title"))?;
+    let mut texts = title
+        .text()
+        .map(|t| t.trim().trim_start_matches('>').trim())
+        .filter(|t| !t.is_empty());
+    let manga = texts
+        .next()
+        .ok_or(TruyenTranhTuanError::ParseError("cannot find manga name"))?
+        .to_string();
+    let chapter = texts.next().unwrap_or_default().to_string();
+    Ok((manga, chapter))
+}
+
+/// Pages are in a javascript array, named `slides_page_url_path` on some mirrors
+fn get_pages(html: &str) -> Result<Vec<DownloadItem>, TruyenTranhTuanError> {
+    let url_list_str = RegexBuilder::new(r#"slides_page(?:_url)?_path\s*=\s*(\[.*?\])"#)
+        .multi_line(true)
+        .dot_matches_new_line(true)
+        .build()?
+        .captures(html)
+        .ok_or(TruyenTranhTuanError::ParseError("cannot find chapter list"))?
+        .get(1)
+        .ok_or(TruyenTranhTuanError::ParseError(
+            "cannot parse chapter list",
+        ))?
+        .as_str();
+    let url_list: Vec<String> = serde_json::from_str(url_list_str)?;
+    Ok(url_list
+        .iter()
+        .map(|page_url| {
+            let file_name = Path::new(page_url)
+                .file_name()
+                .map(|x| x.to_string_lossy().into_owned());
+            DownloadItem::new(page_url, file_name.as_deref())
+        })
+        .collect())
+}
+
 impl Chapter for TruyenTranhTuanChapter {
+    fn source(&self) -> &'static str {
+        "truyentranhtuan"
+    }
+
     fn url(&self) -> String {
         self.url.to_string()
     }
@@ -108,3 +126,26 @@ async fn test_build_truyentranhtuan_chapter() {
     assert!(chapter.chapter.contains("1086"));
     assert!(!chapter.pages.is_empty());
 }
+
+#[cfg(test)]
+#[test]
+fn test_alternate_mirror_layout() {
+    let html = r#"<html><body>
+        <div id="read-title"><a href="/one-piece/">One Piece</a> &gt; Chương 1086</div>
+        <script>
+            var slides_page_url_path = ["https://i.truyentranhtuan.com/1.jpg",
+                "https://i.truyentranhtuan.com/2.jpg"];
+        </script>
+    </body></html>"#;
+    assert_eq!(
+        get_title_and_chapter_name(html).unwrap(),
+        (String::from("One Piece"), String::from("Chương 1086"))
+    );
+    let pages = get_pages(html).unwrap();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[1].url(), "https://i.truyentranhtuan.com/2.jpg");
+    assert_eq!(pages[1].name(), Some("2.jpg"));
+
+    let html = r#"<div id="read-title"></div>"#;
+    assert!(get_title_and_chapter_name(html).is_err());
+}
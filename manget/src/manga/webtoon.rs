@@ -0,0 +1,186 @@
+use std::time::Duration;
+
+use reqwest::IntoUrl;
+use scraper::{Html, Selector};
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+const MANGA_SELECTOR: &str = ".subj_info .subj";
+const EPISODE_SELECTOR: &str = ".subj_info .subj_episode";
+const IMAGE_SELECTOR: &str = "#_imageList img[data-url]";
+
+/// Selectors checked by `diagnose`, with what they find
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] = &[
+    ("manga", MANGA_SELECTOR),
+    ("episode", EPISODE_SELECTOR),
+    ("images", IMAGE_SELECTOR),
+];
+
+type Result<T> = std::result::Result<T, WebtoonError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebtoonError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error("Parse error: {0}")]
+    ParseError(&'static str),
+}
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct WebtoonChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    pages: Vec<DownloadItem>,
+}
+
+impl WebtoonChapter {
+    /// Episode urls look like https://www.webtoons.com/en/fantasy/tower-of-god/season-1-ep-1/viewer?title_no=95&episode_no=1
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
+        Self::from_url_with_timeout(url, crate::client::fetch_timeout()).await
+    }
+
+    async fn from_url_with_timeout(url: impl IntoUrl, timeout: Duration) -> Result<Self> {
+        let url = url.into_url()?;
+        let html = crate::client::fetch_text_with(
+            url.clone(),
+            None,
+            crate::client::cookie_for(&url),
+            timeout,
+            crate::client::request_delay(),
+        )
+        .await?;
+        let (manga, chapter) = get_title_and_episode_name(&html)?;
+        let pages = get_pages(&html)?;
+        Ok(Self {
+            url: url.to_string(),
+            manga,
+            chapter,
+            pages,
+        })
+    }
+}
+
+impl Chapter for WebtoonChapter {
+    fn source(&self) -> &'static str {
+        "webtoon"
+    }
+
+    fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+
+    fn referer(&self) -> Option<String> {
+        // images return 403 without it
+        Some("https://www.webtoons.com".to_string())
+    }
+}
+
+fn get_title_and_episode_name(html: &str) -> Result<(String, String)> {
+    let doc = Html::parse_document(html);
+    let title_selector = Selector::parse(MANGA_SELECTOR).unwrap();
+    let episode_selector = Selector::parse(EPISODE_SELECTOR).unwrap();
+    let title = doc
+        .select(&title_selector)
+        .next()
+        .ok_or(WebtoonError::ParseError("cannot find title"))?
+        .text()
+        .collect::<String>();
+    let episode = doc
+        .select(&episode_selector)
+        .next()
+        .ok_or(WebtoonError::ParseError("cannot find episode"))?
+        .text()
+        .collect::<String>();
+    Ok((title.trim().to_string(), episode.trim().to_string()))
+}
+
+fn get_pages(html: &str) -> Result<Vec<DownloadItem>> {
+    let doc = Html::parse_document(html);
+    let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
+    let pages: Vec<_> = doc
+        .select(&img_selector)
+        .filter_map(|img| img.value().attr("data-url"))
+        .enumerate()
+        .map(|(i, src)| DownloadItem::new(src.trim(), Some(format!("page_{:03}", i + 1))))
+        .collect();
+    if pages.is_empty() {
+        return Err(WebtoonError::ParseError("cannot find images"));
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HTML: &str = r#"<html><body>
+    <div class="subj_info">
+        <a href="https://www.webtoons.com/en/fantasy/tower-of-god/list?title_no=95" class="subj" title="Tower of God">Tower of God</a>
+        <span class="ico_arr2"></span>
+        <h1 class="subj_episode" title="[Season 1] Ep. 1">[Season 1] Ep. 1</h1>
+    </div>
+    <div class="viewer_img _img_viewer_area" id="_imageList">
+        <img src="https://webtoons-static.pstatic.net/image/bg_transparency.png" data-url="https://webtoon-phinf.pstatic.net/20130930_1/1.jpg?type=q90" class="_images" alt="image">
+        <img src="https://webtoons-static.pstatic.net/image/bg_transparency.png" data-url="https://webtoon-phinf.pstatic.net/20130930_1/2.jpg?type=q90" class="_images" alt="image">
+        <img src="https://webtoons-static.pstatic.net/image/bg_transparency.png" class="_images" alt="image">
+    </div>
+    </body></html>"#;
+
+    #[test]
+    fn test_get_title_and_episode_name() {
+        assert_eq!(
+            get_title_and_episode_name(HTML).unwrap(),
+            (
+                String::from("Tower of God"),
+                String::from("[Season 1] Ep. 1")
+            )
+        );
+    }
+
+    #[test]
+    fn test_get_pages() {
+        let pages = get_pages(HTML).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(
+            pages[0].url(),
+            "https://webtoon-phinf.pstatic.net/20130930_1/1.jpg?type=q90"
+        );
+        assert_eq!(pages[1].name(), Some("page_002"));
+    }
+
+    #[tokio::test]
+    async fn test_from_url_times_out() {
+        use std::time::Instant;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        Mock::given(wiremock::matchers::method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(30)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let start = Instant::now();
+        let result = WebtoonChapter::from_url_with_timeout(
+            format!("{}/viewer", server.uri()),
+            Duration::from_millis(200),
+        )
+        .await;
+        assert!(matches!(result, Err(WebtoonError::RequestError(e)) if e.is_timeout()));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,146 @@
+use reqwest::IntoUrl;
+use scraper::{Html, Selector};
+
+use crate::{download::DownloadItem, manga::Chapter};
+
+const TITLE_SELECTOR: &str = "title";
+const IMAGE_SELECTOR: &str = "section img";
+
+/// Selectors checked by `diagnose`, with what they find. The images are in the response of the
+/// `/images` endpoint, not in the chapter page.
+pub(super) const DIAGNOSIS_SELECTORS: &[(&str, &str)] = &[("title", TITLE_SELECTOR)];
+
+type Result<T> = std::result::Result<T, WeebCentralError>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WeebCentralError {
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+    #[error("Parse error: {0}")]
+    ParseError(&'static str),
+}
+
+#[derive(Debug)]
+#[cfg_attr(test, derive(Default))]
+pub struct WeebCentralChapter {
+    url: String,
+    manga: String,
+    chapter: String,
+    pages: Vec<DownloadItem>,
+}
+
+impl WeebCentralChapter {
+    pub async fn from_url(url: impl IntoUrl) -> Result<Self> {
+        let url = url.into_url()?;
+        let html = crate::client::fetch_text(url.clone()).await?;
+        let (manga, chapter) = get_title_and_chapter_name(&html)?;
+
+        // images are loaded separately by the reader
+        let images_html = crate::client::fetch_text(format!(
+            "{}/images?is_prev=False&current_page=1&reading_style=long_strip",
+            url.as_str().trim_end_matches('/')
+        ))
+        .await?;
+        let pages = get_pages(&images_html)?;
+
+        Ok(Self {
+            url: url.to_string(),
+            manga,
+            chapter,
+            pages,
+        })
+    }
+}
+
+impl Chapter for WeebCentralChapter {
+    fn source(&self) -> &'static str {
+        "weebcentral"
+    }
+
+    fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    fn manga(&self) -> String {
+        self.manga.clone()
+    }
+
+    fn chapter(&self) -> String {
+        self.chapter.clone()
+    }
+
+    fn pages_download_info(&self) -> &Vec<DownloadItem> {
+        &self.pages
+    }
+}
+
+/// The page title has the form "Chapter 110 | Solo Leveling | Weeb Central"
+fn get_title_and_chapter_name(html: &str) -> Result<(String, String)> {
+    let doc = Html::parse_document(html);
+    let title_selector = Selector::parse(TITLE_SELECTOR).unwrap();
+    let title = doc
+        .select(&title_selector)
+        .next()
+        .ok_or(WeebCentralError::ParseError("cannot find title"))?
+        .text()
+        .collect::<String>();
+    let mut parts = title.split(" | ").map(str::trim);
+    let chapter = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or(WeebCentralError::ParseError("cannot find chapter"))?
+        .to_string();
+    let manga = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or(WeebCentralError::ParseError("cannot find manga"))?
+        .to_string();
+    Ok((manga, chapter))
+}
+
+fn get_pages(images_html: &str) -> Result<Vec<DownloadItem>> {
+    let doc = Html::parse_fragment(images_html);
+    let img_selector = Selector::parse(IMAGE_SELECTOR).unwrap();
+    let pages: Vec<_> = doc
+        .select(&img_selector)
+        .filter_map(|img| img.value().attr("src"))
+        .enumerate()
+        .map(|(i, src)| DownloadItem::new(src, Some(format!("page_{:03}", i + 1))))
+        .collect();
+    if pages.is_empty() {
+        return Err(WeebCentralError::ParseError("cannot find images"));
+    }
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_title_and_chapter_name() {
+        let html = r#"<html><head><title>Chapter 110 | Solo Leveling | Weeb Central</title></head><body></body></html>"#;
+        assert_eq!(
+            get_title_and_chapter_name(html).unwrap(),
+            (String::from("Solo Leveling"), String::from("Chapter 110"))
+        );
+    }
+
+    #[test]
+    fn test_get_pages() {
+        let html = r#"
+<section class="flex-1 flex flex-col pb-4 cursor-pointer" x-data="{ scroll: $persist(false) }">
+    <img src="https://hot.planeptune.us/manga/Solo-Leveling/0110-001.png" alt="Page 1" class="maw-w-full mx-auto">
+    <img src="https://hot.planeptune.us/manga/Solo-Leveling/0110-002.png" alt="Page 2" class="maw-w-full mx-auto">
+    <img src="https://hot.planeptune.us/manga/Solo-Leveling/0110-003.png" alt="Page 3" class="maw-w-full mx-auto">
+</section>"#;
+        let pages = get_pages(html).unwrap();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(
+            pages[0].url(),
+            "https://hot.planeptune.us/manga/Solo-Leveling/0110-001.png"
+        );
+        assert_eq!(pages[2].name(), Some("page_003"));
+        assert!(get_pages("<section></section>").is_err());
+    }
+}
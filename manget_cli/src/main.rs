@@ -1,32 +1,106 @@
 use std::{
+    collections::HashMap,
     fs,
-    io::{Read, Write},
     ops::Deref,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
     time::Duration,
 };
 
 use clap::{Args, Parser};
-use manget::manga::{download_chapter, download_chapter_as_cbz, get_chapter, ChapterError};
+use manget::download::{DownloadEvent, DownloadStats};
+use manget::manga::{
+    chapter_path, diagnose, download_chapter_as_cbz_with_options, download_chapter_as_epub,
+    download_chapter_flattened, download_chapter_with_options, get_chapter, get_list_chapters,
+    pack_folders_to_cbz, parse_chapter_number, verify_cbz, Chapter, ChapterDownloadOptions,
+    ChapterError, ChapterFilter,
+};
+use serde::{Deserialize, Serialize};
 use tower::{
     limit::{ConcurrencyLimitLayer, RateLimitLayer},
     Service, ServiceBuilder, ServiceExt,
 };
-use zip::{write::FileOptions, ZipWriter};
 
 /// Manga download tool
 #[derive(Debug, Parser)]
 #[command(author, version, about)]
 struct DownloadArgs {
     /* Common */
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        help = "output directory, chapters go to a folder named after their manga in the current directory by default"
+    )]
     out_dir: Option<PathBuf>,
     #[arg(long)]
     cbz: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["cbz", "make_cbz"],
+        help = "save the pages directly in the out dir, prefixed with the chapter name, without a chapter folder"
+    )]
+    flatten: bool,
+    #[arg(
+        long = "limit-rate",
+        value_parser = parse_rate,
+        help = "limit the download speed of each chapter, in bytes per second, ex: 500k or 2M"
+    )]
+    limit_rate: Option<usize>,
+    #[arg(
+        long = "group-by-manga",
+        help = "download each chapter into a folder named after its manga"
+    )]
+    group_by_manga: bool,
+    #[arg(
+        long = "allow-partial",
+        help = "still save a chapter when some of its pages cannot be downloaded"
+    )]
+    allow_partial: bool,
+    #[arg(
+        long = "request-delay",
+        value_name = "MS",
+        help = "minimum delay in milliseconds between two requests to the same site when getting chapter info"
+    )]
+    request_delay: Option<u64>,
+    #[arg(
+        long,
+        value_name = "CBZ",
+        conflicts_with_all = ["url", "group_batch"],
+        help = "check that every page of a cbz is a valid image and none is missing"
+    )]
+    verify: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "print one json object per chapter instead of the human readable lines, ex: for scripts"
+    )]
+    json: bool,
+    #[arg(
+        long = "chapters",
+        help = "only download chapters whose number is in the filter, ex: 10-20 or 5,7,9. With a url, the url is a series or list whose chapters are filtered, ex: a MangaDex title or list"
+    )]
+    chapters: Option<ChapterFilter>,
+    #[arg(
+        long,
+        value_name = "CHAPTER",
+        conflicts_with = "new_only",
+        help = "only download chapters numbered above CHAPTER, skipping those already in the out dir. With a url, the url is a series or list, ex: a MangaDex title or list"
+    )]
+    since: Option<f64>,
+    #[arg(
+        long = "new-only",
+        help = "only download chapters numbered above the highest one already in the out dir. With a url, the url is a series or list, ex: a MangaDex title or list"
+    )]
+    new_only: bool,
 
     /* Group URL */
     #[arg(conflicts_with = "group_batch")]
     url: Option<String>,
+    #[arg(
+        long,
+        requires = "url",
+        help = "print what the site scraper finds on the chapter page instead of downloading, useful to report site issues"
+    )]
+    diagnose: bool,
 
     #[command(flatten)]
     batch_args: BatchDownloadArgs,
@@ -35,11 +109,22 @@ struct DownloadArgs {
 #[derive(Debug, Args)]
 #[group(id = "group_batch")]
 struct BatchDownloadArgs {
-    #[arg(short, long)]
+    #[arg(
+        short,
+        long,
+        help = "file of chapter urls, one per line, or a .json/.yaml list of {url, out_name, format}"
+    )]
     file: Option<PathBuf>,
+    #[arg(
+        long = "retry-failed",
+        value_name = "FILE",
+        conflicts_with = "file",
+        help = "download again the chapters listed in a failures file written by a --continue run"
+    )]
+    retry_failed: Option<PathBuf>,
     #[arg(
         long = "continue",
-        help = "continue to download even if there is error"
+        help = "continue to download even if there is error, failed urls are written to failures.txt in the out dir"
     )]
     ignore_error: bool,
     #[arg(long = "cl", help = "concurrency limt")]
@@ -57,175 +142,535 @@ struct BatchDownloadArgs {
     make_cbz: bool,
 }
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Highest chapter number of each folder before the batch, shared by its requests
+type HighestChapters = Arc<Mutex<HashMap<PathBuf, Option<f64>>>>;
+
+/// Failed urls of a batch run, in the out dir
+const FAILURES_FILE: &str = "failures.txt";
+
+/// A chapter downloaded by `download_one`
+struct DownloadedChapter {
+    path: PathBuf,
+    number: Option<f64>,
+    stats: Option<DownloadStats>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ChapterStatus {
+    Downloaded,
+    Skipped,
+    Failed,
+}
+
+/// Result of a chapter printed by `--json`
+#[derive(Debug, Serialize)]
+struct ChapterReport {
+    url: String,
+    status: ChapterStatus,
+    path: Option<PathBuf>,
+    pages: Option<usize>,
+    bytes: Option<u64>,
+    error: Option<String>,
+}
+
+impl ChapterReport {
+    fn new(url: &str, result: &Result<Option<DownloadedChapter>, ChapterError>) -> Self {
+        let mut report = Self {
+            url: url.to_string(),
+            status: ChapterStatus::Skipped,
+            path: None,
+            pages: None,
+            bytes: None,
+            error: None,
+        };
+        match result {
+            Ok(Some(chapter)) => {
+                report.status = ChapterStatus::Downloaded;
+                report.path = Some(chapter.path.clone());
+                report.pages = chapter.stats.as_ref().map(|s| s.pages);
+                report.bytes = chapter.stats.as_ref().map(|s| s.bytes);
+            }
+            Ok(None) => (),
+            Err(e) => {
+                report.status = ChapterStatus::Failed;
+                report.error = Some(e.to_string());
+            }
+        }
+        report
+    }
+}
+
+/// Output of a downloaded chapter
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChapterFormat {
+    Folder,
+    Cbz,
+    Epub,
+}
+
+/// A chapter of the batch file. In a JSON or YAML file, the output name and format can be set
+/// per chapter, ex: `[{"url": "...", "out_name": "chapter 1", "format": "cbz"}]`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct BatchEntry {
+    url: String,
+    out_name: Option<String>,
+    format: Option<ChapterFormat>,
+}
+
+/// Parse the batch file by its extension: JSON, YAML or else one url per line
+fn parse_batch_file(path: &Path, content: &str) -> Result<Vec<BatchEntry>, String> {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("json") => serde_json::from_str(content).map_err(|e| e.to_string()),
+        Some("yaml" | "yml") => serde_yaml::from_str(content).map_err(|e| e.to_string()),
+        _ => Ok(content
+            .trim()
+            .lines()
+            .map(|url| BatchEntry {
+                url: url.to_string(),
+                out_name: None,
+                format: None,
+            })
+            .collect()),
+    }
+}
+
+#[derive(Clone)]
 struct DownloadRequest {
     url: String,
     out_dir: Option<PathBuf>,
-    cbz: bool,
+    out_name: Option<String>,
+    format: ChapterFormat,
+    chapters: Option<ChapterFilter>,
+    limit_rate: Option<usize>,
+    group_by_manga: bool,
+    allow_partial: bool,
+    /// Only download chapters numbered above this one, see `is_new_chapter`
+    since: Option<f64>,
+    /// Set by `--new-only` instead of `since`, which then is the highest chapter of the folder
+    highest_chapters: Option<HighestChapters>,
+    /// Skip chapters already in the out dir
+    skip_existing: bool,
+    /// Print a `ChapterReport` instead of the human readable lines
+    json: bool,
+    /// Save the pages of a folder chapter directly in the out dir
+    flatten: bool,
+}
+
+/// Parse a rate like "500k", "2M" or "1024" to bytes per second
+fn parse_rate(rate: &str) -> Result<usize, String> {
+    let rate = rate.trim();
+    let (number, multiplier) = match rate.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&rate[..i], 1024),
+        Some((i, 'm' | 'M')) => (&rate[..i], 1024 * 1024),
+        _ => (rate, 1),
+    };
+    number
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .map(|n| n * multiplier)
+        .ok_or_else(|| format!("invalid rate '{rate}'"))
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+async fn main() -> Result<(), BoxError> {
     let args = DownloadArgs::parse();
     env_logger::init();
+    if let Some(delay) = args.request_delay {
+        manget::set_request_delay(Duration::from_millis(delay));
+    }
+
+    if let Some(path) = &args.verify {
+        let report = verify_cbz(path)?;
+        print!("{report}");
+        if !report.is_ok() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    match (args.url, args.batch_args.file) {
+    let default_format = if args.cbz {
+        ChapterFormat::Cbz
+    } else {
+        ChapterFormat::Folder
+    };
+    let batch_file = args.batch_args.file.or(args.batch_args.retry_failed);
+    let mut entries = match (args.url, batch_file) {
+        (Some(url), _) if args.diagnose => {
+            print!("{}", diagnose(url).await?);
+            return Ok(());
+        }
+        // selecting chapters reads the url as a series or list
+        (Some(url), _) if args.chapters.is_some() || args.since.is_some() || args.new_only => {
+            series_entries(&url).await?
+        }
         (Some(url), _) => {
             download_one(DownloadRequest {
                 url: url.to_string(),
                 out_dir: args.out_dir.clone(),
-                cbz: args.cbz,
+                out_name: None,
+                format: default_format,
+                chapters: None,
+                limit_rate: args.limit_rate,
+                group_by_manga: args.group_by_manga,
+                allow_partial: args.allow_partial,
+                since: None,
+                highest_chapters: None,
+                skip_existing: false,
+                json: args.json,
+                flatten: args.flatten,
             })
             .await?;
+            return Ok(());
         }
         (_, Some(file)) => {
             let content = fs::read_to_string(&file)?;
+            parse_batch_file(&file, &content)?
+        }
+        (None, None) => unreachable!(),
+    };
+    if args.batch_args.reverse {
+        entries.reverse();
+    }
 
-            let maybe_concurrency_limit = args
-                .batch_args
-                .concurrency_limit
-                .map(ConcurrencyLimitLayer::new);
+    let maybe_concurrency_limit = args
+        .batch_args
+        .concurrency_limit
+        .map(ConcurrencyLimitLayer::new);
 
-            let maybe_rate_limit = if let (Some(max_chap), Some(dur)) =
-                (args.batch_args.max_chap, args.batch_args.duration)
-            {
-                Some(RateLimitLayer::new(max_chap, Duration::from_secs(dur)))
-            } else {
-                None
-            };
+    let maybe_rate_limit =
+        if let (Some(max_chap), Some(dur)) = (args.batch_args.max_chap, args.batch_args.duration) {
+            Some(RateLimitLayer::new(max_chap, Duration::from_secs(dur)))
+        } else {
+            None
+        };
 
-            // Create a download service
-            let mut download_service = ServiceBuilder::new()
-                .option_layer(maybe_concurrency_limit)
-                .option_layer(maybe_rate_limit)
-                .service_fn(download_one);
+    // Create a download service
+    let mut download_service = ServiceBuilder::new()
+        .option_layer(maybe_concurrency_limit)
+        .option_layer(maybe_rate_limit)
+        .service_fn(download_one);
 
-            let urls: Box<dyn Iterator<Item = &str>> = if args.batch_args.reverse {
-                Box::new(content.trim().lines().rev())
-            } else {
-                Box::new(content.trim().lines())
-            };
-
-            let mut downloaded_paths = Vec::new();
-
-            for url in urls {
-                let request = DownloadRequest {
-                    url: url.to_string(),
-                    out_dir: args.out_dir.clone(),
-                    cbz: args.cbz,
-                };
-                match download_service.ready().await?.call(request).await {
-                    Err(e) => {
-                        if !args.batch_args.ignore_error {
-                            return Err(e);
-                        } else {
-                            eprintln!("{e}");
-                        }
-                    }
-                    Ok(path) => downloaded_paths.push(path),
-                }
-            }
+    let skip_existing = args.new_only || args.since.is_some();
+    let template = DownloadRequest {
+        url: String::new(),
+        out_dir: args.out_dir.clone(),
+        out_name: None,
+        format: default_format,
+        chapters: args.chapters.clone(),
+        limit_rate: args.limit_rate,
+        group_by_manga: args.group_by_manga,
+        allow_partial: args.allow_partial,
+        since: args.since,
+        // chapters may go to a folder by manga, known once they are resolved
+        highest_chapters: args.new_only.then(HighestChapters::default),
+        skip_existing,
+        json: args.json,
+        flatten: args.flatten,
+    };
+    let (mut downloaded_chapters, failed_urls) = download_batch(
+        &mut download_service,
+        batch_requests(entries, &template),
+        args.batch_args.ignore_error,
+    )
+    .await?;
+    let failures_path = args
+        .out_dir
+        .as_deref()
+        .unwrap_or(Path::new("."))
+        .join(FAILURES_FILE);
+    write_failures(&failures_path, &failed_urls)?;
 
-            if args.batch_args.make_cbz {
-                println!("Making cbz...");
-                make_cbz(&downloaded_paths)?;
-                println!("Done.");
-            }
+    if args.batch_args.make_cbz {
+        if !args.json {
+            println!("Making cbz...");
+        }
+        make_cbz(&mut downloaded_chapters)?;
+        if !args.json {
+            println!("Done.");
         }
-        (None, None) => unreachable!(),
     }
 
     Ok(())
 }
 
-async fn download_one(request: DownloadRequest) -> Result<PathBuf, ChapterError> {
+/// The chapters of a series or list url, as batch entries
+async fn series_entries(url: &str) -> Result<Vec<BatchEntry>, ChapterError> {
+    Ok(get_list_chapters(url)
+        .await?
+        .into_iter()
+        .map(|url| BatchEntry {
+            url,
+            out_name: None,
+            format: None,
+        })
+        .collect())
+}
+
+/// The requests of the batch `entries`, with the options of `template` unless an entry sets them
+fn batch_requests(entries: Vec<BatchEntry>, template: &DownloadRequest) -> Vec<DownloadRequest> {
+    entries
+        .into_iter()
+        .map(|entry| DownloadRequest {
+            url: entry.url,
+            out_name: entry.out_name,
+            format: entry.format.unwrap_or(template.format),
+            ..template.clone()
+        })
+        .collect()
+}
+
+async fn download_one(request: DownloadRequest) -> Result<Option<DownloadedChapter>, ChapterError> {
+    let url = request.url.clone();
+    let json = request.json;
+    let result = download_request(request).await;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&ChapterReport::new(&url, &result)).unwrap()
+        );
+    }
+    result
+}
+
+async fn download_request(
+    request: DownloadRequest,
+) -> Result<Option<DownloadedChapter>, ChapterError> {
     let url = request.url;
     let out_dir = request.out_dir;
-    let cbz = request.cbz;
 
     let chapter_own = get_chapter(url).await?;
     let chapter = chapter_own.deref();
-    let downloaded_path = if cbz {
-        download_chapter_as_cbz(
-            chapter,
+    if let Some(filter) = &request.chapters {
+        if !chapter.chapter_number().is_some_and(|n| filter.matches(n)) {
+            if !request.json {
+                println!("Skipped: '{}'", chapter.full_name());
+            }
+            return Ok(None);
+        }
+    }
+    let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+    let mut options = ChapterDownloadOptions::new();
+    options
+        .set_event_sender(sender)
+        .set_max_bytes_per_sec(request.limit_rate)
+        .set_group_by_manga(request.group_by_manga)
+        .set_allow_partial(request.allow_partial);
+    let path = output_path(
+        chapter,
+        out_dir.as_deref(),
+        request.out_name.as_deref(),
+        request.group_by_manga,
+    );
+    if request.skip_existing {
+        let target = match request.format {
+            ChapterFormat::Cbz => path.with_extension("cbz"),
+            ChapterFormat::Epub => path.with_extension("epub"),
+            ChapterFormat::Folder => path.clone(),
+        };
+        let since = match &request.highest_chapters {
+            Some(highest_chapters) => {
+                let dir = path
+                    .parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."))
+                    .to_path_buf();
+                *highest_chapters
+                    .lock()
+                    .unwrap()
+                    .entry(dir)
+                    .or_insert_with_key(|dir| highest_chapter_number(dir))
+            }
+            None => request.since,
+        };
+        if !is_new_chapter(chapter.chapter_number(), &target, since) {
+            if !request.json {
+                println!("Skipped: '{}'", chapter.full_name());
+            }
+            return Ok(None);
+        }
+    }
+    let downloaded_path = match request.format {
+        ChapterFormat::Cbz => {
+            download_chapter_as_cbz_with_options(
+                chapter,
+                Some(path.with_extension("cbz")),
+                &options,
+            )
+            .await?
+        }
+        ChapterFormat::Epub => {
+            download_chapter_as_epub(chapter, Some(path.with_extension("epub"))).await?
+        }
+        ChapterFormat::Folder if request.flatten => {
+            let out_dir = out_dir.unwrap_or_else(|| PathBuf::from("."));
+            download_chapter_flattened(chapter, &out_dir, &options).await?;
             out_dir
-                .as_ref()
-                .map(|p| p.join(chapter.full_name()).with_extension("cbz")),
-        )
-        .await?
-    } else {
-        download_chapter(
-            chapter,
-            out_dir.as_ref().map(|p| p.join(chapter.full_name())),
-        )
-        .await?
+        }
+        ChapterFormat::Folder => {
+            download_chapter_with_options(chapter, Some(path), &options).await?
+        }
     };
 
-    println!(
-        "Downloaded: '{}'",
-        downloaded_path.file_name().unwrap().to_string_lossy()
-    );
+    drop(options);
+    let mut stats = None;
+    while let Ok(event) = events.try_recv() {
+        if let DownloadEvent::ChapterFinished { stats: s } = event {
+            stats = Some(s);
+        }
+    }
+    if !request.json {
+        let stats = stats
+            .as_ref()
+            .map(|s| format!(" ({s})"))
+            .unwrap_or_default();
+        let name = match downloaded_path.file_name() {
+            Some(name) if !request.flatten => name.to_string_lossy().to_string(),
+            _ => chapter.full_name(),
+        };
+        println!("Downloaded: '{name}'{stats}");
+    }
+
+    Ok(Some(DownloadedChapter {
+        path: downloaded_path,
+        number: chapter.chapter_number(),
+        stats,
+    }))
+}
 
-    Ok(downloaded_path)
+/// Where a chapter is downloaded, before the extension of its format. Without out dir, chapters
+/// are grouped by manga in the current directory to keep series apart.
+fn output_path(
+    chapter: &dyn Chapter,
+    out_dir: Option<&Path>,
+    out_name: Option<&str>,
+    group_by_manga: bool,
+) -> PathBuf {
+    match (out_name, out_dir) {
+        (Some(name), out_dir) => out_dir.unwrap_or(Path::new("")).join(name),
+        (None, Some(out_dir)) => chapter_path(chapter, out_dir, group_by_manga),
+        (None, None) => chapter_path(chapter, Path::new("."), true),
+    }
 }
 
-fn make_cbz<T1, T2>(paths: T1) -> Result<(), std::io::Error>
+/// Whether a chapter is to be downloaded in incremental mode: it is not at `target` yet and its
+/// number is above `since`. Every missing chapter is new if `since` is not set.
+fn is_new_chapter(number: Option<f64>, target: &Path, since: Option<f64>) -> bool {
+    !target.exists() && since.is_none_or(|since| number.is_some_and(|n| n > since))
+}
+
+/// Highest chapter number of the chapters (folders, cbz or epub files) in `dir`
+fn highest_chapter_number(dir: &Path) -> Option<f64> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            // a folder "chap 5.5" has no extension
+            let name = if path.is_dir() {
+                path.file_name()?
+            } else {
+                path.file_stem()?
+            };
+            parse_chapter_number(&name.to_string_lossy())
+        })
+        .max_by(f64::total_cmp)
+}
+
+/// Download the chapters one after another through `service`. When `ignore_error` is set, the
+/// urls of failed chapters are returned instead of stopping at the first error.
+async fn download_batch<S>(
+    service: &mut S,
+    requests: Vec<DownloadRequest>,
+    ignore_error: bool,
+) -> Result<(Vec<DownloadedChapter>, Vec<String>), BoxError>
 where
-    T1: IntoIterator<Item = T2>,
-    T2: AsRef<Path>,
+    S: Service<DownloadRequest, Response = Option<DownloadedChapter>>,
+    S::Error: Into<BoxError>,
 {
-    let mut new_names = Vec::new();
-    let mut parent = None;
-    for (i, path) in paths.into_iter().enumerate() {
-        let path = path.as_ref();
-        parent = Some(path.parent().unwrap_or(Path::new(".")).to_path_buf());
-        let current_name = path.file_name().unwrap();
-        let new_name = format!("{:05}_{}", i, current_name.to_string_lossy());
-        let new_path = path.with_file_name(&new_name);
-        fs::rename(path, &new_path)?;
-        new_names.push(new_name);
-    }
-
-    if new_names.is_empty() {
-        return Ok(());
+    let mut downloaded_chapters = Vec::new();
+    let mut failed_urls = Vec::new();
+    for request in requests {
+        let url = request.url.clone();
+        let service = service.ready().await.map_err(Into::<BoxError>::into)?;
+        match service.call(request).await.map_err(Into::<BoxError>::into) {
+            Err(e) => {
+                if !ignore_error {
+                    return Err(e);
+                } else {
+                    eprintln!("{e}");
+                    failed_urls.push(url);
+                }
+            }
+            Ok(Some(chapter)) => downloaded_chapters.push(chapter),
+            Ok(None) => (),
+        }
     }
+    Ok((downloaded_chapters, failed_urls))
+}
 
-    let parent = parent.unwrap();
-
-    // zip all folder and create cbz file
-    let file = fs::File::create(parent.join("manga.cbz"))?;
-    let mut writer = ZipWriter::new(file);
-    let mut buf = Vec::new();
-    for name in new_names.iter() {
-        // writer.add_directory(name, FileOptions::default())?;
-        for entry in fs::read_dir(parent.join(name))? {
-            let file_path = entry?.path();
-            if file_path.is_file() {
-                writer.start_file(
-                    format!(
-                        "{}/{}",
-                        name,
-                        file_path.file_name().unwrap().to_string_lossy()
-                    ),
-                    FileOptions::default(),
-                )?;
-
-                fs::File::open(file_path)?.read_to_end(&mut buf)?;
-                writer.write_all(&buf)?;
-                buf.clear();
-            }
+/// Write the failed urls, one per line, to be read by `--retry-failed`. A previous failures
+/// file is removed when everything succeeded.
+fn write_failures(path: &Path, failed_urls: &[String]) -> std::io::Result<()> {
+    if failed_urls.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
         }
-        // The folder has been added to cbz, delete it
-        let _ = fs::remove_dir_all(parent.join(name));
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
     }
+    fs::write(path, failed_urls.join("\n") + "\n")?;
+    eprintln!(
+        "{} chapters failed, retry them with --retry-failed {}",
+        failed_urls.len(),
+        path.display()
+    );
+    Ok(())
+}
 
+fn make_cbz(chapters: &mut [DownloadedChapter]) -> Result<(), std::io::Error> {
+    sort_by_chapter_number(chapters);
+    let paths: Vec<PathBuf> = chapters.iter().map(|c| c.path.clone()).collect();
+    let Some(parent) = paths.last().map(|p| p.parent().unwrap_or(Path::new("."))) else {
+        return Ok(());
+    };
+    pack_folders_to_cbz(&paths, &parent.join("manga.cbz"))?;
+    // The folders have been added to cbz, delete them
+    for path in paths {
+        let _ = fs::remove_dir_all(path);
+    }
     Ok(())
 }
 
+/// Sort chapters by number so the cbz reads in order whatever the order of the input urls.
+/// The input order is kept if any chapter number is unknown.
+fn sort_by_chapter_number(chapters: &mut [DownloadedChapter]) {
+    if chapters.iter().all(|c| c.number.is_some()) {
+        chapters.sort_by(|a, b| {
+            a.number
+                .unwrap_or_default()
+                .total_cmp(&b.number.unwrap_or_default())
+        });
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::path::{Path, PathBuf};
 
-    use crate::{download_one, DownloadRequest};
+    use crate::{
+        batch_requests, download_batch, download_one, highest_chapter_number, is_new_chapter,
+        make_cbz, output_path, parse_batch_file, parse_rate, series_entries, write_failures,
+        BatchEntry, ChapterFormat, ChapterReport, DownloadRequest, DownloadedChapter,
+    };
 
     struct TestResource {
         dir: PathBuf,
@@ -250,9 +695,438 @@ mod test {
         let resource = TestResource::new("test");
         let download_request = DownloadRequest {
             url: "https://mangadex.org/chapter/f9a8fc1f-1fb5-43af-8844-1672ee6c7290".to_string(),
-            cbz: false,
+            format: ChapterFormat::Folder,
             out_dir: Some(resource.dir.clone()),
+            out_name: None,
+            chapters: None,
+            limit_rate: None,
+            group_by_manga: false,
+            allow_partial: false,
+            since: None,
+            highest_chapters: None,
+            skip_existing: false,
+            json: false,
+            flatten: false,
         };
         download_one(download_request).await.unwrap();
     }
+
+    #[test]
+    fn test_make_cbz_sorts_by_chapter_number() {
+        let dir = tempfile::tempdir().unwrap();
+        let chapter = |name: &str, number: Option<f64>| {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(path.join("page_001.png"), name).unwrap();
+            DownloadedChapter {
+                path,
+                number,
+                stats: None,
+            }
+        };
+        let mut chapters = vec![
+            chapter("chap 10", Some(10.0)),
+            chapter("chap 2", Some(2.0)),
+            chapter("chap 2.5", Some(2.5)),
+        ];
+        make_cbz(&mut chapters).unwrap();
+
+        let file = std::fs::File::open(dir.path().join("manga.cbz")).unwrap();
+        let mut archive = zip::ZipArchive::new(file).unwrap();
+        let names: Vec<String> = (0..archive.len())
+            .map(|i| archive.by_index(i).unwrap().name().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "00000_chap 2/page_001.png",
+                "00001_chap 2.5/page_001.png",
+                "00002_chap 10/page_001.png"
+            ]
+        );
+        assert!(!dir.path().join("chap 2").exists());
+
+        // without every number, the input order is kept
+        let mut chapters = vec![chapter("b", Some(2.0)), chapter("a", None)];
+        make_cbz(&mut chapters).unwrap();
+        let file = std::fs::File::open(dir.path().join("manga.cbz")).unwrap();
+        let mut archive = zip::ZipArchive::new(file).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "00000_b/page_001.png");
+    }
+
+    #[test]
+    fn test_parse_rate() {
+        assert_eq!(parse_rate("1000"), Ok(1000));
+        assert_eq!(parse_rate("500k"), Ok(500 * 1024));
+        assert_eq!(parse_rate("2M"), Ok(2 * 1024 * 1024));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+    }
+
+    #[test]
+    fn test_parse_batch_file() {
+        let json = r#"[
+            {"url": "https://mangadex.org/chapter/1", "out_name": "one", "format": "cbz"},
+            {"url": "https://mangadex.org/chapter/2", "format": "epub"},
+            {"url": "https://mangadex.org/chapter/3"}
+        ]"#;
+        let entries = parse_batch_file(Path::new("chapters.json"), json).unwrap();
+        assert_eq!(
+            entries,
+            [
+                BatchEntry {
+                    url: String::from("https://mangadex.org/chapter/1"),
+                    out_name: Some(String::from("one")),
+                    format: Some(ChapterFormat::Cbz),
+                },
+                BatchEntry {
+                    url: String::from("https://mangadex.org/chapter/2"),
+                    out_name: None,
+                    format: Some(ChapterFormat::Epub),
+                },
+                BatchEntry {
+                    url: String::from("https://mangadex.org/chapter/3"),
+                    out_name: None,
+                    format: None,
+                },
+            ]
+        );
+
+        let yaml = "- url: https://mangadex.org/chapter/1\n  out_name: one\n  format: cbz\n";
+        let entries = parse_batch_file(Path::new("chapters.yml"), yaml).unwrap();
+        assert_eq!(entries[0].format, Some(ChapterFormat::Cbz));
+
+        let text = "https://mangadex.org/chapter/1\nhttps://mangadex.org/chapter/2\n";
+        let entries = parse_batch_file(Path::new("chapters.txt"), text).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].url, "https://mangadex.org/chapter/2");
+        assert!(parse_batch_file(Path::new("chapters.json"), "[{}]").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retry_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let request = |url: &str| DownloadRequest {
+            url: url.to_string(),
+            format: ChapterFormat::Folder,
+            out_dir: Some(dir.path().to_path_buf()),
+            out_name: None,
+            chapters: None,
+            limit_rate: None,
+            group_by_manga: false,
+            allow_partial: false,
+            since: None,
+            highest_chapters: None,
+            skip_existing: false,
+            json: false,
+            flatten: false,
+        };
+        let urls = [
+            "https://unsupported-site.test/chapter/1",
+            "https://unsupported-site.test/chapter/2",
+        ];
+        let mut service = tower::service_fn(download_one);
+        let (downloaded, failed_urls) = download_batch(
+            &mut service,
+            urls.iter().map(|url| request(url)).collect(),
+            true,
+        )
+        .await
+        .unwrap();
+        assert!(downloaded.is_empty());
+        assert_eq!(failed_urls, urls);
+        let failures_path = dir.path().join(crate::FAILURES_FILE);
+        write_failures(&failures_path, &failed_urls).unwrap();
+
+        // the rerun reads the failures file as a batch file
+        let content = std::fs::read_to_string(&failures_path).unwrap();
+        let entries = parse_batch_file(&failures_path, &content).unwrap();
+        let requests = entries.iter().map(|entry| request(&entry.url)).collect();
+        let (_, failed_urls) = download_batch(&mut service, requests, true).await.unwrap();
+        assert_eq!(failed_urls, urls);
+        assert!(download_batch(&mut service, vec![request(urls[0])], false)
+            .await
+            .is_err());
+
+        write_failures(&failures_path, &[]).unwrap();
+        assert!(!failures_path.exists());
+    }
+
+    // file urls of local pages are simpler to build on unix
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_json_report() {
+        use manget::manga::{register_site, Chapter, ManualChapter};
+
+        let source = tempfile::tempdir().unwrap();
+        let urls: Vec<String> = (1..=2)
+            .map(|i| {
+                let page = source.path().join(format!("{i}.png"));
+                std::fs::write(&page, "page").unwrap();
+                format!("file://{}", page.display())
+            })
+            .collect();
+        register_site(
+            "json-fixture.test",
+            |url| url.domain() == Some("json-fixture.test"),
+            move |_| {
+                let chapter = ManualChapter::new("Manga", "chap 1", urls.clone(), None);
+                Box::pin(async move { Ok(Box::new(chapter) as Box<dyn Chapter>) })
+            },
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let request = |url: &str| DownloadRequest {
+            url: url.to_string(),
+            format: ChapterFormat::Folder,
+            out_dir: Some(dir.path().to_path_buf()),
+            out_name: None,
+            chapters: None,
+            limit_rate: None,
+            group_by_manga: false,
+            allow_partial: false,
+            since: None,
+            highest_chapters: None,
+            skip_existing: false,
+            json: true,
+            flatten: false,
+        };
+        let report = |url: &str, result| {
+            let json = serde_json::to_string(&ChapterReport::new(url, &result)).unwrap();
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+
+        let url = "https://json-fixture.test/chapter/1";
+        let json = report(url, download_one(request(url)).await);
+        assert_eq!(json["url"], url);
+        assert_eq!(json["status"], "downloaded");
+        assert_eq!(
+            json["path"],
+            dir.path().join("Manga - chap 1").to_string_lossy().as_ref()
+        );
+        assert_eq!(json["pages"], 2);
+        assert_eq!(json["bytes"], 8);
+        assert!(json["error"].is_null());
+
+        let url = "https://unsupported-site.test/chapter/1";
+        let json = report(url, download_one(request(url)).await);
+        assert_eq!(json["status"], "failed");
+        assert!(json["path"].is_null());
+        assert!(json["error"].as_str().is_some_and(|e| !e.is_empty()));
+    }
+
+    /// Register the site `domain` whose chapter urls end with their number, ex:
+    /// "https://{domain}/3" is "Series - chap 3", and return the url of its series listing the
+    /// chapters 1 to `count`
+    #[cfg(unix)]
+    fn fixture_series(domain: &'static str, count: usize, pages: &Path) -> String {
+        use manget::manga::{register_series_site, Chapter, ManualChapter};
+
+        let page = pages.join("1.png");
+        std::fs::write(&page, "page").unwrap();
+        let page_url = format!("file://{}", page.display());
+        register_series_site(
+            domain,
+            move |url| url.domain() == Some(domain),
+            move |url| {
+                let number = url.path().trim_start_matches('/').to_string();
+                let chapter = ManualChapter::new(
+                    "Series",
+                    format!("chap {number}"),
+                    [page_url.clone()],
+                    None,
+                );
+                Box::pin(async move { Ok(Box::new(chapter) as Box<dyn Chapter>) })
+            },
+            move |_| {
+                let urls = (1..=count)
+                    .map(|i| format!("https://{domain}/{i}"))
+                    .collect();
+                Box::pin(async move { Ok(urls) })
+            },
+        );
+        format!("https://{domain}/series")
+    }
+
+    /// The options of a series download into `out_dir`, before any chapter selection
+    #[cfg(unix)]
+    fn series_template(out_dir: &Path) -> DownloadRequest {
+        DownloadRequest {
+            url: String::new(),
+            format: ChapterFormat::Folder,
+            out_dir: Some(out_dir.to_path_buf()),
+            out_name: None,
+            chapters: None,
+            limit_rate: None,
+            group_by_manga: false,
+            allow_partial: false,
+            since: None,
+            highest_chapters: None,
+            skip_existing: false,
+            json: false,
+            flatten: false,
+        }
+    }
+
+    /// Names of the chapters downloaded from `requests`
+    #[cfg(unix)]
+    async fn download_names(requests: Vec<DownloadRequest>) -> Vec<String> {
+        let mut service = tower::service_fn(download_one);
+        let (downloaded, failed_urls) =
+            download_batch(&mut service, requests, false).await.unwrap();
+        assert!(failed_urls.is_empty());
+        downloaded
+            .iter()
+            .map(|c| c.path.file_name().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_series_chapter_filter() {
+        let pages = tempfile::tempdir().unwrap();
+        let url = fixture_series("filter-series.test", 5, pages.path());
+        let entries = series_entries(&url).await.unwrap();
+        assert_eq!(entries.len(), 5);
+        let dir = tempfile::tempdir().unwrap();
+        let mut template = series_template(dir.path());
+        template.chapters = Some("2-3,5".parse().unwrap());
+
+        let names = download_names(batch_requests(entries, &template)).await;
+        assert_eq!(
+            names,
+            ["Series - chap 2", "Series - chap 3", "Series - chap 5"]
+        );
+        assert!(!dir.path().join("Series - chap 1").exists());
+
+        // a site without series listing
+        assert!(matches!(
+            series_entries("https://unsupported-site.test/series").await,
+            Err(manget::manga::ChapterError::SiteNotSupported(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_series_new_chapters() {
+        let pages = tempfile::tempdir().unwrap();
+        let url = fixture_series("new-series.test", 6, pages.path());
+        let entries = series_entries(&url).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        for chapter in ["Series - chap 1", "Series - chap 2", "Series - chap 4"] {
+            std::fs::create_dir(dir.path().join(chapter)).unwrap();
+        }
+
+        // --since 2: the missing chapters above 2, not the existing chapter 4
+        let mut template = series_template(dir.path());
+        template.since = Some(2.0);
+        template.skip_existing = true;
+        let names = download_names(batch_requests(entries.clone(), &template)).await;
+        assert_eq!(
+            names,
+            ["Series - chap 3", "Series - chap 5", "Series - chap 6"]
+        );
+
+        // --new-only: above the highest chapter present, 4 once 5 and 6 are removed
+        std::fs::remove_dir_all(dir.path().join("Series - chap 5")).unwrap();
+        std::fs::remove_dir_all(dir.path().join("Series - chap 6")).unwrap();
+        let mut template = series_template(dir.path());
+        template.highest_chapters = Some(crate::HighestChapters::default());
+        template.skip_existing = true;
+        let names = download_names(batch_requests(entries, &template)).await;
+        assert_eq!(names, ["Series - chap 5", "Series - chap 6"]);
+    }
+
+    #[test]
+    fn test_chapters_with_url() {
+        use clap::Parser;
+
+        let args = crate::DownloadArgs::try_parse_from([
+            "manget_cli",
+            "https://mangadex.org/list/1/name",
+            "--chapters",
+            "10-20",
+        ])
+        .unwrap();
+        assert!(args.url.is_some());
+        assert!(args.chapters.unwrap().matches(15.0));
+
+        let args = crate::DownloadArgs::try_parse_from([
+            "manget_cli",
+            "https://mangadex.org/list/1/name",
+            "--new-only",
+        ])
+        .unwrap();
+        assert!(args.new_only);
+        let args = crate::DownloadArgs::try_parse_from([
+            "manget_cli",
+            "https://mangadex.org/list/1/name",
+            "--since",
+            "12",
+        ])
+        .unwrap();
+        assert_eq!(args.since, Some(12.0));
+    }
+
+    #[test]
+    fn test_output_path() {
+        use manget::manga::{Chapter, ManualChapter};
+
+        let chapter = ManualChapter::new("Manga", "chap 1", [""; 0], None);
+        let name = chapter.full_name();
+        assert_eq!(
+            output_path(&chapter, None, None, false),
+            Path::new(".").join("Manga").join(&name)
+        );
+        // an out dir keeps the chapters in it unless grouped by manga
+        let out_dir = Path::new("out");
+        assert_eq!(
+            output_path(&chapter, Some(out_dir), None, false),
+            out_dir.join(&name)
+        );
+        assert_eq!(
+            output_path(&chapter, Some(out_dir), None, true),
+            out_dir.join("Manga").join(&name)
+        );
+        assert_eq!(
+            output_path(&chapter, None, Some("chapter"), false),
+            Path::new("chapter")
+        );
+    }
+
+    #[test]
+    fn test_new_chapters() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Manga - chap 1.cbz"), "").unwrap();
+        std::fs::write(dir.path().join("Manga - chap 2.cbz"), "").unwrap();
+        std::fs::write(dir.path().join("Manga - chap 4.cbz"), "").unwrap();
+        std::fs::write(dir.path().join("failures.txt"), "").unwrap();
+
+        let chapters = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let select = |since: Option<f64>| {
+            chapters
+                .iter()
+                .copied()
+                .filter(|&n| {
+                    let target = dir.path().join(format!("Manga - chap {n}.cbz"));
+                    is_new_chapter(Some(n), &target, since)
+                })
+                .collect::<Vec<_>>()
+        };
+        // --new-only: above the highest chapter present
+        let highest = highest_chapter_number(dir.path());
+        assert_eq!(highest, Some(4.0));
+        assert_eq!(select(highest), [5.0, 6.0]);
+        // --since 2: the missing chapter 3 too, not the existing chapter 4
+        assert_eq!(select(Some(2.0)), [3.0, 5.0, 6.0]);
+        assert!(!is_new_chapter(
+            None,
+            &dir.path().join("Oneshot.cbz"),
+            Some(2.0)
+        ));
+
+        let empty = tempfile::tempdir().unwrap();
+        assert_eq!(highest_chapter_number(empty.path()), None);
+        assert_eq!(select(None), [3.0, 5.0, 6.0]);
+    }
 }
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use manget::download::DownloadEvent;
+use sanitize_filename::sanitize;
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use crate::{
+    download_chapter_from_url, AppError, DownloadRequest, TempFile, JOB_PERMITS,
+    MAX_UNFINISHED_JOBS,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// Messages streamed by the progress websocket of a job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ProgressMessage {
+    /// Some bytes of a page were received
+    Progress {
+        url: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    /// A page is downloaded
+    Page { url: String, size: u64 },
+    /// The job finished, this is the last message
+    Status {
+        status: JobStatus,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+impl ProgressMessage {
+    fn from_event(event: DownloadEvent) -> Option<Self> {
+        match event {
+            DownloadEvent::Progress {
+                url,
+                downloaded,
+                total,
+            } => Some(ProgressMessage::Progress {
+                url,
+                downloaded,
+                total,
+            }),
+            DownloadEvent::Finished { url, size, .. } => Some(ProgressMessage::Page { url, size }),
+            _ => None,
+        }
+    }
+}
+
+/// Messages buffered for a slow websocket before it misses some
+const PROGRESS_CAPACITY: usize = 256;
+
+/// The downloaded file of a finished job
+#[derive(Debug, Clone)]
+struct JobFile {
+    name: String,
+    content_type: &'static str,
+    file: Arc<TempFile>,
+}
+
+#[derive(Debug)]
+struct Job {
+    status: JobStatus,
+    file: Option<JobFile>,
+    error: Option<String>,
+    finished_at: Option<Instant>,
+    progress: broadcast::Sender<ProgressMessage>,
+}
+
+impl Job {
+    /// The last progress message, once the job is finished
+    fn final_message(&self) -> Option<ProgressMessage> {
+        matches!(self.status, JobStatus::Done | JobStatus::Failed).then(|| {
+            ProgressMessage::Status {
+                status: self.status,
+                error: self.error.clone(),
+            }
+        })
+    }
+}
+
+/// In-memory store of download jobs, shared between handlers and background tasks.
+#[derive(Debug, Clone, Default)]
+pub struct Jobs {
+    inner: Arc<Mutex<HashMap<Uuid, Job>>>,
+}
+
+impl Jobs {
+    /// Run the task returned by `task` in the background and return the id to poll its status.
+    /// The task gets a sender for its download events, streamed by the progress websocket. It
+    /// waits for one of the `JOB_PERMITS`, and fails with `AppError::TooManyJobs` if
+    /// `MAX_UNFINISHED_JOBS` are already pending or running.
+    fn spawn<F, Fut>(&self, task: F) -> Result<Uuid, AppError>
+    where
+        F: FnOnce(mpsc::UnboundedSender<DownloadEvent>) -> Fut,
+        Fut: Future<Output = Result<JobFile, AppError>> + Send + 'static,
+    {
+        let id = Uuid::new_v4();
+        let (progress, _) = broadcast::channel(PROGRESS_CAPACITY);
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let unfinished = inner
+                .values()
+                .filter(|job| job.finished_at.is_none())
+                .count();
+            if unfinished >= MAX_UNFINISHED_JOBS {
+                return Err(AppError::TooManyJobs);
+            }
+            inner.insert(
+                id,
+                Job {
+                    status: JobStatus::Pending,
+                    file: None,
+                    error: None,
+                    finished_at: None,
+                    progress: progress.clone(),
+                },
+            );
+        }
+        let (events, mut events_receiver) = mpsc::unbounded_channel();
+        let task = task(events);
+        let forward = tokio::spawn(async move {
+            while let Some(event) = events_receiver.recv().await {
+                if let Some(message) = ProgressMessage::from_event(event) {
+                    // no websocket may be listening
+                    let _ = progress.send(message);
+                }
+            }
+        });
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let _permit = JOB_PERMITS
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            jobs.update(id, |job| job.status = JobStatus::Running);
+            let result = task.await;
+            // the events end when the task drops its sender
+            let _ = forward.await;
+            jobs.update(id, |job| {
+                match result {
+                    Ok(file) => {
+                        job.status = JobStatus::Done;
+                        job.file = Some(file);
+                    }
+                    Err(e) => {
+                        job.status = JobStatus::Failed;
+                        job.error = Some(e.to_string());
+                    }
+                }
+                job.finished_at = Some(Instant::now());
+                if let Some(message) = job.final_message() {
+                    let _ = job.progress.send(message);
+                }
+            });
+        });
+        Ok(id)
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) {
+        if let Some(job) = self.inner.lock().unwrap().get_mut(&id) {
+            f(job);
+        }
+    }
+
+    /// Remove jobs that finished more than `ttl` ago.
+    fn remove_expired(&self, ttl: Duration) {
+        self.inner
+            .lock()
+            .unwrap()
+            .retain(|_, job| job.finished_at.is_none_or(|t| t.elapsed() < ttl));
+    }
+
+    /// Periodically remove finished jobs older than `ttl`.
+    pub fn spawn_cleanup(&self, ttl: Duration) {
+        let jobs = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ttl / 2);
+            loop {
+                interval.tick().await;
+                jobs.remove_expired(ttl);
+            }
+        });
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JobResponseBody {
+    id: Uuid,
+    status: JobStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    download_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+pub async fn create_job(
+    State(jobs): State<Jobs>,
+    Json(DownloadRequest { url, format }): Json<DownloadRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let id = jobs.spawn(|events| async move {
+        let chapter = download_chapter_from_url(&url, format, Some(events)).await?;
+        Ok(JobFile {
+            name: chapter.file_name.clone(),
+            content_type: format.content_type(),
+            file: Arc::new(chapter.into_file().await?),
+        })
+    })?;
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(JobResponseBody {
+            id,
+            status: JobStatus::Pending,
+            download_url: None,
+            error: None,
+        }),
+    ))
+}
+
+pub async fn job_status(
+    State(jobs): State<Jobs>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let inner = jobs.inner.lock().unwrap();
+    let job = inner.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(JobResponseBody {
+        id,
+        status: job.status,
+        download_url: (job.status == JobStatus::Done).then(|| format!("/jobs/{id}/download")),
+        error: job.error.clone(),
+    }))
+}
+
+pub async fn job_download(
+    State(jobs): State<Jobs>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    // only the handle is cloned, the file is read once the lock is released
+    let file = jobs
+        .inner
+        .lock()
+        .unwrap()
+        .get(&id)
+        .and_then(|job| job.file.clone())
+        .ok_or(AppError::JobNotFound(id))?;
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!("attachment; filename={}", sanitize(file.name)))?,
+    );
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(file.content_type),
+    );
+    Ok((headers, file.file.into_body().await?))
+}
+
+/// Stream the progress of a job as JSON messages, closing after the final status
+pub async fn job_progress(
+    ws: WebSocketUpgrade,
+    State(jobs): State<Jobs>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppError> {
+    let (receiver, final_message) = {
+        let inner = jobs.inner.lock().unwrap();
+        let job = inner.get(&id).ok_or(AppError::JobNotFound(id))?;
+        (job.progress.subscribe(), job.final_message())
+    };
+    Ok(ws.on_upgrade(move |socket| stream_progress(socket, receiver, final_message)))
+}
+
+async fn stream_progress(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<ProgressMessage>,
+    final_message: Option<ProgressMessage>,
+) {
+    let send = |message: &ProgressMessage| {
+        Message::Text(serde_json::to_string(message).unwrap_or_default())
+    };
+    if let Some(message) = final_message {
+        let _ = socket.send(send(&message)).await;
+    } else {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => {
+                    if socket.send(send(&message)).await.is_err() {
+                        return;
+                    }
+                    if matches!(message, ProgressMessage::Status { .. }) {
+                        break;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::app;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    async fn get_json(jobs: &Jobs, uri: &str) -> serde_json::Value {
+        let response = app(jobs.clone())
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// The file of a job done with `data`
+    fn job_file(data: &[u8]) -> JobFile {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chapter.zip");
+        std::fs::write(&path, data).unwrap();
+        JobFile {
+            name: String::from("chapter.cbz"),
+            content_type: "application/vnd.comicbook+zip",
+            file: Arc::new(TempFile { path, _dir: dir }),
+        }
+    }
+
+    async fn poll_until_finished(jobs: &Jobs, id: &str) -> serde_json::Value {
+        loop {
+            let job = get_json(jobs, &format!("/jobs/{id}")).await;
+            if job["status"] == "done" || job["status"] == "failed" {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_job_done() {
+        let jobs = Jobs::default();
+        let id = jobs
+            .spawn(|_| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok(job_file(&[1, 2, 3]))
+            })
+            .unwrap();
+
+        let job = poll_until_finished(&jobs, &id.to_string()).await;
+        assert_eq!(job["status"], "done");
+        let download_url = job["download_url"].as_str().unwrap();
+        let response = app(jobs.clone())
+            .oneshot(Request::get(download_url).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/vnd.comicbook+zip"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], &[1, 2, 3]);
+
+        jobs.remove_expired(Duration::ZERO);
+        assert!(jobs.inner.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_too_many_jobs() {
+        let jobs = Jobs::default();
+        let (finish, finished) = tokio::sync::watch::channel(false);
+        let spawn = || {
+            let mut finished = finished.clone();
+            jobs.spawn(|_| async move {
+                let _ = finished.wait_for(|finished| *finished).await;
+                Ok(job_file(&[1]))
+            })
+        };
+        let ids: Vec<_> = (0..MAX_UNFINISHED_JOBS).map(|_| spawn().unwrap()).collect();
+        assert!(matches!(spawn(), Err(AppError::TooManyJobs)));
+
+        finish.send(true).unwrap();
+        for id in ids {
+            poll_until_finished(&jobs, &id.to_string()).await;
+        }
+        // finished jobs make room for new ones
+        assert!(spawn().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_job_downloads_chapter() {
+        use crate::test::{fixture_pages, register_fixture_site};
+
+        let pages = tempfile::tempdir().unwrap();
+        register_fixture_site(
+            "job-fixture.test",
+            fixture_pages(pages.path(), &["1.png", "2.png"]),
+        );
+        let jobs = Jobs::default();
+        let request = Request::post("/jobs")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"url":"https://job-fixture.test/1","format":"raw-zip"}"#,
+            ))
+            .unwrap();
+        let response = app(jobs.clone()).oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let job = poll_until_finished(&jobs, job["id"].as_str().unwrap()).await;
+        assert_eq!(job["status"], "done");
+        let response = app(jobs.clone())
+            .oneshot(
+                Request::get(job["download_url"].as_str().unwrap())
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/zip");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=Fixture - chap 1.zip"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let archive = zip::ZipArchive::new(std::io::Cursor::new(body)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["page_001.png", "page_002.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_job_failed() {
+        let jobs = Jobs::default();
+        let request = Request::post("/jobs")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"url":"https://example.com/chapter/1"}"#))
+            .unwrap();
+        let response = app(jobs.clone()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let job = poll_until_finished(&jobs, job["id"].as_str().unwrap()).await;
+        assert_eq!(job["status"], "failed");
+        assert!(job["error"].as_str().unwrap().contains("not supported"));
+    }
+
+    #[tokio::test]
+    async fn test_job_progress_websocket() {
+        use futures::StreamExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let jobs = Jobs::default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = app(jobs.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let (started, start) = tokio::sync::oneshot::channel::<()>();
+        let id = jobs
+            .spawn(|events| async move {
+                // wait for the websocket to be connected
+                let _ = start.await;
+                for url in ["https://a.test/1.png", "https://a.test/2.png"] {
+                    events
+                        .send(DownloadEvent::Progress {
+                            url: url.to_string(),
+                            downloaded: 4,
+                            total: Some(4),
+                        })
+                        .unwrap();
+                    events
+                        .send(DownloadEvent::Finished {
+                            url: url.to_string(),
+                            path: std::path::PathBuf::from("page.png"),
+                            size: 4,
+                        })
+                        .unwrap();
+                }
+                Ok(job_file(&[1, 2, 3]))
+            })
+            .unwrap();
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{address}/ws/{id}"))
+            .await
+            .unwrap();
+        started.send(()).unwrap();
+        let mut messages = Vec::new();
+        while let Some(message) = socket.next().await {
+            match message.unwrap() {
+                Message::Text(text) => {
+                    messages.push(serde_json::from_str::<serde_json::Value>(&text).unwrap())
+                }
+                Message::Close(_) => break,
+                _ => (),
+            }
+        }
+        let types: Vec<_> = messages
+            .iter()
+            .map(|m| m["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, ["progress", "page", "progress", "page", "status"]);
+        assert_eq!(messages[1]["url"], "https://a.test/1.png");
+        assert_eq!(messages[4]["status"], "done");
+
+        // a finished job only sends its status
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{address}/ws/{id}"))
+            .await
+            .unwrap();
+        let message = socket.next().await.unwrap().unwrap();
+        assert_eq!(
+            message.into_text().unwrap(),
+            r#"{"type":"status","status":"done"}"#
+        );
+    }
+}
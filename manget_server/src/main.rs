@@ -1,30 +1,96 @@
+mod jobs;
 mod novel;
 
+use axum::body::Body;
 use axum::http::header::InvalidHeaderValue;
 use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::{debug_handler, Json, Router};
+use futures::StreamExt;
+use manget::download::DownloadEvent;
 use manget::manga;
 use manget::manga::ChapterError;
 use sanitize_filename::sanitize;
 use serde::{Deserialize, Serialize};
-use std::io::Read;
 use std::ops::Deref;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, Semaphore};
+use tokio_util::io::{ReaderStream, SyncIoBridge};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use uuid::Uuid;
 
+/// How long the result of a finished job is kept
+const JOB_TTL: Duration = Duration::from_secs(30 * 60);
+/// Most chapters of one batch download
+const MAX_BATCH_CHAPTERS: usize = 50;
+/// Batch downloads running at the same time, others wait for their turn
+static BATCH_PERMITS: Semaphore = Semaphore::const_new(2);
+/// Jobs downloading at the same time, others stay pending
+static JOB_PERMITS: Semaphore = Semaphore::const_new(4);
+/// Most jobs pending or running, new ones are rejected until some finish
+const MAX_UNFINISHED_JOBS: usize = 64;
+/// Bytes of the zip of a chapter buffered before the client reads them
+const ZIP_PIPE_CAPACITY: usize = 64 * 1024;
+/// Header listing the chapter urls skipped by a batch download, separated by spaces
+const FAILED_URLS_HEADER: &str = "x-failed-urls";
+
 #[derive(Debug, Deserialize)]
 struct DownloadRequest {
     url: String,
+    #[serde(default)]
+    format: OutputFormat,
+}
+
+/// Output formats of the download endpoint. There is no PDF builder, so `pdf` is rejected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat {
+    /// The pages with a `ComicInfo.xml` describing the chapter
+    #[default]
+    Cbz,
+    /// Only the pages
+    RawZip,
+}
+
+impl OutputFormat {
+    const ALL: &'static [OutputFormat] = &[OutputFormat::Cbz, OutputFormat::RawZip];
+
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Cbz => "cbz",
+            OutputFormat::RawZip => "zip",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Cbz => "application/vnd.comicbook+zip",
+            OutputFormat::RawZip => "application/zip",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchDownloadRequest {
+    urls: Vec<String>,
+    /// Skip the chapters which cannot be found or downloaded instead of failing the whole batch
+    #[serde(default)]
+    skip_failed: bool,
 }
 
 #[derive(Debug, Deserialize)]
 struct NovelDownloadRequest {
     title: String,
     content: String,
+    /// Url of the chapter page, used to resolve relative image urls
+    #[serde(default)]
+    url: Option<String>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -37,19 +103,58 @@ enum AppError {
     EpubError(String),
     #[error(transparent)]
     HeaderError(#[from] InvalidHeaderValue),
+    #[error("job '{0}' not found or not finished")]
+    JobNotFound(Uuid),
+    #[error("too many jobs, retry later")]
+    TooManyJobs,
+    #[error("{0}")]
+    BadRequest(String),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponseBody {
+    error: String,
+}
+
+impl AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Chapter(ChapterError::SiteNotSupported(_) | ChapterError::InvalidUrl(_)) => {
+                StatusCode::BAD_REQUEST
+            }
+            AppError::Chapter(ChapterError::ChapterNotFound(_)) => StatusCode::NOT_FOUND,
+            AppError::Chapter(e) if e.status() == Some(404) => StatusCode::NOT_FOUND,
+            AppError::JobNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::TooManyJobs => StatusCode::TOO_MANY_REQUESTS,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!("{:?}", self);
+        }
+        let body = ErrorResponseBody {
+            error: self.to_string(),
+        };
+        (status, Json(body)).into_response()
     }
 }
 
 #[debug_handler]
 async fn novel(
-    Json(NovelDownloadRequest { title, content }): Json<NovelDownloadRequest>,
+    Json(NovelDownloadRequest {
+        title,
+        content,
+        url,
+    }): Json<NovelDownloadRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let data = novel::convert_chapter_html_to_epub(&title, &content)
+    let base_url = url.and_then(|u| reqwest::Url::parse(&u).ok());
+    let data = novel::convert_chapter_html_to_epub(&title, &content, base_url.as_ref())
         .await
         .map_err(|e| AppError::EpubError(e.to_string()))?;
     let mut headers = HeaderMap::new();
@@ -62,23 +167,101 @@ async fn novel(
 }
 
 async fn download(json: Json<DownloadRequest>) -> Result<impl IntoResponse, AppError> {
-    let (file_name, file_path) = download_chapter_from_url(&json.url).await?;
-    let mut data = Vec::new();
+    let chapter = download_chapter_from_url(&json.url, json.format, None).await?;
 
-    // load file to local variable and delete file on disk
-    std::fs::File::open(&file_path)?.read_to_end(&mut data)?;
-    let _ = std::fs::remove_file(&file_path);
-    if let Some(p) = file_path.parent() {
-        let _ = std::fs::remove_dir(p);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&format!(
+            "attachment; filename={}",
+            sanitize(&chapter.file_name)
+        ))?,
+    );
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(json.format.content_type()),
+    );
+
+    Ok((headers, chapter.into_body()))
+}
+
+/// Download several chapters into one zip with a folder per chapter, in the order of the urls
+async fn download_batch(
+    Json(BatchDownloadRequest { urls, skip_failed }): Json<BatchDownloadRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if urls.is_empty() || urls.len() > MAX_BATCH_CHAPTERS {
+        return Err(AppError::BadRequest(format!(
+            "expected 1 to {MAX_BATCH_CHAPTERS} urls, got {}",
+            urls.len()
+        )));
     }
+    let _permit = BATCH_PERMITS
+        .acquire()
+        .await
+        .expect("the semaphore is never closed");
+
+    let mut chapters = Vec::new();
+    let mut chapter_urls = Vec::new();
+    let mut failed_urls = Vec::new();
+    for (url, chapter) in urls
+        .iter()
+        .zip(manga::get_chapters_with_precheck(urls.clone()).await)
+    {
+        match chapter {
+            Ok(chapter) => {
+                chapters.push(chapter);
+                chapter_urls.push(url.as_str());
+            }
+            Err(e) if skip_failed => {
+                tracing::warn!("Skipping {url}: {e}");
+                failed_urls.push(url.as_str());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if chapters.is_empty() {
+        return Err(AppError::BadRequest(String::from(
+            "no chapter can be found",
+        )));
+    }
+
+    let tempdir = tempfile::tempdir()?;
+    let zip_path = tempdir.path().join("batch.zip");
+    if skip_failed {
+        let (_, failed) =
+            manga::download_available_chapters_as_volume(&chapters, &zip_path).await?;
+        for (i, e) in failed {
+            tracing::warn!("Skipping {}: {e}", chapter_urls[i]);
+            failed_urls.push(chapter_urls[i]);
+        }
+    } else {
+        manga::download_chapters_as_volume(&chapters, &zip_path).await?;
+    }
+    // the zip is streamed from its file, removed with its folder once sent
+    let zip = Arc::new(TempFile {
+        path: zip_path,
+        _dir: tempdir,
+    });
 
     let mut headers = HeaderMap::new();
     headers.insert(
         header::CONTENT_DISPOSITION,
-        HeaderValue::from_str(&format!("attachment; filename={}", sanitize(file_name)))?,
+        HeaderValue::from_str(&format!(
+            "attachment; filename={}.zip",
+            sanitize(chapters[0].manga())
+        ))?,
     );
-
-    Ok((headers, data))
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/zip"),
+    );
+    if !failed_urls.is_empty() {
+        headers.insert(
+            FAILED_URLS_HEADER,
+            HeaderValue::from_str(&failed_urls.join(" "))?,
+        );
+    }
+    Ok((headers, zip.into_body().await?))
 }
 
 #[derive(Debug, Serialize)]
@@ -87,7 +270,7 @@ struct ChapterInfoResponseBody {
 }
 
 async fn chapter_info(json: Json<DownloadRequest>) -> Result<impl IntoResponse, AppError> {
-    let chapter = manga::get_chapter(&json.url).await?;
+    let chapter = manga::get_chapter_metadata(&json.url).await?;
     let chapter_full_name = chapter.full_name();
     let response_body = ChapterInfoResponseBody {
         chapter_name: chapter_full_name.trim().to_string(),
@@ -95,29 +278,527 @@ async fn chapter_info(json: Json<DownloadRequest>) -> Result<impl IntoResponse,
     Ok(Json(response_body))
 }
 
-async fn download_chapter_from_url(url: &str) -> Result<(String, PathBuf), ChapterError> {
+/// A chapter downloaded into a temporary folder, zipped when it is sent
+#[derive(Debug)]
+struct DownloadedChapter {
+    file_name: String,
+    folder: PathBuf,
+    /// Removes the folder with the chapter
+    _dir: TempDir,
+}
+
+impl DownloadedChapter {
+    /// Stream the zip of the chapter, the pages are compressed while the body is sent
+    fn into_body(self) -> Body {
+        let (reader, writer) = tokio::io::duplex(ZIP_PIPE_CAPACITY);
+        let writer = SyncIoBridge::new(writer);
+        let (done, result) = oneshot::channel();
+        tokio::task::spawn_blocking(move || {
+            // move the whole chapter, so its folder lives until it is zipped
+            let chapter = self;
+            let _ = done.send(manga::zip_folder_to_stream(&chapter.folder, writer));
+        });
+        // end the body with an error if zipping failed, so the client does not get a truncated
+        // file as if it were complete
+        let error = futures::stream::once(async move {
+            match result.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(Err(e)),
+                Err(_) => Some(Err(std::io::Error::other("zipping the chapter panicked"))),
+            }
+        })
+        .filter_map(std::future::ready);
+        Body::from_stream(ReaderStream::new(reader).chain(error))
+    }
+
+    /// Zip the chapter into a temporary file, the pages are removed once it is written
+    async fn into_file(self) -> std::io::Result<TempFile> {
+        tokio::task::spawn_blocking(move || {
+            let chapter = self;
+            let dir = tempfile::tempdir()?;
+            let path = dir.path().join("chapter.zip");
+            manga::zip_folder_to_stream(&chapter.folder, std::fs::File::create(&path)?)?;
+            Ok(TempFile { path, _dir: dir })
+        })
+        .await?
+    }
+}
+
+/// A file in a temporary folder, removed with the folder
+#[derive(Debug)]
+struct TempFile {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl TempFile {
+    /// Stream the file, which is kept until the body is dropped
+    async fn into_body(self: Arc<Self>) -> std::io::Result<Body> {
+        let file = tokio::fs::File::open(&self.path).await?;
+        Ok(Body::from_stream(ReaderStream::new(file).map(
+            move |chunk| {
+                let _file = &self;
+                chunk
+            },
+        )))
+    }
+}
+
+/// Download a chapter into a temporary folder, sending its progress to `events` if any
+async fn download_chapter_from_url(
+    url: &str,
+    format: OutputFormat,
+    events: Option<UnboundedSender<DownloadEvent>>,
+) -> Result<DownloadedChapter, ChapterError> {
     let chapter = manga::get_chapter(url).await?;
-    let random_file_name = Uuid::new_v4().to_string();
-    let zip_path = tempfile::tempdir()?.into_path().join(random_file_name);
-    let file_path = manga::download_chapter_as_cbz(chapter.deref(), Some(zip_path)).await?;
+    let mut options = manga::ChapterDownloadOptions::new();
+    if let Some(events) = events {
+        options.set_event_sender(events);
+    }
+    let dir = tempfile::tempdir()?;
+    let folder =
+        manga::download_chapter_with_options(chapter.deref(), Some(dir.path()), &options).await?;
+    if format == OutputFormat::Cbz {
+        manga::write_chapter_comic_info(chapter.deref(), &folder)?;
+    }
     let chapter_full_name = chapter.full_name();
-    Ok((format!("{chapter_full_name}.cbz"), file_path))
+    Ok(DownloadedChapter {
+        file_name: format!("{chapter_full_name}.{}", format.extension()),
+        folder,
+        _dir: dir,
+    })
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+async fn sites() -> Json<Vec<String>> {
+    Json(manga::supported_sites())
+}
+
+async fn formats() -> Json<&'static [OutputFormat]> {
+    Json(OutputFormat::ALL)
+}
 
-    let app = Router::new()
+fn app(jobs: jobs::Jobs) -> Router {
+    Router::new()
         .layer(TraceLayer::new_for_http())
         .layer(CorsLayer::permissive())
         .route("/", get(|| async { "Toan's server" }))
         .route("/get_chapter_info", get(chapter_info))
         .route("/download", post(download))
-        .route("/novel", post(novel));
+        .route("/download_batch", post(download_batch))
+        .route("/novel", post(novel))
+        .route("/sites", get(sites))
+        .route("/formats", get(formats))
+        .route("/jobs", post(jobs::create_job))
+        .route("/jobs/:id", get(jobs::job_status))
+        .route("/jobs/:id/download", get(jobs::job_download))
+        .route("/ws/:id", get(jobs::job_progress))
+        .with_state(jobs)
+}
+
+/// Resolve on SIGINT (Ctrl-C) or, on unix, SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for Ctrl-C");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
+    tracing::info!("shutting down, waiting for in-flight requests");
+}
+
+/// Serve `app` until `shutdown` resolves, then stop accepting connections
+/// and wait for the in-flight requests to finish.
+async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    shutdown: impl std::future::Future<Output = ()> + Send + 'static,
+) -> std::io::Result<()> {
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::DEBUG)
+        .init();
+
+    let jobs = jobs::Jobs::default();
+    jobs.spawn_cleanup(JOB_TTL);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    serve(listener, app(jobs), shutdown_signal()).await.unwrap();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Request;
+    use std::io::{Cursor, Read};
+    use tower::ServiceExt;
+
+    /// Write the pages `names` into `dir` and return their file urls
+    pub(crate) fn fixture_pages(dir: &std::path::Path, names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                std::fs::write(&path, name).unwrap();
+                reqwest::Url::from_file_path(path).unwrap().to_string()
+            })
+            .collect()
+    }
+
+    /// Resolve the urls of `domain` to chapters of the manga "Fixture" with `page_urls`, named
+    /// after the url path
+    pub(crate) fn register_fixture_site(domain: &'static str, page_urls: Vec<String>) {
+        register_fixture_chapters(domain, move |_| page_urls.clone());
+    }
+
+    /// Like `register_fixture_site`, with the page urls of each chapter given by `chapter_pages`
+    /// from the chapter name
+    fn register_fixture_chapters(
+        domain: &'static str,
+        chapter_pages: impl Fn(&str) -> Vec<String> + Send + Sync + 'static,
+    ) {
+        manga::register_site(
+            domain,
+            move |url| url.domain() == Some(domain),
+            move |url| {
+                let chapter = url.path().trim_start_matches('/').to_string();
+                let page_urls = chapter_pages(&chapter);
+                Box::pin(async move {
+                    Ok(Box::new(manga::ManualChapter::new(
+                        "Fixture",
+                        format!("chap {chapter}"),
+                        page_urls,
+                        None,
+                    )) as Box<dyn manga::Chapter>)
+                })
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_returns_zip() {
+        let pages = tempfile::tempdir().unwrap();
+        register_fixture_site(
+            "download-fixture.test",
+            fixture_pages(pages.path(), &["1.png", "2.png"]),
+        );
+
+        let response = post_download("https://download-fixture.test/1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=Fixture - chap 1.cbz"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["ComicInfo.xml", "page_001.png", "page_002.png"]);
+        let mut comic_info = String::new();
+        archive
+            .by_name("ComicInfo.xml")
+            .unwrap()
+            .read_to_string(&mut comic_info)
+            .unwrap();
+        let comic_info = roxmltree::Document::parse(&comic_info).unwrap();
+        let text = |tag: &str| {
+            comic_info
+                .descendants()
+                .find(|n| n.has_tag_name(tag))
+                .and_then(|n| n.text())
+                .map(str::to_string)
+        };
+        assert_eq!(text("Title").as_deref(), Some("chap 1"));
+        assert_eq!(text("Series").as_deref(), Some("Fixture"));
+        assert_eq!(text("PageCount").as_deref(), Some("2"));
+        let mut page = String::new();
+        archive
+            .by_name("page_002.png")
+            .unwrap()
+            .read_to_string(&mut page)
+            .unwrap();
+        assert_eq!(page, "2.png");
+    }
+
+    async fn post_download(url: &str) -> axum::response::Response {
+        let request = Request::post("/download")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(format!(r#"{{"url":"{url}"}}"#)))
+            .unwrap();
+        app(jobs::Jobs::default()).oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_bad_url_is_bad_request() {
+        let response = post_download("not a url").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).starts_with(r#"{"error":"invalid url"#));
+    }
+
+    #[tokio::test]
+    async fn test_novel_returns_epub() {
+        let request = Request::post("/novel")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"title":"Chapter 1","content":"<div class=\"br-section\"><p>Hello</p></div>"}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut mimetype = String::new();
+        archive
+            .by_name("mimetype")
+            .unwrap()
+            .read_to_string(&mut mimetype)
+            .unwrap();
+        assert_eq!(mimetype, "application/epub+zip");
+    }
+
+    #[tokio::test]
+    async fn test_sites() {
+        let response = app(jobs::Jobs::default())
+            .oneshot(Request::get("/sites").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let sites: Vec<String> = serde_json::from_slice(&body).unwrap();
+        assert!(sites.iter().any(|s| s == "mangadex.org"));
+        assert!(sites.iter().any(|s| s == "mangapark.net"));
+    }
+
+    #[tokio::test]
+    async fn test_download_raw_zip() {
+        let pages = tempfile::tempdir().unwrap();
+        register_fixture_site(
+            "raw-zip-fixture.test",
+            fixture_pages(pages.path(), &["1.png", "2.png"]),
+        );
+
+        let request = Request::post("/download")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"url":"https://raw-zip-fixture.test/1","format":"raw-zip"}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/zip"
+        );
+        assert_eq!(
+            response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=Fixture - chap 1.zip"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(names, ["page_001.png", "page_002.png"]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_format_is_rejected() {
+        let request = Request::post("/download")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"url":"https://mangadex.org/chapter/1","format":"pdf"}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert!(response.status().is_client_error());
+    }
+
+    #[tokio::test]
+    async fn test_formats() {
+        let response = app(jobs::Jobs::default())
+            .oneshot(Request::get("/formats").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], br#"["cbz","raw-zip"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_site_is_bad_request() {
+        let response = post_download("https://example.com/chapter/1").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_download_batch() {
+        let pages = tempfile::tempdir().unwrap();
+        register_fixture_site(
+            "batch-fixture.test",
+            fixture_pages(pages.path(), &["1.png", "2.png"]),
+        );
+
+        let request = Request::post("/download_batch")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"urls": ["https://batch-fixture.test/1", "https://batch-fixture.test/2", "https://example.com/3"], "skip_failed": true}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=Fixture.zip"
+        );
+        assert_eq!(
+            response.headers().get(FAILED_URLS_HEADER).unwrap(),
+            "https://example.com/3"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "00000_Fixture - chap 1/page_001.png",
+                "00000_Fixture - chap 1/page_002.png",
+                "00001_Fixture - chap 2/page_001.png",
+                "00001_Fixture - chap 2/page_002.png",
+                "ComicInfo.xml",
+            ]
+        );
+
+        // without skip_failed, the batch fails
+        let request = Request::post("/download_batch")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"urls": ["https://batch-fixture.test/1", "https://example.com/3"]}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_download_batch_skips_failed_pages() {
+        // serves the page "1.png", any other page is a 404
+        let pages = Router::new().route(
+            "/1.png",
+            get(|| async { ([(header::CONTENT_TYPE, "image/png")], "1") }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, pages).await.unwrap() });
+        register_fixture_chapters("broken-page-fixture.test", move |chapter| {
+            let pages: &[&str] = match chapter {
+                "2" => &["1.png", "missing.png"],
+                _ => &["1.png"],
+            };
+            pages
+                .iter()
+                .map(|page| format!("http://{addr}/{page}"))
+                .collect()
+        });
+
+        let request = Request::post("/download_batch")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"urls": ["https://broken-page-fixture.test/1", "https://broken-page-fixture.test/2", "https://broken-page-fixture.test/3"], "skip_failed": true}"#,
+            ))
+            .unwrap();
+        let response = app(jobs::Jobs::default()).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(FAILED_URLS_HEADER).unwrap(),
+            "https://broken-page-fixture.test/2"
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut names: Vec<_> = archive.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "00000_Fixture - chap 1/page_001.png",
+                "00001_Fixture - chap 3/page_001.png",
+                "ComicInfo.xml",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let app = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "finished"
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, async {
+            let _ = signal.await;
+        }));
+
+        let client = reqwest::Client::new();
+        let in_flight = tokio::spawn({
+            let client = client.clone();
+            async move { client.get(format!("http://{addr}/slow")).send().await }
+        });
+        // let the request reach the handler before shutting down
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // new connections are refused while the in-flight request finishes
+        assert!(reqwest::Client::new()
+            .get(format!("http://{addr}/slow"))
+            .send()
+            .await
+            .is_err());
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "finished");
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
 }
@@ -1,8 +1,13 @@
-use std::io::Cursor;
+use std::{collections::HashSet, io::Cursor, path::Path};
 
-use image::ImageReader;
+use futures::StreamExt;
+use image::{ImageFormat, ImageReader};
 use reqwest::Url;
 use scraper::{Html, Selector};
+use tracing::warn;
+
+/// Images of a chapter are fetched concurrently, up to this number at a time
+const MAX_CONCURRENT_IMAGE_DOWNLOADS: usize = 8;
 
 struct Image {
     url: String,
@@ -11,44 +16,80 @@ struct Image {
     data: Vec<u8>,
 }
 
+#[derive(Debug, thiserror::Error)]
+enum ImageError {
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Decode(#[from] image::ImageError),
+}
+
+/// Convert chapter html to epub. Relative image urls are resolved against `base_url` if provided.
 pub async fn convert_chapter_html_to_epub(
     title: &str,
     content: &str,
+    base_url: Option<&Url>,
 ) -> epub_builder::Result<Vec<u8>> {
-    let mut processed_content = process_chapter_content(content);
-    let images = extract_images(&processed_content).await;
+    build_novel_epub(
+        title,
+        vec![(title.to_string(), content.to_string())],
+        base_url,
+    )
+    .await
+}
 
-    for image in &images {
-        processed_content =
-            processed_content.replace(&image.url, &format!("Images/{}", image.name));
+/// Build one epub from several chapters given as (heading, html), ex: a whole volume.
+/// Each chapter is a `chapter_N.xhtml` listed in the table of contents. Images shared by
+/// chapters are embedded once.
+pub async fn build_novel_epub(
+    title: &str,
+    chapters: Vec<(String, String)>,
+    base_url: Option<&Url>,
+) -> epub_builder::Result<Vec<u8>> {
+    let mut contents: Vec<_> = chapters
+        .iter()
+        .map(|(_, content)| process_chapter_content(content))
+        .collect();
+    let images = extract_images(&contents.join("\n"), base_url).await;
+
+    for content in contents.iter_mut() {
+        for image in &images {
+            *content = content.replace(
+                &format!("\"{}\"", escape_xml(&image.url)),
+                &format!("\"Images/{}\"", image.name),
+            );
+        }
     }
 
-    let xhtml = format!(
-        r#"<?xml version="1.0" encoding="utf-8"?>
+    let mut output = Vec::new();
+    let mut builder = epub_builder::EpubBuilder::new(epub_builder::ZipLibrary::new()?)?;
+    builder
+        .metadata("title", title)?
+        .epub_version(epub_builder::EpubVersion::V30);
+    for (i, ((heading, _), content)) in chapters.iter().zip(contents).enumerate() {
+        let xhtml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
 <!DOCTYPE html>
 
 <html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
 <head>
-  <title>{title}</title>
+  <title>{heading}</title>
 </head>
 
 <body>
-{processed_content}
+{content}
 </body>
 </html>
 "#
-    );
-
-    let mut output = Vec::new();
-    let mut builder = epub_builder::EpubBuilder::new(epub_builder::ZipLibrary::new()?)?;
-    builder
-        .metadata("title", title)?
-        .epub_version(epub_builder::EpubVersion::V30)
-        .add_content(
-            epub_builder::EpubContent::new("chapter.xhtml", xhtml.as_bytes())
-                .title(title)
+        );
+        builder.add_content(
+            epub_builder::EpubContent::new(format!("chapter_{}.xhtml", i + 1), xhtml.as_bytes())
+                .title(heading)
                 .reftype(epub_builder::ReferenceType::Text),
         )?;
+    }
 
     for image in images {
         builder.add_resource(
@@ -68,23 +109,67 @@ fn process_chapter_content(content: &str) -> String {
     let texts: Vec<_> = html
         .select(&selector)
         .filter(|e| e.value().name() != "div")
-        .map(|e| e.html())
-        .map(|t| {
-            if t.starts_with("<img") {
-                t.replace(">", "/>")
-            } else {
-                t
-            }
+        .map(|e| {
+            let mut xhtml = String::new();
+            write_xhtml(&e, &mut xhtml);
+            xhtml
         })
+        .filter(|t| !t.is_empty())
         .collect();
-    texts
-        .join("\n")
-        .replace("<br>", "<br/>")
-        .replace("<hr>", "<hr/>")
+    texts.join("\n")
 }
 
-async fn extract_images(content: &str) -> Vec<Image> {
-    let urls = {
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Serialize a node as well-formed XHTML: void elements are self-closed, text and attributes are
+/// escaped, scripts, styles and comments are dropped.
+fn write_xhtml(node: &ego_tree::NodeRef<scraper::Node>, out: &mut String) {
+    match node.value() {
+        scraper::Node::Text(text) => out.push_str(&escape_xml(text)),
+        scraper::Node::Element(element) => {
+            let name = element.name();
+            if name == "script" || name == "style" {
+                return;
+            }
+            out.push('<');
+            out.push_str(name);
+            for (attr, value) in element.attrs() {
+                // skip attribute names that are not valid XML, ex: from broken markup
+                if attr.is_empty()
+                    || !attr
+                        .chars()
+                        .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+                {
+                    continue;
+                }
+                out.push_str(&format!(" {}=\"{}\"", attr, escape_xml(value)));
+            }
+            if VOID_ELEMENTS.contains(&name) {
+                out.push_str("/>");
+                return;
+            }
+            out.push('>');
+            for child in node.children() {
+                write_xhtml(&child, out);
+            }
+            out.push_str(&format!("</{name}>"));
+        }
+        _ => (),
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+async fn extract_images(content: &str, base_url: Option<&Url>) -> Vec<Image> {
+    let srcs = {
         let html = Html::parse_document(content);
         let selector = Selector::parse("img").unwrap();
         let img_elements = html.select(&selector);
@@ -94,41 +179,273 @@ async fn extract_images(content: &str) -> Vec<Image> {
             .map(|s| s.to_string())
             .collect::<Vec<_>>()
     };
-    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-    for url in urls {
-        let thread_tx = tx.clone();
-        tokio::spawn(async move {
-            let result = reqwest::get(&url).await;
-            thread_tx.send((url, result)).unwrap();
+    // the same image may be used several times
+    let mut seen = HashSet::new();
+    let srcs: Vec<_> = srcs
+        .into_iter()
+        .filter(|s| seen.insert(s.clone()))
+        .collect();
+    let urls: Vec<_> = srcs
+        .into_iter()
+        .filter_map(|src| {
+            match Url::parse(&src).or_else(|e| base_url.ok_or(e).and_then(|b| b.join(&src))) {
+                Ok(url) => Some((src, url)),
+                Err(e) => {
+                    warn!("Skip image '{}': {}", src, e);
+                    None
+                }
+            }
+        })
+        .collect();
+    let client = reqwest::Client::new();
+    let mut results: Vec<_> = futures::stream::iter(urls.into_iter().enumerate())
+        .map(|(i, (src, url))| {
+            let client = &client;
+            async move {
+                let result = fetch_image(client, url.clone()).await;
+                (i, src, url, result)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_IMAGE_DOWNLOADS)
+        .collect()
+        .await;
+    // keep the order of the images in the content
+    results.sort_by_key(|(i, ..)| *i);
+
+    let mut images = Vec::new();
+    for (_, src, url, result) in results {
+        let (data, mime_type, extension) = match result {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("Skip image '{}': {}", url, e);
+                continue;
+            }
+        };
+        let name = url
+            .path_segments()
+            .and_then(|mut s| s.next_back())
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                Path::new(s)
+                    .with_extension(extension)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .unwrap_or_else(|| format!("image_{}.{}", images.len(), extension));
+        // different urls may end with the same file name
+        let name = if images.iter().any(|image: &Image| image.name == name) {
+            format!("image_{}_{}", images.len(), name)
+        } else {
+            name
+        };
+        images.push(Image {
+            url: src,
+            mime_type: mime_type.to_string(),
+            data,
+            name,
         });
     }
-    drop(tx);
-    let mut images = Vec::new();
-    while let Some((url, result)) = rx.recv().await {
-        if let Ok(res) = result.and_then(|res| res.error_for_status()) {
-            let tmp_data = res.bytes().await.unwrap().to_vec();
-            let img = ImageReader::new(Cursor::new(tmp_data))
-                .with_guessed_format()
-                .unwrap()
-                .decode()
-                .unwrap();
-            let mut data = Vec::new();
-            img.write_to(&mut Cursor::new(&mut data), image::ImageFormat::Jpeg)
-                .unwrap();
-            let name = Url::parse(&url)
-                .unwrap()
-                .path_segments()
-                .unwrap()
-                .last()
-                .unwrap()
-                .to_string();
-            images.push(Image {
-                url,
-                mime_type: "image/jpeg".to_string(),
-                data,
-                name,
-            });
+    images
+}
+
+/// Fetch an image, keeping its format if epub readers support it (JPEG, PNG, GIF). Other formats
+/// are converted to PNG if they have transparency, to JPEG otherwise.
+/// Returns the data, its mime type and extension.
+async fn fetch_image(
+    client: &reqwest::Client,
+    url: Url,
+) -> Result<(Vec<u8>, &'static str, &'static str), ImageError> {
+    let tmp_data = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?
+        .to_vec();
+    let reader = ImageReader::new(Cursor::new(&tmp_data)).with_guessed_format()?;
+    let format = reader.format();
+    let img = reader.decode()?;
+    match format {
+        Some(ImageFormat::Jpeg) => return Ok((tmp_data, "image/jpeg", "jpg")),
+        Some(ImageFormat::Png) => return Ok((tmp_data, "image/png", "png")),
+        Some(ImageFormat::Gif) => return Ok((tmp_data, "image/gif", "gif")),
+        _ => (),
+    }
+    let mut data = Vec::new();
+    if img.color().has_alpha() {
+        img.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+        Ok((data, "image/png", "png"))
+    } else {
+        image::DynamicImage::ImageRgb8(img.into_rgb8())
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Jpeg)?;
+        Ok((data, "image/jpeg", "jpg"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{routing::get, Router};
+
+    async fn serve_images() -> Url {
+        let mut png = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let app = Router::new()
+            .route(
+                "/good.png",
+                get({
+                    let png = png.clone();
+                    || async { png }
+                }),
+            )
+            .route(
+                "/chapter/images/relative.png",
+                get({
+                    let png = png.clone();
+                    || async { png }
+                }),
+            )
+            .route("/broken.png", get(|| async { "not an image" }))
+            .route(
+                "/many/:name",
+                get({
+                    let png = png.clone();
+                    || async { png }
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        Url::parse(&format!("http://{addr}/chapter/1")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_extract_images_skip_bad_images() {
+        let base_url = serve_images().await;
+        let content = format!(
+            r#"<div class="br-section"><p>Hello</p><img src="{0}"><img src="{1}"><img src="{2}"><img src="images/relative.png"></div>"#,
+            base_url.join("/good.png").unwrap(),
+            base_url.join("/broken.png").unwrap(),
+            base_url.join("/missing.png").unwrap(),
+        );
+        let processed_content = process_chapter_content(&content);
+
+        let mut names: Vec<_> = extract_images(&processed_content, Some(&base_url))
+            .await
+            .into_iter()
+            .map(|img| img.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, ["good.png", "relative.png"]);
+
+        let names: Vec<_> = extract_images(&processed_content, None)
+            .await
+            .into_iter()
+            .map(|img| img.name)
+            .collect();
+        assert_eq!(names, ["good.png"]);
+
+        let epub = convert_chapter_html_to_epub("Chapter 1", &content, Some(&base_url))
+            .await
+            .unwrap();
+        assert!(!epub.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_extract_images_keeps_order() {
+        let base_url = serve_images().await;
+        let expected: Vec<_> = (0..30).rev().map(|i| format!("{i}.png")).collect();
+        let content: String = expected
+            .iter()
+            .map(|name| format!(r#"<img src="/many/{name}">"#))
+            .collect();
+
+        let names: Vec<_> = extract_images(&content, Some(&base_url))
+            .await
+            .into_iter()
+            .map(|img| img.name)
+            .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[tokio::test]
+    async fn test_extract_images_keeps_png() {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 0]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let app = Router::new().route(
+            "/transparent",
+            get({
+                let png = png.clone();
+                || async { png }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let content = format!(r#"<img src="http://{addr}/transparent">"#);
+        let images = extract_images(&content, None).await;
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].name, "transparent.png");
+        assert_eq!(images[0].mime_type, "image/png");
+        assert_eq!(images[0].data, png);
+    }
+
+    #[tokio::test]
+    async fn test_build_novel_epub() {
+        use std::io::Read;
+
+        let base_url = serve_images().await;
+        let chapters: Vec<_> = (1..=3)
+            .map(|i| {
+                (
+                    format!("Chapter {i}"),
+                    format!(
+                        r#"<div class="br-section"><p>Part {i}</p><img src="/good.png"></div>"#
+                    ),
+                )
+            })
+            .collect();
+        let epub = build_novel_epub("Volume 1", chapters, Some(&base_url))
+            .await
+            .unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+        let mut opf = String::new();
+        archive
+            .by_name("OEBPS/content.opf")
+            .unwrap()
+            .read_to_string(&mut opf)
+            .unwrap();
+        let spine = &opf[opf.find("<spine").unwrap()..opf.find("</spine>").unwrap()];
+        assert_eq!(spine.matches("<itemref").count(), 3);
+        for i in 1..=3 {
+            assert!(archive.by_name(&format!("OEBPS/chapter_{i}.xhtml")).is_ok());
         }
+        let images: Vec<_> = archive
+            .file_names()
+            .filter(|name| name.starts_with("OEBPS/Images/"))
+            .collect();
+        assert_eq!(images, ["OEBPS/Images/good.png"]);
+    }
+
+    #[test]
+    fn test_process_chapter_content_is_xml() {
+        let content = r#"<div class="br-section">
+            <p>Tom & Jerry <b>run<p>unclosed
+            <img src="/a.png?x=1&y=2" alt="a">
+            <script>alert("<p>")</script><style>p {}</style>
+            <hr><br><p>1 < 2 <!-- comment --></p>
+        </div>"#;
+        let xhtml = process_chapter_content(content);
+        assert!(!xhtml.contains("script") && !xhtml.contains("style"));
+        assert!(xhtml.contains(r#"src="/a.png?x=1&amp;y=2""#));
+        assert!(xhtml.contains("<hr/>") && xhtml.contains("<br/>"));
+        roxmltree::Document::parse(&format!("<body>{xhtml}</body>")).unwrap();
     }
-    images
 }